@@ -0,0 +1,146 @@
+//! How much the compression layer shrinks palettized PNGs and JSON, and how
+//! long it takes. The sizes are printed before the timings, since criterion
+//! only measures time.
+
+#[allow(dead_code)]
+#[path = "../src/color.rs"]
+mod color;
+#[allow(dead_code)]
+#[path = "../src/distance.rs"]
+mod distance;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_TYPE},
+        Request, Response,
+    },
+};
+use color::Illuminant;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use distance::{Distance, Matcher};
+use image::{DynamicImage, RgbImage};
+use std::{convert::Infallible, io::Cursor};
+use tokio::runtime::Runtime;
+use tower::{service_fn, ServiceBuilder, ServiceExt as _};
+use tower_http::compression::{predicate::SizeAbove, CompressionLayer};
+
+/// The PICO-8 palette.
+static PALETTE: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x00],
+    [0x1D, 0x2B, 0x53],
+    [0x7E, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xAB, 0x52, 0x36],
+    [0x5F, 0x57, 0x4F],
+    [0xC2, 0xC3, 0xC7],
+    [0xFF, 0xF1, 0xE8],
+    [0xFF, 0x00, 0x4D],
+    [0xFF, 0xA3, 0x00],
+    [0xFF, 0xEC, 0x27],
+    [0x00, 0xE4, 0x36],
+    [0x29, 0xAD, 0xFF],
+    [0x83, 0x76, 0x9C],
+    [0xFF, 0x77, 0xA8],
+    [0xFF, 0xCC, 0xAA],
+];
+
+/// A 512x512 gradient palettized to PICO-8, as /palettize would send it.
+fn palettized_png() -> Vec<u8> {
+    let matcher = Matcher::new(PALETTE, Distance::Ciede2000, Illuminant::D65);
+    let image = RgbImage::from_fn(512, 512, |x, y| {
+        image::Rgb(matcher.nearest([(x / 2) as u8, (y / 2) as u8, 128]).0)
+    });
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    png.into_inner()
+}
+
+/// The kind of JSON /palettize/compare-metrics and friends send.
+fn metrics_json() -> Vec<u8> {
+    let rows = (0..500)
+        .map(|i| {
+            format!(
+                r##"{{"palette":"palette_{}","color":"#{:06X}","psnr":{:.3},"ssim":{:.4}}}"##,
+                i,
+                i * 997 % 0xFFFFFF,
+                20.0 + (i % 17) as f64 / 3.0,
+                0.5 + (i % 13) as f64 / 30.0,
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", rows.join(",")).into_bytes()
+}
+
+/// Send `body` through the compression layer the way the app sets it up for
+/// these content types, and return what goes over the wire.
+async fn compressed(
+    body: &'static [u8],
+    content_type: &'static str,
+    encoding: Option<&str>,
+) -> usize {
+    let service = ServiceBuilder::new()
+        .layer(CompressionLayer::new().compress_when(SizeAbove::default()))
+        .service(service_fn(move |_: Request<Body>| async move {
+            Ok::<_, Infallible>(
+                Response::builder()
+                    .header(CONTENT_TYPE, content_type)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        }));
+
+    let mut request = Request::builder();
+    if let Some(encoding) = encoding {
+        request = request.header(ACCEPT_ENCODING, encoding);
+    }
+    let response = service
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    to_bytes(Body::new(response.into_body()), usize::MAX)
+        .await
+        .unwrap()
+        .len()
+}
+
+fn compression(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let payloads: [(&str, &'static [u8], &str); 2] = [
+        ("png", palettized_png().leak(), "image/png"),
+        ("json", metrics_json().leak(), "application/json"),
+    ];
+
+    for (name, body, content_type) in payloads {
+        for encoding in ["gzip", "zstd"] {
+            let size = runtime.block_on(compressed(body, content_type, Some(encoding)));
+            println!(
+                "{} {}: {} bytes to {} ({:.1}%)",
+                name,
+                encoding,
+                body.len(),
+                size,
+                100.0 * size as f64 / body.len() as f64,
+            );
+        }
+    }
+
+    let mut group = c.benchmark_group("compression");
+    for (name, body, content_type) in payloads {
+        for encoding in [None, Some("gzip"), Some("zstd")] {
+            group.bench_with_input(
+                BenchmarkId::new(name, encoding.unwrap_or("identity")),
+                &encoding,
+                |b, &encoding| {
+                    b.iter(|| runtime.block_on(compressed(body, content_type, encoding)))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compression);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+//! How much remembering each color's nearest palette color saves on pixel
+//! art, which repeats a few colors over and over. The uncached side goes
+//! through `nearest_n`, which measures every palette color for every pixel
+//! the way `nearest` did before it had a cache.
+
+#[allow(dead_code)]
+#[path = "../src/color.rs"]
+mod color;
+#[allow(dead_code)]
+#[path = "../src/distance.rs"]
+mod distance;
+
+use color::Illuminant;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use distance::{Distance, Matcher};
+use std::hint::black_box;
+
+/// The PICO-8 palette.
+static PALETTE: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x00],
+    [0x1D, 0x2B, 0x53],
+    [0x7E, 0x25, 0x53],
+    [0x00, 0x87, 0x51],
+    [0xAB, 0x52, 0x36],
+    [0x5F, 0x57, 0x4F],
+    [0xC2, 0xC3, 0xC7],
+    [0xFF, 0xF1, 0xE8],
+    [0xFF, 0x00, 0x4D],
+    [0xFF, 0xA3, 0x00],
+    [0xFF, 0xEC, 0x27],
+    [0x00, 0xE4, 0x36],
+    [0x29, 0xAD, 0xFF],
+    [0x83, 0x76, 0x9C],
+    [0xFF, 0x77, 0xA8],
+    [0xFF, 0xCC, 0xAA],
+];
+
+/// A handful of colors near, but not in, the palette.
+static SPRITE_COLORS: &[[u8; 3]] = &[
+    [12, 10, 20],
+    [40, 50, 90],
+    [120, 50, 80],
+    [20, 140, 90],
+    [180, 90, 60],
+    [200, 200, 190],
+    [250, 170, 30],
+    [60, 170, 240],
+];
+
+/// 256x256 pixels of 8x8 blocks of the sprite colors.
+fn pixel_art() -> Vec<[u8; 3]> {
+    (0..256 * 256)
+        .map(|i| {
+            let (x, y) = (i % 256 / 8, i / 256 / 8);
+            SPRITE_COLORS[(x * 7 + y * 3) % SPRITE_COLORS.len()]
+        })
+        .collect()
+}
+
+fn nearest_color(c: &mut Criterion) {
+    let pixels = pixel_art();
+    let mut group = c.benchmark_group("pixel art nearest color");
+    for distance in [Distance::L1, Distance::Ciede2000] {
+        // a new matcher each time, like each request, so the cache starts
+        // empty
+        group.bench_with_input(
+            BenchmarkId::new("cached", distance.name()),
+            &pixels,
+            |b, pixels| {
+                b.iter(|| {
+                    let matcher = Matcher::new(PALETTE, distance, Illuminant::D65);
+                    for &pixel in pixels {
+                        black_box(matcher.nearest(black_box(pixel)));
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("uncached", distance.name()),
+            &pixels,
+            |b, pixels| {
+                b.iter(|| {
+                    let matcher = Matcher::new(PALETTE, distance, Illuminant::D65);
+                    for &pixel in pixels {
+                        black_box(matcher.nearest_n(black_box(pixel), 1));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, nearest_color);
+criterion_main!(benches);
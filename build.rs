@@ -0,0 +1,15 @@
+//! Embeds when the binary was built, so a running server can say how stale
+//! it is.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let built = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    println!("cargo:rustc-env=PALETTIZER_BUILD_TIMESTAMP={}", built);
+    // without this, cargo reruns the script whenever any file changes, but
+    // the timestamp is only worth updating when the code does
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+}
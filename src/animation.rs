@@ -0,0 +1,143 @@
+//! Animated GIF output.
+
+use crate::{bad_request, process::nearest_color};
+use image::{DynamicImage, GenericImageView};
+use std::{borrow::Cow, collections::HashMap};
+
+const DEFAULT_DELAY_MS: u32 = 100;
+
+/// Encode frames which have already been palettized with `colors` as an
+/// animated GIF. `colors` becomes the global color table with one more entry
+/// after it for transparent pixels, so there can be at most 255 of them. The
+/// frames must all be the same size, with sides that fit in a `u16`, or it's a
+/// bad request.
+///
+/// Frames without a delay of their own reuse the last one. A `loop_count` of
+/// 0 loops forever.
+pub fn encode_gif(
+    frames: &[DynamicImage],
+    delays_ms: &[u32],
+    colors: &[[u8; 3]],
+    loop_count: u16,
+) -> anyhow::Result<Vec<u8>> {
+    let Some(first) = frames.first() else {
+        return Err(bad_request("need at least one frame"));
+    };
+    let (Ok(width), Ok(height)) = (u16::try_from(first.width()), u16::try_from(first.height()))
+    else {
+        return Err(bad_request("frames are too big for a GIF"));
+    };
+    if frames
+        .iter()
+        .any(|frame| frame.dimensions() != first.dimensions())
+    {
+        return Err(bad_request("frames must all be the same size"));
+    }
+
+    let transparent = colors.len() as u8;
+    let mut global_palette = colors.iter().flatten().copied().collect::<Vec<_>>();
+    global_palette.extend_from_slice(&[0, 0, 0]);
+    let indices = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| (color, i as u8))
+        .collect::<HashMap<_, _>>();
+
+    let mut data = Vec::new();
+    let mut encoder = gif::Encoder::new(&mut data, width, height, &global_palette)?;
+    encoder.set_repeat(match loop_count {
+        0 => gif::Repeat::Infinite,
+        n => gif::Repeat::Finite(n),
+    })?;
+
+    for (i, frame) in frames.iter().enumerate() {
+        let buffer = frame
+            .pixels()
+            .map(|(_, _, color)| {
+                if color.0[3] < 128 {
+                    return transparent;
+                }
+
+                // pixels left alone by min_diff aren't necessarily in the palette
+                let rgb = [color.0[0], color.0[1], color.0[2]];
+                match indices.get(&rgb) {
+                    Some(&index) => index,
+                    None => indices[&nearest_color(colors, rgb).0],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let delay_ms = delays_ms
+            .get(i)
+            .or(delays_ms.last())
+            .copied()
+            .unwrap_or(DEFAULT_DELAY_MS);
+
+        encoder.write_frame(&gif::Frame {
+            width,
+            height,
+            buffer: Cow::Owned(buffer),
+            // in hundredths of a second
+            delay: (delay_ms / 10).min(u16::MAX as u32) as u16,
+            transparent: Some(transparent),
+            dispose: gif::DisposalMethod::Background,
+            ..gif::Frame::default()
+        })?;
+    }
+
+    encoder.into_inner()?;
+    Ok(data)
+}
+
+/// Whether the bytes are a GIF with more than one frame. Only the block
+/// structure is walked, nothing is decoded. Anything that isn't a well formed
+/// GIF isn't animated, and is left for the decoder to complain about.
+pub fn is_animated_gif(bytes: &[u8]) -> bool {
+    if !bytes.starts_with(b"GIF8") || bytes.len() < 13 {
+        return false;
+    }
+
+    // the size of a color table, if the flags byte says there is one
+    let color_table = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 0x07) + 1)
+        } else {
+            0
+        }
+    };
+    // data is split up into sub-blocks, each prefixed with its length and
+    // ended by an empty one
+    let skip_sub_blocks = |mut offset: usize| loop {
+        let size = *bytes.get(offset)? as usize;
+        offset += 1 + size;
+        if size == 0 {
+            return Some(offset);
+        }
+    };
+
+    let mut offset = 13 + color_table(bytes[10]);
+    let mut frames = 0;
+    while let Some(&block) = bytes.get(offset) {
+        let next = match block {
+            // an extension, like frame timing
+            0x21 => skip_sub_blocks(offset + 2),
+            // an image descriptor, then the image's own color table and the
+            // LZW code size
+            0x2c => {
+                frames += 1;
+                if frames > 1 {
+                    return true;
+                }
+                bytes
+                    .get(offset + 9)
+                    .and_then(|&flags| skip_sub_blocks(offset + 10 + color_table(flags) + 1))
+            }
+            _ => None,
+        };
+        match next {
+            Some(next) => offset = next,
+            None => break,
+        }
+    }
+    false
+}
@@ -0,0 +1,141 @@
+//! Palette indices drawn over a palettized image, for seeing which color each
+//! part of it got.
+
+use crate::color;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+/// Each tile gets the index of the color at its center.
+pub const ANNOTATION_TILE_SIZE: u32 = 8;
+
+const DIGIT_WIDTH: u32 = 3;
+const DIGIT_HEIGHT: u32 = 5;
+
+/// 3x5 digits, a row per byte, with the leftmost pixel in the highest of the
+/// three low bits.
+const DIGITS: [[u8; DIGIT_HEIGHT as usize]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Write the palette index of the color at the center of each tile over the
+/// tile, in black or white, whichever stands out more. Tiles on the right and
+/// bottom edges may be smaller, and are centered the same way. Numbers wider
+/// than a tile spill into the next, and are cut off at the image's edges.
+/// Centers that are transparent or not a palette color are left alone.
+pub fn annotate_indices(image: &mut DynamicImage, colors: &[[u8; 3]]) {
+    let (width, height) = image.dimensions();
+    for tile_y in (0..height).step_by(ANNOTATION_TILE_SIZE as usize) {
+        let center_y = tile_y + ANNOTATION_TILE_SIZE.min(height - tile_y) / 2;
+        for tile_x in (0..width).step_by(ANNOTATION_TILE_SIZE as usize) {
+            let center_x = tile_x + ANNOTATION_TILE_SIZE.min(width - tile_x) / 2;
+
+            let Rgba([r, g, b, a]) = image.get_pixel(center_x, center_y);
+            if a == 0 {
+                continue;
+            }
+            let Some(index) = colors.iter().position(|&color| color == [r, g, b]) else {
+                continue;
+            };
+
+            let ink = if color::wcag_contrast_ratio([r, g, b], [0, 0, 0])
+                >= color::wcag_contrast_ratio([r, g, b], [255, 255, 255])
+            {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            };
+            draw_number(image, index, center_x, center_y, ink);
+        }
+    }
+}
+
+/// Draw the number centered on `(center_x, center_y)`, a pixel between
+/// digits.
+fn draw_number(
+    image: &mut DynamicImage,
+    number: usize,
+    center_x: u32,
+    center_y: u32,
+    ink: Rgba<u8>,
+) {
+    let digits = number.to_string();
+    let text_width = digits.len() as u32 * (DIGIT_WIDTH + 1) - 1;
+    let left = center_x as i64 - text_width as i64 / 2;
+    let top = center_y as i64 - DIGIT_HEIGHT as i64 / 2;
+
+    for (i, digit) in digits.bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let digit_left = left + i as i64 * (DIGIT_WIDTH + 1) as i64;
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..DIGIT_WIDTH {
+                if bits & (1 << (DIGIT_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                let x = digit_left + column as i64;
+                let y = top + row as i64;
+                if (0..image.width() as i64).contains(&x) && (0..image.height() as i64).contains(&y)
+                {
+                    image.put_pixel(x as u32, y as u32, ink);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn tiles_get_the_index_of_their_center() {
+        let colors = [[0, 0, 0], [255, 255, 255], [255, 255, 0]];
+        let mut image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 8, Rgba([255, 255, 0, 255])));
+        annotate_indices(&mut image, &colors);
+
+        // the centers are (4, 4) and (12, 4), so each tile gets a 2 a pixel
+        // left and two up from its center, in black since it's on yellow
+        #[rustfmt::skip]
+        let two = [
+            "........",
+            "........",
+            "...###..",
+            ".....#..",
+            "...###..",
+            "...#....",
+            "...###..",
+            "........",
+        ];
+        for tile_x in [0, 8] {
+            for (y, row) in two.iter().enumerate() {
+                for (x, pixel) in row.bytes().enumerate() {
+                    let expected = if pixel == b'#' {
+                        Rgba([0, 0, 0, 255])
+                    } else {
+                        Rgba([255, 255, 0, 255])
+                    };
+                    let (x, y) = (tile_x + x as u32, y as u32);
+                    assert_eq!(image.get_pixel(x, y), expected, "at ({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_colors_are_left_alone() {
+        let colors = [[0, 0, 0], [255, 255, 255]];
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([9, 9, 9, 255])));
+        let mut annotated = image.clone();
+        annotate_indices(&mut annotated, &colors);
+        assert_eq!(annotated, image);
+    }
+}
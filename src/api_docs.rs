@@ -0,0 +1,760 @@
+//! The OpenAPI description of the HTTP API. The form structs here only
+//! describe the multipart fields each endpoint reads, nothing is ever built
+//! from them.
+
+#![allow(dead_code)]
+
+use crate::CONFIG;
+use utoipa::{openapi::Server, OpenApi, ToSchema};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "palettizer"),
+    paths(
+        crate::health,
+        crate::config_schema,
+        crate::palettize,
+        crate::palettize_timed,
+        crate::palettize_preview,
+        crate::palettize_thumbnail,
+        crate::palettize_bulk_preview,
+        crate::palettize_from_url,
+        crate::palettize_async,
+        crate::palettize_diff,
+        crate::palettize_compare_metrics,
+        crate::palettize_stitch,
+        crate::palettize_recolor,
+        crate::palettize_watermark,
+        crate::palettize_animation_loop,
+        crate::palettize_constraints_check,
+        crate::palettize_tileset_dedup,
+        crate::palettize_with_annotations,
+        crate::palettize_with_grid,
+        crate::palettize_video_frame_sequence,
+        crate::validate_palette,
+        crate::palette_validate_hardware,
+        crate::palette_harmony_check,
+        crate::palette_optimize,
+        crate::palette_from_image,
+        crate::palette_histogram,
+        crate::palette_compare,
+        crate::palette_check_contrast,
+        crate::palette_merge,
+        crate::palette_compress,
+        crate::palette_complement,
+        crate::palette_invert,
+        crate::palette_shift,
+        crate::palette_desaturate,
+        crate::palette_sort,
+        crate::palette_sort_by_image,
+        crate::palette_rearrange,
+        crate::palette_generate,
+        crate::palette_interpolate,
+        crate::palette_to_image,
+        crate::palette_to_aseprite,
+        crate::palette_preview_image,
+        crate::palette_convert,
+    )
+)]
+struct ApiDoc;
+
+/// Paths are relative to the configured root.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.servers = Some(vec![Server::new(&CONFIG.root)]);
+    doc
+}
+
+/// Fields for palettizing one image.
+#[derive(ToSchema)]
+pub struct PalettizeForm {
+    /// The image to palettize.
+    #[schema(format = Binary)]
+    image: String,
+    /// The palette as an image, a RIFF .pal file, or text: a hex list, GIMP
+    /// palette, or JSON array.
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    /// Make the palette instead, like `#RRGGBB-#RRGGBB:N`.
+    palette_from_gradient: Option<String>,
+    /// Use every combination of this many steps per channel as the palette
+    /// instead, from 1 to 8.
+    palette_cube: Option<u8>,
+    /// `srgb` or `lab`, how to interpolate the gradient.
+    gradient_space: Option<String>,
+    /// `d65` or `d50`, the white point of Lab gradients and of the `cie76`
+    /// and `ciede2000` distances.
+    lab_illuminant: Option<String>,
+    /// `palettize`, `remap`, `animated_gif`, `flood_fill_palette`, or
+    /// `nearest_n`, which returns each pixel's nearest palette colors in a
+    /// region as JSON.
+    mode: Option<String>,
+    /// For `flood_fill_palette`, like `[{"x": 0, "y": 0}]`. Only the regions
+    /// filled out from these are palettized, the rest keep their colors.
+    seed_pixels: Option<String>,
+    /// For `flood_fill_palette`, how far by L1 distance a pixel can be from
+    /// its seed's color and still be filled. Defaults to 0.
+    fill_tolerance: Option<u32>,
+    /// For `nearest_n`, how many colors to list per pixel. Defaults to 3.
+    n: Option<usize>,
+    /// For `nearest_n`, the top left of the region.
+    roi_x: Option<u32>,
+    roi_y: Option<u32>,
+    /// For `nearest_n`, the size of the region, at most 10 by 10.
+    roi_w: Option<u32>,
+    roi_h: Option<u32>,
+    /// For `remap`, an object mapping `#RRGGBB` colors to their replacements.
+    color_map_json: Option<String>,
+    /// For `animated_gif`, the first frame. Later frames are `frame_1`,
+    /// `frame_2`, ...
+    #[schema(format = Binary)]
+    frame_0: Option<String>,
+    /// For `animated_gif`, comma separated milliseconds to show each frame.
+    frame_delays: Option<String>,
+    /// For `animated_gif`, how many times to loop. 0 loops forever.
+    loop_count: Option<u16>,
+    /// `auto` to give each tile whichever of `palette_0`, `palette_1`, ...
+    /// fits it best.
+    multi_palette_selection: Option<String>,
+    #[schema(format = Binary)]
+    palette_0: Option<String>,
+    tile_width: Option<u32>,
+    tile_height: Option<u32>,
+    /// `1` to pad the image on the right and bottom to a multiple of
+    /// `tile_width` and `tile_height`, filled with `background_color` or
+    /// transparency.
+    force_tile_align: Option<u8>,
+    /// `nds` to palettize for the Nintendo DS, in 8x8 tiles with 16
+    /// subpalettes of 15 colors. The response is JSON with the subpalettes,
+    /// which tile got which, and the image.
+    hardware: Option<String>,
+    /// `l1`, `cie76`, `ciede2000`, or `minkowski`, how to pick the nearest
+    /// palette color.
+    distance: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `1` to clip the palette's Lab values into the sRGB gamut before
+    /// measuring `cie76` or `ciede2000`.
+    lab_clamp: Option<u8>,
+    /// For `minkowski`, the power the channel differences are raised to, at
+    /// least 1 and up to `inf`. Defaults to 2.
+    minkowski_p: Option<f32>,
+    /// `index_0` or `first_color` to make pixels of the palette's first color
+    /// transparent, or `explicit` for `transparent_color`'s.
+    transparency_mode: Option<String>,
+    /// For `explicit`, a `#RRGGBB` color from the palette.
+    transparent_color: Option<String>,
+    /// `rgb` (or `rgb_only`) to palettize color, `alpha_only` to snap alpha
+    /// to the palette colors' luminances and leave color alone, or `all` to
+    /// match color and alpha together, with each palette color's luminance
+    /// as its alpha. `all` can't be dithered.
+    channels: Option<String>,
+    /// `1` to list the palette colors the image ended up using, comma
+    /// separated, in the `X-Palettizer-Used-Colors` header.
+    optimize_palette: Option<u8>,
+    /// `1` to list colors in `X-Palettizer-Used-Colors` and the
+    /// `return_multipart` JSON most used first, rather than in palette order.
+    palette_frequency_sort: Option<u8>,
+    /// With `optimize_palette`, `1` to palettize again with only the used
+    /// colors.
+    strip_unused: Option<u8>,
+    /// Pixels already closer than this to a palette color are left alone.
+    /// It's measured by `distance`, so it's in that distance's units.
+    min_diff: Option<u64>,
+    /// `1` to leave pixels that are exactly a palette color alone, skipping
+    /// the distance.
+    exact_match_passthrough: Option<u8>,
+    /// `protanopia`, `deuteranopia`, or `tritanopia`.
+    simulate: Option<String>,
+    /// How many bits of each channel to keep, from 1 to 8.
+    quantize_bits: Option<u8>,
+    /// Kelvin to shift the white balance to before palettizing, from 1000 to
+    /// 12000. Lower is warmer.
+    color_temp: Option<u32>,
+    /// Degrees to rotate hues by, from 0 to 360.
+    hue_rotate: Option<f32>,
+    /// Multiplies saturation, from 0 to 5.
+    saturation: Option<f32>,
+    /// `none`, `floyd_steinberg`, or `stucki`, how to spread each pixel's
+    /// error onto its neighbors. Only plain palettizing dithers, and not with
+    /// `stream`.
+    dither: Option<String>,
+    /// `1` to dither odd rows right to left.
+    serpentine: Option<u8>,
+    /// `1` to dither alpha too, on its own, to the nearest of `alpha_levels`.
+    /// Needs `dither` and `channels=rgb`.
+    alpha_dither: Option<u8>,
+    /// Comma separated alpha values from 0 to 255, like `0,255` for
+    /// transparency that's either on or off.
+    alpha_levels: Option<String>,
+    /// `1` to ordered dither each channel between the palette's values for
+    /// it, rather than `dither`.
+    per_channel_dither: Option<u8>,
+    /// `1` to fill in single pixels unlike all their neighbors.
+    smooth_noise: Option<u8>,
+    /// Colors that always become a certain palette color, like
+    /// `[{"input": "#FFCCAA", "output": "#FF8866"}]`. Only exact matches are
+    /// pinned.
+    pin_colors: Option<String>,
+    /// `1` to keep `image` in this session, for `use_session_image` later.
+    keep_session_image: Option<u8>,
+    /// `1` to use the last image kept in this session instead of `image`.
+    use_session_image: Option<u8>,
+    /// `1` to forget the session's image.
+    clear_session: Option<u8>,
+    /// `0` to ignore the image's EXIF orientation.
+    exif_rotate: Option<u8>,
+    /// `#RRGGBB` to fill fully transparent pixels with.
+    background_color: Option<String>,
+    /// `1` to leave fully transparent pixels unfilled by `background_color`,
+    /// so they're palettized by their own RGB and keep their alpha.
+    /// `background_color` still pads the image.
+    skip_alpha_composite: Option<u8>,
+    /// `1` to center the image in a square, filled with `background_color` or
+    /// transparency.
+    force_square: Option<u8>,
+    /// `#RRGGBB`, one of the palette's colors, to draw drop shadows with.
+    shadow_color: Option<String>,
+    /// How far right the shadow falls, default 1.
+    shadow_offset_x: Option<i64>,
+    /// How far down the shadow falls, default 1.
+    shadow_offset_y: Option<i64>,
+    /// `#RRGGBB`, one of the palette's colors, to outline non-transparent areas with.
+    outline_color: Option<String>,
+    /// `1` to send the PNG as it's made. Only works for plain palettizing.
+    stream: Option<u8>,
+    /// `png` or `webp`.
+    output_format: Option<String>,
+    /// `svg` to draw the pixels as rects, or `sprite_atlas_json` for the
+    /// position, most common color, and color count of each sprite.
+    return_format: Option<String>,
+    svg_pixel_size: Option<u32>,
+    /// `1` to group the rects by color.
+    svg_group_by_color: Option<u8>,
+    /// The size of each sprite for `sprite_atlas_json`.
+    sprite_w: Option<u32>,
+    sprite_h: Option<u32>,
+    /// The name of the downloaded file, without an extension.
+    output_filename: Option<String>,
+    /// `1` to send a `multipart/mixed` response with the image, then JSON
+    /// with its `color_count`, `colors`, `dimensions`, and
+    /// `processing_time_ms`.
+    return_multipart: Option<u8>,
+}
+
+/// Fields for palettizing an image in the background. The image and palette
+/// fields of [`PalettizeForm`] work here too, but the result is always a PNG.
+#[derive(ToSchema)]
+pub struct AsyncForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    /// An https url on the callback allowlist. The image is posted to it as
+    /// the `image` field of a multipart form, with the job id in the
+    /// `X-Palettizer-Job-Id` header.
+    callback_url: String,
+}
+
+/// Fields for previewing one image with several palettes.
+#[derive(ToSchema)]
+pub struct BulkPreviewForm {
+    #[schema(format = Binary)]
+    image: String,
+    /// The first palette. The rest are `palette_1`, `palette_2`, ...
+    #[schema(format = Binary)]
+    palette_0: String,
+    distance: Option<String>,
+    lab_illuminant: Option<String>,
+    lab_clamp: Option<u8>,
+    minkowski_p: Option<f32>,
+    min_diff: Option<u64>,
+    simulate: Option<String>,
+    quantize_bits: Option<u8>,
+    color_temp: Option<u32>,
+    hue_rotate: Option<f32>,
+    saturation: Option<f32>,
+    dither: Option<String>,
+    serpentine: Option<u8>,
+    alpha_dither: Option<u8>,
+    alpha_levels: Option<String>,
+    per_channel_dither: Option<u8>,
+    smooth_noise: Option<u8>,
+    pin_colors: Option<String>,
+    exif_rotate: Option<u8>,
+    background_color: Option<String>,
+    skip_alpha_composite: Option<u8>,
+}
+
+/// Fields for comparing two palettes on one image.
+#[derive(ToSchema)]
+pub struct DiffForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette_a: String,
+    #[schema(format = Binary)]
+    palette_b: String,
+    distance: Option<String>,
+    lab_illuminant: Option<String>,
+    lab_clamp: Option<u8>,
+    minkowski_p: Option<f32>,
+    min_diff: Option<u64>,
+    simulate: Option<String>,
+    quantize_bits: Option<u8>,
+    color_temp: Option<u32>,
+    hue_rotate: Option<f32>,
+    saturation: Option<f32>,
+    dither: Option<String>,
+    serpentine: Option<u8>,
+    alpha_dither: Option<u8>,
+    alpha_levels: Option<String>,
+    per_channel_dither: Option<u8>,
+    smooth_noise: Option<u8>,
+    pin_colors: Option<String>,
+    exif_rotate: Option<u8>,
+    background_color: Option<String>,
+    skip_alpha_composite: Option<u8>,
+}
+
+/// Fields for swapping ranges of colors while palettizing.
+#[derive(ToSchema)]
+pub struct RecolorForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    /// Like `[{"from": "#RRGGBB", "tolerance": 30, "to": "#AABBCC"}]`. Pixels
+    /// within `tolerance` of `from`, by L1 distance, become the palette color
+    /// nearest `to`. The first rule a pixel matches wins.
+    recolor_rules: String,
+    distance: Option<String>,
+    lab_illuminant: Option<String>,
+    exif_rotate: Option<u8>,
+    background_color: Option<String>,
+    skip_alpha_composite: Option<u8>,
+    output_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for watermarking an image before palettizing it. The rest of
+/// [`PalettizeForm`]'s fields work here too.
+#[derive(ToSchema)]
+pub struct WatermarkForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    /// Drawn over the image, transparency and all.
+    #[schema(format = Binary)]
+    watermark: String,
+    /// Where the watermark's left edge goes, default 0.
+    watermark_x: Option<i64>,
+    /// Where the watermark's top edge goes, default 0.
+    watermark_y: Option<i64>,
+    /// How opaque to draw the watermark, from 0 to 1. Default 1.
+    watermark_alpha: Option<f32>,
+}
+
+/// Fields for making a two frame palette swap animation. The options of
+/// [`PalettizeForm`] work here too.
+#[derive(ToSchema)]
+pub struct AnimationLoopForm {
+    #[schema(format = Binary)]
+    image: String,
+    /// The first frame's palette.
+    #[schema(format = Binary)]
+    palette_a: String,
+    /// Needs as many colors as `palette_a`.
+    #[schema(format = Binary)]
+    palette_b: String,
+    /// Comma separated indices of the colors that change to `palette_b`'s in
+    /// the second frame. Defaults to all of them. Colors of palette images
+    /// count left to right, then top to bottom.
+    swap_entries: Option<String>,
+    /// Comma separated milliseconds to show each frame.
+    frame_delays: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for palettizing a sprite sheet and finding its unique tiles. The
+/// options of [`PalettizeForm`] work here too.
+#[derive(ToSchema)]
+pub struct TilesetDedupForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    tile_width: u32,
+    tile_height: u32,
+}
+
+/// Fields for palettizing an image and labeling each 8x8 tile with the
+/// palette index of its center.
+#[derive(ToSchema)]
+pub struct WithAnnotationsForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for palettizing an image and drawing a grid over it.
+#[derive(ToSchema)]
+pub struct WithGridForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    /// How far apart the grid's lines are.
+    grid_width: u32,
+    grid_height: u32,
+    /// `#RRGGBB`, one of the palette's colors, `#FF0000` by default.
+    grid_color: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for palettizing the frames of a video. The options of
+/// [`PalettizeForm`] work here too, and apply to every frame alike.
+#[derive(ToSchema)]
+pub struct VideoFrameSequenceForm {
+    /// The first frame. Later frames are `frame_1`, `frame_2`, ..., all the
+    /// same size.
+    #[schema(format = Binary)]
+    frame_0: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for putting tiles back together.
+#[derive(ToSchema)]
+pub struct StitchForm {
+    /// The top left tile. The rest are `tile_{row}_{column}`.
+    #[schema(format = Binary)]
+    tile_0_0: String,
+    tile_width: u32,
+    tile_height: u32,
+}
+
+/// Fields for looking at a palette.
+#[derive(ToSchema)]
+pub struct PaletteForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+}
+
+/// Fields for checking a palette against a console's limits.
+#[derive(ToSchema)]
+pub struct ValidateHardwareForm {
+    /// `nes`, `gameboy`, `snes`, `gba`, or `nds`.
+    hardware: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+}
+
+/// Fields for fitting a palette to an image, or measuring how well it fits.
+#[derive(ToSchema)]
+pub struct OptimizeForm {
+    #[schema(format = Binary)]
+    image: String,
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+}
+
+/// Fields for shrinking a palette by merging its closest colors.
+#[derive(ToSchema)]
+pub struct CompressForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// How many colors to merge down to.
+    target_size: usize,
+    /// Weight merged colors by how much of this image palettizes to each.
+    /// Without it, merged colors are the midpoint.
+    #[schema(format = Binary)]
+    image: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for picking a palette out of an image.
+#[derive(ToSchema)]
+pub struct FromImageForm {
+    #[schema(format = Binary)]
+    image: String,
+    /// How many colors to pick, from 1 to 256.
+    n: u16,
+    /// `median_cut` or `kmeans`.
+    algorithm: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for counting an image's colors.
+#[derive(ToSchema)]
+pub struct HistogramForm {
+    #[schema(format = Binary)]
+    image: String,
+    /// Also count how many pixels palettize to each of this palette's colors.
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+}
+
+/// Fields for checking a palette's contrast.
+#[derive(ToSchema)]
+pub struct CheckContrastForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// The lowest WCAG contrast ratio a pair can have without being listed,
+    /// 4.5 by default.
+    min_ratio: Option<f64>,
+}
+
+/// Fields for comparing two palettes.
+#[derive(ToSchema)]
+pub struct ComparePalettesForm {
+    /// The old palette.
+    #[schema(format = Binary)]
+    palette_a: String,
+    /// The new palette.
+    #[schema(format = Binary)]
+    palette_b: String,
+}
+
+/// Fields for merging palettes.
+#[derive(ToSchema)]
+pub struct MergeForm {
+    /// The first palette. The rest are `palette_1`, `palette_2`, ...
+    #[schema(format = Binary)]
+    palette_0: String,
+    /// Median cut the merged palette down to this many colors if it has more.
+    max_colors: Option<usize>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for making a new palette out of another.
+#[derive(ToSchema)]
+pub struct ComplementForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for inverting a palette.
+#[derive(ToSchema)]
+pub struct InvertForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`,
+    /// the same as for /palette/convert.
+    output_format: Option<String>,
+    /// Used if there's no `output_format`, like the other palette endpoints.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for turning a palette to a single hue.
+#[derive(ToSchema)]
+pub struct DesaturateForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// The hue in degrees, 0 to 360.
+    target_hue: f32,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for shifting a palette's colors.
+#[derive(ToSchema)]
+pub struct ShiftForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// -255 to 255, added to each red channel.
+    shift_r: Option<i16>,
+    shift_g: Option<i16>,
+    shift_b: Option<i16>,
+    /// `1` to wrap around past 0 and 255 rather than clamp.
+    wrap: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for sorting a palette.
+#[derive(ToSchema)]
+pub struct SortForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `hue`, `luminance`, `saturation`, `r`, `g`, `b`, or `frequency`.
+    sort_by: Option<String>,
+    /// `1` for the same as `sort_by=frequency`.
+    palette_frequency_sort: Option<u8>,
+    /// For `frequency`, the image to count colors in.
+    #[schema(format = Binary)]
+    image: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list` to
+    /// get the colors back as a palette file rather than a strip, or `svg`.
+    return_format: Option<String>,
+    /// How many colors to put in each row of the strip.
+    strip_cols: Option<usize>,
+    output_filename: Option<String>,
+}
+
+/// Fields for sorting a palette by how much an image uses each color.
+#[derive(ToSchema)]
+pub struct SortByImageForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    #[schema(format = Binary)]
+    image: String,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for reordering a palette.
+#[derive(ToSchema)]
+pub struct RearrangeForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `gray_code` to have neighboring colors differ by as few bits as
+    /// possible, or `tsp` for the shortest path through RGB. Both start from
+    /// the first color.
+    strategy: String,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for making up a palette.
+#[derive(ToSchema)]
+pub struct GenerateForm {
+    /// How many colors to make, from 2 to 256.
+    n: u16,
+    /// `oklab_uniform`.
+    method: Option<String>,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    return_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for fading one palette into another.
+#[derive(ToSchema)]
+pub struct InterpolateForm {
+    #[schema(format = Binary)]
+    palette_a: String,
+    /// Needs as many colors as `palette_a`. Colors are paired up in the order
+    /// they come in, left to right and then top to bottom for images.
+    #[schema(format = Binary)]
+    palette_b: String,
+    /// How many palettes to make, counting `palette_a` and `palette_b`, from 2
+    /// to 256.
+    n: u16,
+    /// `d65` or `d50`, the white point of Lab.
+    lab_illuminant: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for drawing a palette.
+#[derive(ToSchema)]
+pub struct ToImageForm {
+    /// A hex list, GIMP palette, or JSON array, or anything else a palette
+    /// can be.
+    palette: String,
+    /// How tall to draw each row of the strip, from 1 to 64.
+    strip_height: Option<u32>,
+    /// How many colors to put in each row, padding the last with black.
+    /// Defaults to all of them in one row.
+    strip_cols: Option<usize>,
+    /// `1` to put the colors `image` palettizes to most first.
+    palette_frequency_sort: Option<u8>,
+    #[schema(format = Binary)]
+    image: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for converting a palette to another format.
+#[derive(ToSchema)]
+pub struct ConvertForm {
+    /// A palette in any format, which is worked out from its contents.
+    #[schema(format = Binary)]
+    palette: String,
+    /// `json`, `css_vars`, `gpl`, `act`, `riff_pal`, `hex`, or `hex_list`.
+    output_format: Option<String>,
+    output_filename: Option<String>,
+}
+
+/// Fields for looking at a palette as a page of swatches.
+#[derive(ToSchema)]
+pub struct PreviewImageForm {
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    /// `0` to leave out the nearest CSS color name under each swatch.
+    show_names: Option<u8>,
+}
+
+/// Fields for making an Aseprite file out of a palette.
+#[derive(ToSchema)]
+pub struct ToAsepriteForm {
+    /// Anything a palette can be, with at most 256 colors.
+    #[schema(format = Binary)]
+    palette: Option<String>,
+    palette_from_gradient: Option<String>,
+    gradient_space: Option<String>,
+    lab_illuminant: Option<String>,
+    output_filename: Option<String>,
+}
@@ -0,0 +1,76 @@
+//! Writing palettes as Aseprite files, following
+//! <https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md>.
+//! The sprite is a blank indexed one, only there to carry the palette.
+
+/// Indexed sprites can't have more colors than this.
+pub const MAX_COLORS: usize = 256;
+
+/// The blank sprite is this big on each side.
+const SPRITE_SIZE: u16 = 16;
+
+const HEADER_SIZE: usize = 128;
+const FRAME_HEADER_SIZE: usize = 16;
+const FRAME_DURATION_MS: u16 = 100;
+const PALETTE_CHUNK: u16 = 0x2019;
+
+/// A one-frame Aseprite file with the colors as its palette. Needs between 1
+/// and [`MAX_COLORS`] colors.
+pub fn aseprite_palette(colors: &[[u8; 3]]) -> Vec<u8> {
+    assert!(!colors.is_empty() && colors.len() <= MAX_COLORS);
+
+    let mut chunk = Vec::new();
+    chunk.extend_from_slice(&(colors.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(&0u32.to_le_bytes());
+    chunk.extend_from_slice(&(colors.len() as u32 - 1).to_le_bytes());
+    chunk.extend_from_slice(&[0; 8]);
+    for &[r, g, b] in colors {
+        // no flags, so no name
+        chunk.extend_from_slice(&0u16.to_le_bytes());
+        chunk.extend_from_slice(&[r, g, b, 255]);
+    }
+
+    let chunk_size = 4 + 2 + chunk.len();
+    let frame_size = FRAME_HEADER_SIZE + chunk_size;
+    let file_size = HEADER_SIZE + frame_size;
+
+    let mut file = Vec::with_capacity(file_size);
+    file.extend_from_slice(&(file_size as u32).to_le_bytes());
+    file.extend_from_slice(&0xa5e0u16.to_le_bytes());
+    // frames
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&SPRITE_SIZE.to_le_bytes());
+    file.extend_from_slice(&SPRITE_SIZE.to_le_bytes());
+    // bits per pixel, 8 is indexed
+    file.extend_from_slice(&8u16.to_le_bytes());
+    // layer opacity is valid
+    file.extend_from_slice(&1u32.to_le_bytes());
+    // speed, deprecated but still written
+    file.extend_from_slice(&FRAME_DURATION_MS.to_le_bytes());
+    file.extend_from_slice(&[0; 8]);
+    // transparent index, then padding
+    file.extend_from_slice(&[0; 4]);
+    // 0 means 256
+    file.extend_from_slice(&(colors.len() as u16 % 256).to_le_bytes());
+    // square pixels
+    file.extend_from_slice(&[1, 1]);
+    // grid position and size
+    file.extend_from_slice(&0i16.to_le_bytes());
+    file.extend_from_slice(&0i16.to_le_bytes());
+    file.extend_from_slice(&SPRITE_SIZE.to_le_bytes());
+    file.extend_from_slice(&SPRITE_SIZE.to_le_bytes());
+    file.resize(HEADER_SIZE, 0);
+
+    file.extend_from_slice(&(frame_size as u32).to_le_bytes());
+    file.extend_from_slice(&0xf1fau16.to_le_bytes());
+    // old chunk count, then duration and padding
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&FRAME_DURATION_MS.to_le_bytes());
+    file.extend_from_slice(&[0; 2]);
+    file.extend_from_slice(&1u32.to_le_bytes());
+
+    file.extend_from_slice(&(chunk_size as u32).to_le_bytes());
+    file.extend_from_slice(&PALETTE_CHUNK.to_le_bytes());
+    file.extend_from_slice(&chunk);
+
+    file
+}
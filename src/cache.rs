@@ -0,0 +1,115 @@
+//! Remembering responses to forms that have been seen before.
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
+use bytes::Bytes;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+pub struct ResponseCache {
+    /// None if the cache is turned off.
+    entries: Option<Mutex<LruCache<[u8; 32], CachedResponse>>>,
+    max_bytes_per_entry: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, max_bytes_per_entry: usize) -> ResponseCache {
+        ResponseCache {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            max_bytes_per_entry,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<Response> {
+        let entries = self.entries.as_ref()?;
+        let cached = entries
+            .lock()
+            .expect("cache lock poisoned")
+            .get(key)
+            .map(|cached| (cached.headers.clone(), cached.body.clone()));
+        let Some((headers, body)) = cached else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        let mut response = Response::new(Body::from(body));
+        *response.headers_mut() = headers;
+        response
+            .headers_mut()
+            .insert("x-cache", HeaderValue::from_static("HIT"));
+        Some(response)
+    }
+
+    /// Remember the response if it's small enough, and give it back.
+    pub async fn put(&self, key: [u8; 32], response: Response) -> anyhow::Result<Response> {
+        let Some(entries) = &self.entries else {
+            return Ok(response);
+        };
+
+        let (mut parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await?;
+        if body.len() <= self.max_bytes_per_entry {
+            entries.lock().expect("cache lock poisoned").put(
+                key,
+                CachedResponse {
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                },
+            );
+        }
+
+        parts
+            .headers
+            .insert("x-cache", HeaderValue::from_static("MISS"));
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+/// A hash of everything in the form, and the endpoint it was sent to. Fields
+/// are hashed in order of their names, each with its length so that moving
+/// bytes from one field to the next changes the hash.
+pub fn cache_key(endpoint: &str, fields: &HashMap<String, Bytes>) -> [u8; 32] {
+    let mut names = fields.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    for name in names {
+        let value = &fields[name];
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update((value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    }
+    hasher.finalize().into()
+}
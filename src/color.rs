@@ -0,0 +1,669 @@
+//! Conversions between sRGB and other color spaces.
+
+/// sRGB's white point, in CIE XYZ.
+const D65_WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+const D50_WHITE: [f32; 3] = [0.96422, 1.0, 0.82521];
+
+/// Bradford chromatic adaptation, so that sRGB's white ends up as D50 white
+/// rather than a slightly blue color.
+const D65_TO_D50: [[f32; 3]; 3] = [
+    [1.0478112, 0.0228866, -0.0501270],
+    [0.0295424, 0.9904844, -0.0170491],
+    [-0.0092345, 0.0150436, 0.7521316],
+];
+
+const D50_TO_D65: [[f32; 3]; 3] = [
+    [0.9555766, -0.0230393, 0.0631636],
+    [-0.0282895, 1.0099416, 0.0210077],
+    [0.0122982, -0.0204830, 1.3299098],
+];
+
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+// Ottosson's OKLab, from LMS cone responses to linear sRGB.
+const OKLAB_TO_LMS: [[f32; 3]; 3] = [
+    [1.0, 0.3963377774, 0.2158037573],
+    [1.0, -0.1055613458, -0.0638541728],
+    [1.0, -0.0894841775, -1.2914855480],
+];
+
+const LMS_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+    [4.0767416621, -3.3077115913, 0.2309699292],
+    [-1.2684380046, 2.6097574011, -0.3413193965],
+    [-0.0041960863, -0.7034186147, 1.7076147010],
+];
+
+/// How far outside of 0 to 1 a linear channel can be and still round to an
+/// sRGB value.
+const GAMUT_EPSILON: f32 = 0.0005;
+
+/// Where the Lab transfer function switches from a cube root to a line.
+const LAB_DELTA: f32 = 6.0 / 29.0;
+
+fn mul(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        matrix[0][0] * v[0] + matrix[0][1] * v[1] + matrix[0][2] * v[2],
+        matrix[1][0] * v[0] + matrix[1][1] * v[1] + matrix[1][2] * v[2],
+        matrix[2][0] * v[0] + matrix[2][1] * v[1] + matrix[2][2] * v[2],
+    ]
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+pub fn srgb_to_xyz([r, g, b]: [u8; 3]) -> [f32; 3] {
+    mul(
+        &SRGB_TO_XYZ,
+        [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)],
+    )
+}
+
+/// The color's relative luminance as an sRGB level, so grays come out as
+/// their own value.
+pub fn luminance_level(color: [u8; 3]) -> u8 {
+    linear_to_srgb(srgb_to_xyz(color)[1])
+}
+
+/// The WCAG relative luminance, from 0 for black to 1 for white.
+fn wcag_luminance(color: [u8; 3]) -> f64 {
+    let [r, g, b] = color.map(|c| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// The WCAG contrast ratio between two colors, from 1 for the same color to
+/// 21 for black and white.
+pub fn wcag_contrast_ratio(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let (a, b) = (wcag_luminance(a), wcag_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+pub fn xyz_to_srgb(xyz: [f32; 3]) -> [u8; 3] {
+    let [r, g, b] = mul(&XYZ_TO_SRGB, xyz);
+    [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)]
+}
+
+/// The CSS named colors, in alphabetical order. Grays are spelled both ways,
+/// and a few other colors have two names too.
+const CSS_NAMED_COLORS: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+/// The name of the CSS color nearest to `color`, by L1 distance.
+pub fn nearest_css_name(color: [u8; 3]) -> &'static str {
+    CSS_NAMED_COLORS
+        .iter()
+        .min_by_key(|(_, named)| {
+            (0..3)
+                .map(|c| color[c].abs_diff(named[c]) as u32)
+                .sum::<u32>()
+        })
+        .map(|(name, _)| *name)
+        .expect("there are named colors")
+}
+
+/// None if the color is outside of the sRGB gamut.
+pub fn oklab_to_srgb(l: f32, a: f32, b: f32) -> Option<[u8; 3]> {
+    let lms = mul(&OKLAB_TO_LMS, [l, a, b]).map(|c| c * c * c);
+    let linear = mul(&LMS_TO_LINEAR_SRGB, lms);
+    if linear
+        .iter()
+        .any(|&c| !(-GAMUT_EPSILON..=1.0 + GAMUT_EPSILON).contains(&c))
+    {
+        return None;
+    }
+    Some(linear.map(linear_to_srgb))
+}
+
+#[derive(Clone, Copy)]
+pub enum Illuminant {
+    /// What ICC profiles and print use.
+    D50,
+    /// What sRGB uses.
+    D65,
+}
+
+fn xyz_to_lab([x, y, z]: [f32; 3], [xn, yn, zn]: [f32; 3]) -> [f32; 3] {
+    fn f(t: f32) -> f32 {
+        if t > LAB_DELTA.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * LAB_DELTA.powi(2)) + 4.0 / 29.0
+        }
+    }
+
+    let (fx, fy, fz) = (f(x / xn), f(y / yn), f(z / zn));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_xyz([l, a, b]: [f32; 3], [xn, yn, zn]: [f32; 3]) -> [f32; 3] {
+    fn f_inv(t: f32) -> f32 {
+        if t > LAB_DELTA {
+            t.powi(3)
+        } else {
+            3.0 * LAB_DELTA.powi(2) * (t - 4.0 / 29.0)
+        }
+    }
+
+    let fy = (l + 16.0) / 116.0;
+    [
+        xn * f_inv(fy + a / 500.0),
+        yn * f_inv(fy),
+        zn * f_inv(fy - b / 200.0),
+    ]
+}
+
+/// `xyz` should be relative to D50, see [`D65_TO_D50`].
+pub fn xyz_to_lab_d50(xyz: [f32; 3]) -> [f32; 3] {
+    xyz_to_lab(xyz, D50_WHITE)
+}
+
+pub fn xyz_to_lab_d65(xyz: [f32; 3]) -> [f32; 3] {
+    xyz_to_lab(xyz, D65_WHITE)
+}
+
+pub fn srgb_to_lab(color: [u8; 3], illuminant: Illuminant) -> [f32; 3] {
+    let xyz = srgb_to_xyz(color);
+    match illuminant {
+        Illuminant::D50 => xyz_to_lab_d50(mul(&D65_TO_D50, xyz)),
+        Illuminant::D65 => xyz_to_lab_d65(xyz),
+    }
+}
+
+fn lab_to_linear_srgb(lab: [f32; 3], illuminant: Illuminant) -> [f32; 3] {
+    let xyz = match illuminant {
+        Illuminant::D50 => mul(&D50_TO_D65, lab_to_xyz(lab, D50_WHITE)),
+        Illuminant::D65 => lab_to_xyz(lab, D65_WHITE),
+    };
+    mul(&XYZ_TO_SRGB, xyz)
+}
+
+/// Out of gamut colors are clamped per channel.
+pub fn lab_to_srgb(lab: [f32; 3], illuminant: Illuminant) -> [u8; 3] {
+    lab_to_linear_srgb(lab, illuminant).map(linear_to_srgb)
+}
+
+/// Halving the search this many times gets well under a hundredth of a unit
+/// of Lab.
+const GAMUT_CLIP_STEPS: usize = 16;
+
+/// The in gamut Lab color nearest `lab` on the line between it and the gray
+/// of the same lightness, found by binary search. This is Lindbloom's way of
+/// clipping, which keeps the hue and lightness. Searching toward white itself
+/// would end up on white, since it's a corner of the gamut.
+pub fn clip_lab_to_srgb(lab: [f32; 3], illuminant: Illuminant) -> [f32; 3] {
+    let in_gamut = |lab: [f32; 3]| {
+        lab_to_linear_srgb(lab, illuminant)
+            .iter()
+            .all(|c| (-GAMUT_EPSILON..=1.0 + GAMUT_EPSILON).contains(c))
+    };
+    if in_gamut(lab) {
+        return lab;
+    }
+
+    let gray = [lab[0].clamp(0.0, 100.0), 0.0, 0.0];
+    let toward = |t: f32| [0, 1, 2].map(|c| gray[c] + (lab[c] - gray[c]) * t);
+    let (mut inside, mut outside) = (0.0, 1.0);
+    for _ in 0..GAMUT_CLIP_STEPS {
+        let middle = (inside + outside) / 2.0;
+        if in_gamut(toward(middle)) {
+            inside = middle;
+        } else {
+            outside = middle;
+        }
+    }
+    toward(inside)
+}
+
+pub fn cie76(a: [f32; 3], b: [f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Sharma, Wu & Dalal's formulation, with the usual weights of 1.
+pub fn ciede2000([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32 {
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let c_mean = (c1 + c2) / 2.0;
+    let c_mean7 = c_mean.powi(7);
+    let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f32.powi(7))).sqrt());
+
+    let a1 = a1 * (1.0 + g);
+    let a2 = a2 * (1.0 + g);
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let hue = |a: f32, b: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1 = hue(a1, b1);
+    let h2 = hue(a2, b2);
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh.to_radians() / 2.0).sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let c_mean7 = c_mean.powi(7);
+    let r_c = 2.0 * (c_mean7 / (c_mean7 + 25f32.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    ((dl / s_l).powi(2) + (dc / s_c).powi(2) + (dh / s_h).powi(2) + r_t * (dc / s_c) * (dh / s_h))
+        .sqrt()
+}
+
+/// Hue in degrees of a color from 0 to 1, given its largest channel and
+/// chroma.
+fn hue(r: f32, g: f32, b: f32, max: f32, chroma: f32) -> f32 {
+    if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    }
+}
+
+/// Hue in degrees, saturation and value from 0 to 1.
+pub fn srgb_to_hsv([r, g, b]: [u8; 3]) -> [f32; 3] {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    [hue(r, g, b, max, chroma), saturation, max]
+}
+
+/// Hue in degrees, saturation and lightness from 0 to 1.
+pub fn srgb_to_hsl([r, g, b]: [u8; 3]) -> [f32; 3] {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+
+    let lightness = (max + min) / 2.0;
+    let saturation = if lightness == 0.0 || lightness == 1.0 {
+        0.0
+    } else {
+        chroma / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+
+    [hue(r, g, b, max, chroma), saturation, lightness]
+}
+
+pub fn hsl_to_srgb([hue, saturation, lightness]: [f32; 3]) -> [u8; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+
+    let (r, g, b) = match hue as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = lightness - chroma / 2.0;
+    [r, g, b].map(|c| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// How much to multiply each channel by to shift the white balance to a
+/// color temperature in Kelvin, from Tanner Helland's fit of blackbody colors.
+/// The multipliers are scaled so gray stays about as bright, which means warm
+/// temperatures raise red rather than only taking away blue.
+pub fn color_temperature_multipliers(kelvin: u32) -> [f32; 3] {
+    let temp = kelvin as f32 / 100.0;
+
+    let red = if temp <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (temp - 60.0).powf(-0.13320476)
+    };
+    let green = if temp <= 66.0 {
+        99.4708 * temp.ln() - 161.11957
+    } else {
+        288.12216 * (temp - 60.0).powf(-0.07551485)
+    };
+    let blue = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (temp - 10.0).ln() - 305.0448
+    };
+
+    let [red, green, blue] = [red, green, blue].map(|c| c.clamp(0.0, 255.0));
+    let luma = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+    [red, green, blue].map(|c| c / luma)
+}
+
+#[derive(Clone, Copy)]
+pub enum ColorBlindness {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+// Machado, Oliveira & Fernandes 2009, at full severity. these work on linear
+// RGB.
+const PROTANOPIA: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+
+const DEUTERANOPIA: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+
+const TRITANOPIA: [[f32; 3]; 3] = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+/// How a color looks to someone with the given kind of color blindness.
+pub fn simulate_color_blindness([r, g, b]: [u8; 3], kind: ColorBlindness) -> [u8; 3] {
+    let matrix = match kind {
+        ColorBlindness::Protanopia => &PROTANOPIA,
+        ColorBlindness::Deuteranopia => &DEUTERANOPIA,
+        ColorBlindness::Tritanopia => &TRITANOPIA,
+    };
+    let [r, g, b] = mul(
+        matrix,
+        [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)],
+    );
+    [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deuteranopia_dulls_red() {
+        let chroma = |color| {
+            let [_, a, b] = srgb_to_lab(color, Illuminant::D65);
+            a.hypot(b)
+        };
+        let red = [255, 0, 0];
+        let seen = simulate_color_blindness(red, ColorBlindness::Deuteranopia);
+        assert!(chroma(seen) < chroma(red) * 0.75, "{:?}", seen);
+        // red and green look alike, so it's about as much of both
+        assert!(seen[0].abs_diff(seen[1]) < 40, "{:?}", seen);
+    }
+
+    #[test]
+    fn white_is_neutral_in_lab() {
+        for illuminant in [Illuminant::D50, Illuminant::D65] {
+            let [l, a, b] = srgb_to_lab([255, 255, 255], illuminant);
+            assert!((l - 100.0).abs() < 0.01, "{}", l);
+            assert!(a.abs() < 0.01, "{}", a);
+            assert!(b.abs() < 0.01, "{}", b);
+        }
+    }
+
+    #[test]
+    fn css_names() {
+        assert_eq!(nearest_css_name([0xFF, 0x00, 0x00]), "red");
+        assert_eq!(nearest_css_name([0xFF, 0x63, 0x47]), "tomato");
+        // not quite cornflowerblue, but nothing else is closer
+        assert_eq!(nearest_css_name([0x65, 0x95, 0xEE]), "cornflowerblue");
+    }
+
+    #[test]
+    fn low_color_temperatures_are_warmer() {
+        let [r, g, b] = color_temperature_multipliers(2000).map(|m| 128.0 * m);
+        assert!(r > 128.0, "{}", r);
+        assert!(b < 128.0, "{}", b);
+        assert!(g > b);
+    }
+
+    #[test]
+    fn black_and_white_contrast() {
+        let ratio = wcag_contrast_ratio([0xFF, 0xFF, 0xFF], [0x00, 0x00, 0x00]);
+        assert!((ratio - 21.0).abs() < 1e-9, "{}", ratio);
+        assert_eq!(
+            wcag_contrast_ratio([0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]),
+            ratio
+        );
+        assert_eq!(
+            wcag_contrast_ratio([0x77, 0x88, 0x99], [0x77, 0x88, 0x99]),
+            1.0
+        );
+    }
+
+    #[test]
+    fn clipping_leaves_srgb_colors_alone() {
+        let lab = srgb_to_lab([255, 0, 0], Illuminant::D65);
+        assert_eq!(clip_lab_to_srgb(lab, Illuminant::D65), lab);
+    }
+
+    #[test]
+    fn clipping_keeps_lightness_and_hue() {
+        let clipped = clip_lab_to_srgb([50.0, 120.0, 0.0], Illuminant::D50);
+        let linear = lab_to_linear_srgb(clipped, Illuminant::D50);
+        assert!(linear
+            .iter()
+            .all(|c| (-GAMUT_EPSILON..=1.0 + GAMUT_EPSILON).contains(c)));
+        assert_eq!(clipped[0], 50.0);
+        assert!(clipped[1] > 0.0 && clipped[1] < 120.0);
+        assert!(clipped[2].abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,221 @@
+//! A JSON Schema for the config file. The config struct comes out of
+//! `from_env::config!`, which has no way to derive one, so [`ConfigFile`]
+//! mirrors its fields for schemars to derive it from instead. Keep it in step
+//! with the fields in main.rs, the same as `CONFIG_FIELDS`. The tests check
+//! that the two agree.
+
+use crate::{
+    DEFAULT_CACHE_CAPACITY, DEFAULT_CACHE_MAX_BYTES_PER_ENTRY, DEFAULT_CONNECT_TIMEOUT_SECS,
+    DEFAULT_HEALTH_TIMEOUT_MS, DEFAULT_MAX_BULK_PALETTES, DEFAULT_MIN_PALETTE_COLORS,
+    DEFAULT_PALETTIZE_TIMEOUT_MS, DEFAULT_READ_TIMEOUT_SECS, DEFAULT_ROW_TIMEOUT_MS,
+    DEFAULT_SESSION_IMAGE_MAX_BYTES, DEFAULT_SESSION_STORE_MAX_BYTES, DEFAULT_THUMBNAIL_QUALITY,
+    DEFAULT_THUMBNAIL_SIZE,
+};
+use schemars::{schema_for, JsonSchema};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{net::SocketAddr, path::PathBuf};
+
+pub fn config_schema() -> Value {
+    schema_for!(ConfigFile).to_value()
+}
+
+// only schemars looks at the fields
+#[allow(dead_code)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "palettizer config")]
+struct ConfigFile {
+    /// the path every endpoint is under
+    root: String,
+    /// the address and port to listen on
+    bind: SocketAddr,
+    /// how many palettes /palettize/bulk-preview takes at once
+    #[schemars(default = "max_bulk_palettes")]
+    max_bulk_palettes: Option<usize>,
+    /// threads available for processing images, defaults to the number of
+    /// cores
+    #[schemars(range(min = 1))]
+    max_blocking_threads: Option<usize>,
+    /// print an access log line per request to stdout
+    access_log_format: Option<AccessLogFormat>,
+    /// hosts images may be downloaded from
+    url_allowlist: Option<Vec<String>>,
+    /// hosts /palettize/async may send results to
+    callback_allowlist: Option<Vec<String>>,
+    /// the longest side of /palettize/thumbnail images
+    #[schemars(default = "thumbnail_size", range(min = 1))]
+    thumbnail_size: Option<u32>,
+    /// JPEG quality of thumbnails
+    #[schemars(default = "thumbnail_quality", range(min = 1, max = 100))]
+    thumbnail_quality: Option<u8>,
+    /// milliseconds a single row of an image may take to palettize
+    #[schemars(default = "row_timeout_ms", range(min = 1))]
+    row_timeout_ms: Option<u64>,
+    /// how many responses to remember, 0 turns the cache off
+    #[schemars(default = "cache_capacity")]
+    cache_capacity: Option<usize>,
+    /// responses bigger than this many bytes aren't remembered
+    #[schemars(default = "cache_max_bytes_per_entry")]
+    cache_max_bytes_per_entry: Option<usize>,
+    /// uploaded palettes with fewer distinct colors than this are turned away
+    #[schemars(default = "min_palette_colors")]
+    min_palette_colors: Option<usize>,
+    /// images bigger than this aren't kept for use_session_image
+    #[schemars(default = "session_image_max_bytes")]
+    session_image_max_bytes: Option<usize>,
+    /// bytes all sessions together may take up before the ones unused the
+    /// longest are forgotten
+    #[schemars(default = "session_store_max_bytes")]
+    session_store_max_bytes: Option<usize>,
+    /// milliseconds /health may take before giving up with 504
+    #[schemars(default = "health_timeout_ms", range(min = 1))]
+    health_timeout_ms: Option<u64>,
+    /// milliseconds a request to /palettize may take before giving up with
+    /// 504
+    #[schemars(default = "palettize_timeout_ms", range(min = 1))]
+    palettize_timeout_ms: Option<u64>,
+    /// seconds to wait connecting to a host for downloads and callbacks
+    #[schemars(default = "connect_timeout_secs", range(min = 1))]
+    connect_timeout_secs: Option<u64>,
+    /// seconds to wait for more of a response from a host once connected
+    #[schemars(default = "read_timeout_secs", range(min = 1))]
+    read_timeout_secs: Option<u64>,
+    templates: Templates,
+    /// set both paths to speak TLS
+    tls: Option<Tls>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[schemars(inline)]
+enum AccessLogFormat {
+    Clf,
+    Json,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(inline)]
+struct Templates {
+    error: PathBuf,
+    index: PathBuf,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, JsonSchema)]
+#[schemars(inline)]
+struct Tls {
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+}
+
+fn max_bulk_palettes() -> Option<usize> {
+    Some(DEFAULT_MAX_BULK_PALETTES)
+}
+
+fn thumbnail_size() -> Option<u32> {
+    Some(DEFAULT_THUMBNAIL_SIZE)
+}
+
+fn thumbnail_quality() -> Option<u8> {
+    Some(DEFAULT_THUMBNAIL_QUALITY)
+}
+
+fn row_timeout_ms() -> Option<u64> {
+    Some(DEFAULT_ROW_TIMEOUT_MS)
+}
+
+fn cache_capacity() -> Option<usize> {
+    Some(DEFAULT_CACHE_CAPACITY)
+}
+
+fn cache_max_bytes_per_entry() -> Option<usize> {
+    Some(DEFAULT_CACHE_MAX_BYTES_PER_ENTRY)
+}
+
+fn min_palette_colors() -> Option<usize> {
+    Some(DEFAULT_MIN_PALETTE_COLORS)
+}
+
+fn session_image_max_bytes() -> Option<usize> {
+    Some(DEFAULT_SESSION_IMAGE_MAX_BYTES)
+}
+
+fn session_store_max_bytes() -> Option<usize> {
+    Some(DEFAULT_SESSION_STORE_MAX_BYTES)
+}
+
+fn health_timeout_ms() -> Option<u64> {
+    Some(DEFAULT_HEALTH_TIMEOUT_MS)
+}
+
+fn palettize_timeout_ms() -> Option<u64> {
+    Some(DEFAULT_PALETTIZE_TIMEOUT_MS)
+}
+
+fn connect_timeout_secs() -> Option<u64> {
+    Some(DEFAULT_CONNECT_TIMEOUT_SECS)
+}
+
+fn read_timeout_secs() -> Option<u64> {
+    Some(DEFAULT_READ_TIMEOUT_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CONFIG_FIELDS;
+
+    /// The schema's property names, with nested ones as `table.field` like
+    /// `CONFIG_FIELDS`.
+    fn field_names(schema: &Value, prefix: &str, names: &mut Vec<String>) {
+        let properties = schema["properties"]
+            .as_object()
+            .expect("objects have properties");
+        for (name, property) in properties {
+            let name = format!("{}{}", prefix, name);
+            if property.get("properties").is_some() {
+                field_names(property, &format!("{}.", name), names);
+            } else {
+                names.push(name);
+            }
+        }
+    }
+
+    #[test]
+    fn schema_has_every_config_field() {
+        let mut schema_fields = Vec::new();
+        field_names(&config_schema(), "", &mut schema_fields);
+        schema_fields.sort();
+        let mut config_fields = CONFIG_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .collect::<Vec<_>>();
+        config_fields.sort();
+        assert_eq!(schema_fields, config_fields);
+    }
+
+    #[test]
+    fn schema_has_defaults_and_required_fields() {
+        let schema = config_schema();
+        assert_eq!(
+            schema["properties"]["thumbnail_size"]["default"],
+            DEFAULT_THUMBNAIL_SIZE
+        );
+        let mut required = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field.as_str().unwrap())
+            .collect::<Vec<_>>();
+        required.sort();
+        assert_eq!(required, ["bind", "root", "templates"]);
+    }
+
+    #[test]
+    fn example_config_matches_the_mirror() {
+        let content = include_str!("../config.toml");
+        toml::from_str::<ConfigFile>(content).expect("config.toml fits the schema's struct");
+    }
+}
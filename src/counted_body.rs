@@ -0,0 +1,64 @@
+//! Counting the bytes of request and response bodies as they're read and
+//! sent. `Content-Length` isn't always there, and compressed or streamed
+//! responses don't know how big they are until they're done.
+
+use axum::body::{Body, HttpBody};
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// Passes a body through, adding up its data, and tells `done` how much there
+/// was when it's dropped. That's after the last of it, or as far as it got if
+/// it wasn't read to the end.
+pub struct CountedBody {
+    inner: Body,
+    bytes: u64,
+    done: Option<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl CountedBody {
+    pub fn wrap(body: Body, done: impl FnOnce(u64) + Send + 'static) -> Body {
+        Body::new(CountedBody {
+            inner: body,
+            bytes: 0,
+            done: Some(Box::new(done)),
+        })
+    }
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            done(self.bytes);
+        }
+    }
+}
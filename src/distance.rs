@@ -0,0 +1,258 @@
+//! Ways of measuring how far apart two colors are.
+
+use crate::color::{self, Illuminant};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+#[derive(Clone, Copy)]
+pub enum Distance {
+    /// The sum of the differences of each channel, in sRGB.
+    L1,
+    /// Straight line distance in Lab.
+    Cie76,
+    /// Lab distance corrected for how people see differences in lightness,
+    /// chroma, and hue.
+    Ciede2000,
+    /// The channel differences raised to a power `p` of at least 1, summed,
+    /// and rooted, in sRGB. 1 is the same as L1, 2 is straight line distance,
+    /// and infinity is the biggest channel difference.
+    Minkowski(f32),
+}
+
+impl Distance {
+    pub const ALL: [Distance; 3] = [Distance::L1, Distance::Cie76, Distance::Ciede2000];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Distance::L1 => "l1",
+            Distance::Cie76 => "cie76",
+            Distance::Ciede2000 => "ciede2000",
+            Distance::Minkowski(_) => "minkowski",
+        }
+    }
+
+    fn uses_lab(self) -> bool {
+        !matches!(self, Distance::L1 | Distance::Minkowski(_))
+    }
+}
+
+/// Photos have far more colors than this, and remembering all of them would
+/// take more memory than it saves time.
+const MAX_CACHED_COLORS: usize = 1 << 16;
+
+/// Finds the nearest palette color by some distance. The palette is converted
+/// to whatever space the distance needs once, up front, rather than for every
+/// pixel, and colors already looked up are remembered.
+pub struct Matcher<'colors> {
+    colors: &'colors [[u8; 3]],
+    distance: Distance,
+    /// The white point of Lab distances.
+    illuminant: Illuminant,
+    lab: Vec<[f32; 3]>,
+    /// Colors which are their own nearest, without measuring anything.
+    exact: HashSet<[u8; 3]>,
+    cache: RefCell<HashMap<[u8; 3], ([u8; 3], f32)>>,
+}
+
+impl<'colors> Matcher<'colors> {
+    pub fn new(
+        colors: &'colors [[u8; 3]],
+        distance: Distance,
+        illuminant: Illuminant,
+    ) -> Matcher<'colors> {
+        let lab = if distance.uses_lab() {
+            colors
+                .iter()
+                .map(|&color| color::srgb_to_lab(color, illuminant))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Matcher {
+            colors,
+            distance,
+            illuminant,
+            lab,
+            exact: HashSet::new(),
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Colors that are in the palette are always matched to themselves. Lab
+    /// distances might otherwise round their way to a different entry.
+    pub fn passing_exact_matches(mut self) -> Matcher<'colors> {
+        self.exact = self.colors.iter().copied().collect();
+        self
+    }
+
+    /// Pull the palette's Lab values into the sRGB gamut. Colors that came
+    /// from 8 bit sRGB are already in it, give or take rounding, so this
+    /// only keeps that rounding from reaching CIEDE2000.
+    pub fn clipping_lab(mut self) -> Matcher<'colors> {
+        for lab in self.lab.iter_mut() {
+            *lab = color::clip_lab_to_srgb(*lab, self.illuminant);
+        }
+        self
+    }
+
+    /// The closest palette color and its distance from `color`.
+    pub fn nearest(&self, color: [u8; 3]) -> ([u8; 3], f32) {
+        if self.exact.contains(&color) {
+            return (color, 0.0);
+        }
+        if let Some(&nearest) = self.cache.borrow().get(&color) {
+            return nearest;
+        }
+
+        let lab = self.lab_of(color);
+        let mut min_diff = f32::INFINITY;
+        let mut min_color = [0, 0, 0];
+        for (i, palette_color) in self.colors.iter().enumerate() {
+            let diff = self.distance_to(i, color, lab);
+            if diff < min_diff {
+                min_diff = diff;
+                min_color = *palette_color;
+            }
+        }
+        let nearest = (min_color, self.finish(min_diff));
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() < MAX_CACHED_COLORS {
+            cache.insert(color, nearest);
+        }
+        nearest
+    }
+
+    /// The `n` closest palette colors and their distances, closest first.
+    /// Unlike [`Matcher::nearest`], exact matches are measured like anything
+    /// else.
+    pub fn nearest_n(&self, color: [u8; 3], n: usize) -> Vec<([u8; 3], f32)> {
+        let lab = self.lab_of(color);
+        let mut diffs = self
+            .colors
+            .iter()
+            .enumerate()
+            .map(|(i, &palette_color)| (palette_color, self.distance_to(i, color, lab)))
+            .collect::<Vec<_>>();
+        diffs.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        diffs.truncate(n);
+        for (_, diff) in diffs.iter_mut() {
+            *diff = self.finish(*diff);
+        }
+        diffs
+    }
+
+    /// The closest palette color to `color` counting alpha too, with its
+    /// alpha and the distance. The `i`th palette color has the `i`th of
+    /// `alphas`, and alpha differences are added on as they are, so they
+    /// weigh the same as a channel of L1. Like [`Matcher::nearest_n`], exact
+    /// matches are measured like anything else.
+    pub fn nearest_rgba(&self, color: [u8; 3], alpha: u8, alphas: &[u8]) -> ([u8; 3], u8, f32) {
+        let lab = self.lab_of(color);
+        let mut min_diff = f32::INFINITY;
+        let mut min_color = ([0, 0, 0], 0);
+        for (i, (&palette_color, &palette_alpha)) in self.colors.iter().zip(alphas).enumerate() {
+            let diff =
+                self.finish(self.distance_to(i, color, lab)) + palette_alpha.abs_diff(alpha) as f32;
+            if diff < min_diff {
+                min_diff = diff;
+                min_color = (palette_color, palette_alpha);
+            }
+        }
+        (min_color.0, min_color.1, min_diff)
+    }
+
+    fn lab_of(&self, color: [u8; 3]) -> [f32; 3] {
+        if self.distance.uses_lab() {
+            color::srgb_to_lab(color, self.illuminant)
+        } else {
+            [0.0; 3]
+        }
+    }
+
+    /// How far `color`, which is `lab` in Lab, is from the `i`th palette color,
+    /// or something that ranks the same. See [`Matcher::finish`].
+    fn distance_to(&self, i: usize, color: [u8; 3], lab: [f32; 3]) -> f32 {
+        let palette_color = self.colors[i];
+        match self.distance {
+            Distance::L1 => {
+                (color[0].abs_diff(palette_color[0]) as u32
+                    + color[1].abs_diff(palette_color[1]) as u32
+                    + color[2].abs_diff(palette_color[2]) as u32) as f32
+            }
+            Distance::Cie76 => color::cie76(lab, self.lab[i]),
+            Distance::Ciede2000 => color::ciede2000(lab, self.lab[i]),
+            Distance::Minkowski(p) if p.is_infinite() => (0..3)
+                .map(|c| color[c].abs_diff(palette_color[c]))
+                .max()
+                .unwrap_or(0) as f32,
+            // the root is left for finish, since it doesn't change the order
+            Distance::Minkowski(p) => (0..3)
+                .map(|c| (color[c].abs_diff(palette_color[c]) as f32).powf(p))
+                .sum(),
+        }
+    }
+
+    /// Turn what [`Matcher::distance_to`] measured into the actual distance.
+    fn finish(&self, diff: f32) -> f32 {
+        match self.distance {
+            Distance::Minkowski(p) if p.is_finite() => diff.powf(1.0 / p),
+            _ => diff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PALETTE: &[[u8; 3]] = &[
+        [0, 0, 0],
+        [255, 255, 255],
+        [200, 30, 40],
+        [20, 180, 60],
+        [40, 60, 220],
+        [128, 128, 128],
+        [250, 200, 20],
+    ];
+
+    fn ranking(distance: Distance, color: [u8; 3]) -> Vec<([u8; 3], f32)> {
+        Matcher::new(PALETTE, distance, Illuminant::D65).nearest_n(color, PALETTE.len())
+    }
+
+    #[test]
+    fn minkowski_1_ranks_like_l1() {
+        for color in [[10, 20, 30], [130, 120, 140], [220, 50, 30], [90, 200, 250]] {
+            let l1 = ranking(Distance::L1, color);
+            let minkowski = ranking(Distance::Minkowski(1.0), color);
+            for ((l1_color, l1_diff), (minkowski_color, minkowski_diff)) in
+                l1.iter().zip(minkowski.iter())
+            {
+                assert_eq!(l1_color, minkowski_color);
+                assert!((l1_diff - minkowski_diff).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn exact_matches_pass_through_ciede2000() {
+        for illuminant in [Illuminant::D65, Illuminant::D50] {
+            let matcher =
+                Matcher::new(PALETTE, Distance::Ciede2000, illuminant).passing_exact_matches();
+            for &color in PALETTE {
+                assert_eq!(matcher.nearest(color), (color, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn minkowski_infinity_is_the_biggest_channel_difference() {
+        let (nearest, diff) =
+            Matcher::new(PALETTE, Distance::Minkowski(f32::INFINITY), Illuminant::D65)
+                .nearest([120, 130, 150]);
+        assert_eq!(nearest, [128, 128, 128]);
+        assert_eq!(diff, 22.0);
+    }
+}
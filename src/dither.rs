@@ -0,0 +1,83 @@
+//! Error diffusion kernels, for spreading the difference between a pixel and
+//! its palette color onto the pixels that haven't been palettized yet, and
+//! ordered dithering of each channel on its own.
+
+#[derive(Clone, Copy)]
+pub enum Dither {
+    FloydSteinberg,
+    /// Spreads the error over the next two rows too, which is smoother but
+    /// slower.
+    Stucki,
+}
+
+impl Dither {
+    /// Where the error goes, as `(dx, dy, weight)` from the pixel, going left
+    /// to right.
+    pub fn kernel(self) -> &'static [(i64, u32, f32)] {
+        match self {
+            Dither::FloydSteinberg => &[
+                (1, 0, 7.0 / 16.0),
+                (-1, 1, 3.0 / 16.0),
+                (0, 1, 5.0 / 16.0),
+                (1, 1, 1.0 / 16.0),
+            ],
+            Dither::Stucki => &[
+                (1, 0, 8.0 / 42.0),
+                (2, 0, 4.0 / 42.0),
+                (-2, 1, 2.0 / 42.0),
+                (-1, 1, 4.0 / 42.0),
+                (0, 1, 8.0 / 42.0),
+                (1, 1, 4.0 / 42.0),
+                (2, 1, 2.0 / 42.0),
+                (-2, 2, 1.0 / 42.0),
+                (-1, 2, 2.0 / 42.0),
+                (0, 2, 4.0 / 42.0),
+                (1, 2, 2.0 / 42.0),
+                (2, 2, 1.0 / 42.0),
+            ],
+        }
+    }
+}
+
+/// Thresholds for a 4x4 Bayer matrix, out of 16.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// The values each channel takes in a palette, for dithering them separately.
+pub struct ChannelLevels([Vec<u8>; 3]);
+
+impl ChannelLevels {
+    pub fn new(colors: &[[u8; 3]]) -> ChannelLevels {
+        ChannelLevels([0, 1, 2].map(|c| {
+            let mut levels = colors.iter().map(|color| color[c]).collect::<Vec<_>>();
+            levels.sort_unstable();
+            levels.dedup();
+            levels
+        }))
+    }
+
+    /// Round each channel of the color at `(x, y)` to one of the two palette
+    /// values on either side of it, picking the upper one more often the
+    /// closer it is.
+    pub fn dither(&self, color: [u8; 3], x: u32, y: u32) -> [u8; 3] {
+        let threshold = (BAYER_4X4[y as usize % 4][x as usize % 4] as f32 + 0.5) / 16.0;
+        [0, 1, 2].map(|c| {
+            let levels = &self.0[c];
+            let value = color[c];
+            let upper = levels.partition_point(|&level| level < value);
+            if upper == levels.len() {
+                return levels[levels.len() - 1];
+            }
+            if upper == 0 || levels[upper] == value {
+                return levels[upper];
+            }
+
+            let (low, high) = (levels[upper - 1], levels[upper]);
+            let fraction = (value - low) as f32 / (high - low) as f32;
+            if fraction > threshold {
+                high
+            } else {
+                low
+            }
+        })
+    }
+}
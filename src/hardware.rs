@@ -0,0 +1,110 @@
+//! What old consoles' palettes can and can't hold.
+
+use crate::palette::{NDS_SUBPALETTES, NDS_SUBPALETTE_COLORS};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy)]
+pub enum Hardware {
+    Nes,
+    GameBoy,
+    Snes,
+    Gba,
+    Nds,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ConstraintCheck {
+    pub constraint: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Every NES subpalette starts with the same background color, so 8
+/// subpalettes of 4 only hold this many.
+const NES_COLORS: usize = 1 + 8 * 3;
+const GAME_BOY_SHADES: usize = 4;
+/// Color RAM on the SNES and GBA.
+const CGRAM_COLORS: usize = 256;
+
+/// Check the palette against each of the hardware's limits. Palettes are only
+/// colors, so limits on which tiles use which subpalette aren't checked.
+pub fn check_hardware(colors: &[[u8; 3]], hardware: Hardware) -> Vec<ConstraintCheck> {
+    match hardware {
+        Hardware::Nes => vec![at_most(
+            colors,
+            NES_COLORS,
+            "at most 25 colors, a shared background and 3 for each of 8 subpalettes",
+        )],
+
+        Hardware::GameBoy => {
+            let greys = colors.iter().filter(|[r, g, b]| r == g && g == b).count();
+            vec![
+                ConstraintCheck {
+                    constraint: "exactly 4 shades",
+                    passed: colors.len() == GAME_BOY_SHADES,
+                    detail: format!("the palette has {} colors", colors.len()),
+                },
+                ConstraintCheck {
+                    constraint: "every color is grey",
+                    passed: greys == colors.len(),
+                    detail: format!("{} of {} colors are grey", greys, colors.len()),
+                },
+            ]
+        }
+
+        Hardware::Snes => vec![
+            at_most(
+                colors,
+                CGRAM_COLORS,
+                "at most 256 colors, 16 subpalettes of 16",
+            ),
+            fits_15_bit(colors),
+        ],
+
+        Hardware::Gba => vec![
+            at_most(
+                colors,
+                CGRAM_COLORS,
+                "at most 256 colors, one palette of 256 or 16 of 16",
+            ),
+            fits_15_bit(colors),
+        ],
+
+        Hardware::Nds => vec![
+            at_most(
+                colors,
+                NDS_SUBPALETTES * NDS_SUBPALETTE_COLORS,
+                "at most 240 colors, 16 subpalettes of 15 and a transparent color",
+            ),
+            fits_15_bit(colors),
+        ],
+    }
+}
+
+fn at_most(colors: &[[u8; 3]], max: usize, constraint: &'static str) -> ConstraintCheck {
+    ConstraintCheck {
+        constraint,
+        passed: colors.len() <= max,
+        detail: format!("the palette has {} colors", colors.len()),
+    }
+}
+
+/// Whether a channel is one of the 32 levels 5 bits has, either shifted up or
+/// scaled up to 255.
+fn fits_5_bits(c: u8) -> bool {
+    let scaled = (c as f32 * 31.0 / 255.0).round();
+    c & 0b111 == 0 || (scaled * 255.0 / 31.0).round() as u8 == c
+}
+
+fn fits_15_bit(colors: &[[u8; 3]]) -> ConstraintCheck {
+    let misfits = colors
+        .iter()
+        .filter(|color| !color.iter().all(|&c| fits_5_bits(c)))
+        .count();
+    ConstraintCheck {
+        constraint: "every color fits in 15 bits",
+        passed: misfits == 0,
+        detail: format!("{} of {} colors don't", misfits, colors.len()),
+    }
+}
@@ -0,0 +1,106 @@
+//! Check how closely a palette's hues follow the classic color harmonies.
+
+use crate::color;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Hue offsets from the base hue for each harmony.
+const HARMONIES: &[(&str, &[f32])] = &[
+    ("complementary", &[0.0, 180.0]),
+    ("analogous", &[-30.0, 0.0, 30.0]),
+    ("triadic", &[0.0, 120.0, 240.0]),
+    ("split_complementary", &[0.0, 150.0, 210.0]),
+    ("tetradic", &[0.0, 60.0, 180.0, 240.0]),
+];
+
+/// Greys and near-blacks don't have a meaningful hue.
+const MIN_SATURATION: f32 = 0.1;
+const MIN_VALUE: f32 = 0.1;
+
+/// A palette approximates a harmony if its mean deviation is at most this
+/// many degrees.
+const MAX_DEVIATION: f32 = 15.0;
+
+#[derive(Serialize, ToSchema)]
+pub struct HarmonyReport {
+    pub hues: Vec<f32>,
+    pub harmonies: Vec<HarmonyFit>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HarmonyFit {
+    pub harmony: &'static str,
+    /// The best fitting hue angles, in degrees.
+    pub angles: Vec<f32>,
+    /// Mean distance in degrees between the palette's hues and the angles.
+    /// Missing if the palette has no saturated colors.
+    pub deviation: Option<f32>,
+    pub approximates: bool,
+}
+
+fn hue_distance(a: f32, b: f32) -> f32 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+/// How far apart the hues and the harmony's angles are. This goes both ways,
+/// so a palette has to actually use every angle to fit well rather than
+/// just sitting near one of them.
+fn mean_deviation(hues: &[f32], angles: &[f32]) -> f32 {
+    let nearest = |hue: f32, others: &[f32]| {
+        others
+            .iter()
+            .map(|&other| hue_distance(hue, other))
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    let hues_to_angles = hues.iter().map(|&h| nearest(h, angles)).sum::<f32>() / hues.len() as f32;
+    let angles_to_hues =
+        angles.iter().map(|&a| nearest(a, hues)).sum::<f32>() / angles.len() as f32;
+    (hues_to_angles + angles_to_hues) / 2.0
+}
+
+pub fn check_harmony(colors: &[[u8; 3]]) -> HarmonyReport {
+    let hues = colors
+        .iter()
+        .map(|&c| color::srgb_to_hsv(c))
+        .filter(|[_, s, v]| *s >= MIN_SATURATION && *v >= MIN_VALUE)
+        .map(|[h, _, _]| h)
+        .collect::<Vec<_>>();
+
+    let harmonies = HARMONIES
+        .iter()
+        .map(|&(harmony, offsets)| {
+            if hues.is_empty() {
+                return HarmonyFit {
+                    harmony,
+                    angles: Vec::new(),
+                    deviation: None,
+                    approximates: false,
+                };
+            }
+
+            // try every base hue and keep whichever fits best
+            let (angles, deviation) = (0..360)
+                .map(|base| {
+                    let angles = offsets
+                        .iter()
+                        .map(|offset| (base as f32 + offset).rem_euclid(360.0))
+                        .collect::<Vec<_>>();
+                    let deviation = mean_deviation(&hues, &angles);
+                    (angles, deviation)
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .expect("there are 360 base hues");
+
+            HarmonyFit {
+                harmony,
+                angles,
+                deviation: Some(deviation),
+                approximates: deviation <= MAX_DEVIATION,
+            }
+        })
+        .collect();
+
+    HarmonyReport { hues, harmonies }
+}
@@ -1,54 +1,245 @@
-use anyhow::anyhow;
+use animation::{encode_gif, is_animated_gif};
+use annotate::annotate_indices;
+use anyhow::{anyhow, Context as _};
+use aseprite::aseprite_palette;
 use axum::{
     body::Body,
-    extract::{DefaultBodyLimit, Multipart},
-    http::StatusCode,
+    error_handling::HandleErrorLayer,
+    extract::{ConnectInfo, DefaultBodyLimit, Multipart, Request},
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{Html, IntoResponse, Response},
     routing::{get, post},
-    Router,
+    BoxError, Json, Router,
 };
-use image::{GenericImage, GenericImageView, Pixel};
+use axum_server::tls_rustls::RustlsConfig;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use bytes::Bytes;
+use cache::{cache_key, ResponseCache};
+use color::{ColorBlindness, Illuminant};
+use counted_body::CountedBody;
+use distance::Distance;
+use dither::Dither;
+use hardware::{check_hardware, ConstraintCheck, Hardware};
+use harmony::{check_harmony, HarmonyReport};
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, GenericImage, GenericImageView, ImageDecoder, ImageReader, Rgba,
+};
+use metrics::{changed_pixels, psnr, ssim};
+use palette::{
+    audit_palette, compare_palettes, complement_palette, cube_palette, dedup_colors,
+    gradient_palette, hex_color, interpolate_palettes, invert_palette, is_act, is_riff_pal,
+    low_contrast_pairs, monochrome_palette, nds_subpalettes, oklab_uniform_palette, palette_colors,
+    palette_colors_in_order, palette_strip, parse_act, parse_gradient, parse_hex_color,
+    parse_riff_pal, parse_text_palette, shift_palette, GradientSpace, LowContrastPair,
+    PaletteAudit, PaletteComparison, PaletteFormat, NDS_SUBPALETTES, NDS_SUBPALETTE_COLORS,
+};
+use palette_sort::{rearrange_palette, sort_by_frequency, sort_palette, Rearrange, SortBy};
+use process::{
+    add_drop_shadow, add_outline, center_crop, dedup_tiles, draw_grid, flood_fill_mask,
+    make_transparent, nearest_candidates, overlay_watermark, pad_to_size, pad_to_square,
+    palettize_image, palettize_tiles, recolor_image, region_colors, remap_image, used_colors,
+    Channels, DiffStats, Options, RecolorRule, RowTimeout,
+};
+use quantize::{
+    compress_palette, histogram, kmeans, mean_squared_error, median_cut, optimization_sample,
+    optimize_palette, palette_usage,
+};
+use serde::{Deserialize, Serialize};
+use session_store::BoundedMemoryStore;
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{HashMap, HashSet},
+    io::{Cursor, Write},
     net::SocketAddr,
+    num::NonZeroUsize,
     path::PathBuf,
-    sync::LazyLock,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, OnceLock,
+    },
+    time::{Duration, Instant},
 };
+use stream::{stream_png, ChannelWriter};
+use svg::image_to_svg;
+use swatch::swatch_page;
 use tera::{Context, Tera};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tower::{timeout::TimeoutLayer, ServiceBuilder};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate as _, SizeAbove},
+        CompressionLayer,
+    },
+    trace::TraceLayer,
+};
+use tower_sessions::{cookie::time, ExpiredDeletion as _, Expiry, Session, SessionManagerLayer};
+use utoipa::ToSchema;
+use utoipa_swagger_ui::SwaggerUi;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+mod animation;
+mod annotate;
+mod api_docs;
+mod aseprite;
+mod cache;
+mod color;
+mod config_schema;
+mod counted_body;
+mod distance;
+mod dither;
+mod hardware;
+mod harmony;
+mod metrics;
+mod palette;
+mod palette_sort;
+mod process;
+mod quantize;
+mod session_store;
+mod stream;
+mod svg;
+mod swatch;
 
 from_env::config!(
     "Palettizer",
     root: String,
     bind: SocketAddr,
+    // how many palettes /palettize/bulk-preview takes at once, default 8
+    max_bulk_palettes: Option<usize>,
+    // threads available for processing images, defaults to the number of cores
+    max_blocking_threads: Option<usize>,
+    // "clf" or "json" to print an access log line per request to stdout
+    access_log_format: Option<String>,
+    // hosts images may be downloaded from. nothing is downloaded without it
+    url_allowlist: Option<Vec<String>>,
+    // hosts /palettize/async may send results to. nothing is sent without it
+    callback_allowlist: Option<Vec<String>>,
+    // the longest side of /palettize/thumbnail images, default 128
+    thumbnail_size: Option<u32>,
+    // JPEG quality of thumbnails from 1 to 100, default 80
+    thumbnail_quality: Option<u8>,
+    // milliseconds a single row of an image may take to palettize before the
+    // request gives up, default 5000
+    row_timeout_ms: Option<u64>,
+    // how many responses to remember, default 32. 0 turns the cache off
+    cache_capacity: Option<usize>,
+    // uploaded palettes with fewer distinct colors than this are turned away,
+    // default 2
+    min_palette_colors: Option<usize>,
+    // images bigger than this aren't kept for use_session_image, default 8MB
+    session_image_max_bytes: Option<usize>,
+    // bytes all sessions together may take up before the ones unused the
+    // longest are forgotten, default 256MB
+    session_store_max_bytes: Option<usize>,
+    // responses bigger than this many bytes aren't remembered, default 4MB
+    cache_max_bytes_per_entry: Option<usize>,
+    // milliseconds /health may take before it gives up with 504, default 100
+    health_timeout_ms: Option<u64>,
+    // milliseconds a request to /palettize may take before it gives up with
+    // 504, default 60000
+    palettize_timeout_ms: Option<u64>,
+    // seconds to wait connecting to a host for downloads and callbacks,
+    // default 10
+    connect_timeout_secs: Option<u64>,
+    // seconds to wait for more of a response from a host once connected,
+    // default 30
+    read_timeout_secs: Option<u64>,
     templates {
         error: PathBuf,
         index: PathBuf,
     }
+    // when both paths are set the server speaks TLS and negotiates HTTP/2 via
+    // ALPN, which multiplexes concurrent uploads over a single connection.
+    // this is especially nice for batch jobs. otherwise plain HTTP/1.1 is used.
+    // the whole table can be left out, see parse_config
+    tls {
+        cert_path: Option<PathBuf>,
+        key_path: Option<PathBuf>,
+    }
 );
 
+/// Every config field, with nested ones as `table.field`. Keep this in step
+/// with the fields above, and config_schema.rs too.
+static CONFIG_FIELDS: &[&str] = &[
+    "root",
+    "bind",
+    "max_bulk_palettes",
+    "max_blocking_threads",
+    "access_log_format",
+    "url_allowlist",
+    "callback_allowlist",
+    "thumbnail_size",
+    "thumbnail_quality",
+    "row_timeout_ms",
+    "cache_capacity",
+    "cache_max_bytes_per_entry",
+    "min_palette_colors",
+    "session_image_max_bytes",
+    "session_store_max_bytes",
+    "health_timeout_ms",
+    "palettize_timeout_ms",
+    "connect_timeout_secs",
+    "read_timeout_secs",
+    "templates.error",
+    "templates.index",
+    "tls.cert_path",
+    "tls.key_path",
+];
+
+/// The environment variable `hydrate_from_env` reads a config field from.
+fn env_var_name(field: &str) -> String {
+    format!("PALETTIZER_{}", field.replace('.', "_").to_uppercase())
+}
+
 static CONFIG: LazyLock<Config> = LazyLock::new(|| {
-    let arg = std::env::args().nth(1).expect("need config filename arg");
+    // the test harness takes the arguments, so tests use the example config
+    let arg = if cfg!(test) {
+        String::from(concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"))
+    } else {
+        std::env::args().nth(1).expect("need config filename arg")
+    };
     let content = std::fs::read_to_string(arg).expect("could not read config file");
-    let mut config = toml::from_str::<Config>(&content).expect("invalid TOML");
+    let mut config = parse_config(&content).expect("invalid TOML");
     config.hydrate_from_env();
     config
 });
 
+/// `config!` has no way to make a table optional, so a config without a
+/// `[tls]` table gets an empty one, which leaves TLS off.
+fn parse_config(content: &str) -> Result<Config, toml::de::Error> {
+    let mut table = toml::from_str::<toml::Table>(content)?;
+    table
+        .entry("tls")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    table.try_into()
+}
+
 static ERROR_TEMPLATE: &str = "error";
 static INDEX_TEMPLATE: &str = "index";
 
-static TEMPLATES: LazyLock<Tera> = LazyLock::new(|| {
-    let mut tera = Tera::default();
+static TEMPLATES: OnceLock<Tera> = OnceLock::new();
+
+fn templates() -> &'static Tera {
+    TEMPLATES
+        .get()
+        .expect("templates are loaded before serving")
+}
 
-    let error_template =
-        std::fs::read_to_string(CONFIG.templates.error.as_path()).expect("no such error template");
-    tera.add_raw_template(ERROR_TEMPLATE, &error_template)
-        .expect("could not parse error template");
+fn load_templates(config: &Config) -> anyhow::Result<Tera> {
+    let mut tera = Tera::default();
 
-    let index_template =
-        std::fs::read_to_string(CONFIG.templates.index.as_path()).expect("no such index template");
-    tera.add_raw_template(INDEX_TEMPLATE, &index_template)
-        .expect("could not parse index template");
+    for (name, path) in [
+        (ERROR_TEMPLATE, &config.templates.error),
+        (INDEX_TEMPLATE, &config.templates.index),
+    ] {
+        let template = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {} template {}", name, path.display()))?;
+        tera.add_raw_template(name, &template)
+            .with_context(|| format!("could not parse {} template {}", name, path.display()))?;
+    }
 
     fn make_url(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
         let Some(tera::Value::String(text)) = args.get("text") else {
@@ -64,11 +255,357 @@ static TEMPLATES: LazyLock<Tera> = LazyLock::new(|| {
     }
     tera.register_function("url", make_url);
 
-    tera
-});
+    Ok(tera)
+}
+
+/// Say which build is running and how it's configured, for working out what a
+/// deployment is actually doing.
+fn log_startup_info(config: &Config) {
+    let built = env!("PALETTIZER_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0))
+        .map_or(String::from("unknown"), |built| built.to_rfc3339());
+    tracing::info!(
+        "{} {}, built {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        built
+    );
+    tracing::info!(
+        "config from {}",
+        std::env::args().nth(1).unwrap_or_default()
+    );
+    tracing::info!("root {}, bind {}", config.root, config.bind);
+    tracing::info!(
+        "templates: error {}, index {}",
+        config.templates.error.display(),
+        config.templates.index.display()
+    );
+
+    // only what's been changed from the defaults
+    let set = [
+        (
+            "max_bulk_palettes",
+            config.max_bulk_palettes.map(|n| n.to_string()),
+        ),
+        (
+            "max_blocking_threads",
+            config.max_blocking_threads.map(|n| n.to_string()),
+        ),
+        ("access_log_format", config.access_log_format.clone()),
+        (
+            "url_allowlist",
+            config.url_allowlist.as_ref().map(|hosts| hosts.join(", ")),
+        ),
+        (
+            "callback_allowlist",
+            config
+                .callback_allowlist
+                .as_ref()
+                .map(|hosts| hosts.join(", ")),
+        ),
+        (
+            "thumbnail_size",
+            config.thumbnail_size.map(|n| n.to_string()),
+        ),
+        (
+            "thumbnail_quality",
+            config.thumbnail_quality.map(|n| n.to_string()),
+        ),
+        (
+            "row_timeout_ms",
+            config.row_timeout_ms.map(|n| n.to_string()),
+        ),
+        (
+            "cache_capacity",
+            config.cache_capacity.map(|n| n.to_string()),
+        ),
+        (
+            "cache_max_bytes_per_entry",
+            config.cache_max_bytes_per_entry.map(|n| n.to_string()),
+        ),
+        (
+            "min_palette_colors",
+            config.min_palette_colors.map(|n| n.to_string()),
+        ),
+        (
+            "session_image_max_bytes",
+            config.session_image_max_bytes.map(|n| n.to_string()),
+        ),
+        (
+            "session_store_max_bytes",
+            config.session_store_max_bytes.map(|n| n.to_string()),
+        ),
+        (
+            "health_timeout_ms",
+            config.health_timeout_ms.map(|n| n.to_string()),
+        ),
+        (
+            "palettize_timeout_ms",
+            config.palettize_timeout_ms.map(|n| n.to_string()),
+        ),
+        (
+            "connect_timeout_secs",
+            config.connect_timeout_secs.map(|n| n.to_string()),
+        ),
+        (
+            "read_timeout_secs",
+            config.read_timeout_secs.map(|n| n.to_string()),
+        ),
+        (
+            "tls.cert_path",
+            config
+                .tls
+                .cert_path
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        ),
+        (
+            "tls.key_path",
+            config
+                .tls
+                .key_path
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        ),
+    ];
+    for (field, value) in set {
+        if let Some(value) = value {
+            tracing::info!("{} = {}", field, value);
+        }
+    }
+}
+
+/// Make sure everything the config points to is actually there before we
+/// start taking requests, rather than finding out on the first one. The
+/// templates it loaded are handed back for [`TEMPLATES`].
+fn run_startup_checks(config: &Config) -> anyhow::Result<Tera> {
+    let tera = load_templates(config)?;
+
+    if let Some(format) = &config.access_log_format {
+        if !matches!(format.as_str(), "clf" | "json") {
+            return Err(anyhow!(
+                "access_log_format must be clf or json, got {}",
+                format
+            ));
+        }
+    }
+
+    if config.thumbnail_size == Some(0) {
+        return Err(anyhow!("thumbnail_size must be at least 1"));
+    }
+    if let Some(quality) = config.thumbnail_quality {
+        if !(1..=100).contains(&quality) {
+            return Err(anyhow!(
+                "thumbnail_quality must be between 1 and 100, got {}",
+                quality
+            ));
+        }
+    }
+
+    if config.row_timeout_ms == Some(0) {
+        return Err(anyhow!("row_timeout_ms must be at least 1"));
+    }
+    if config.health_timeout_ms == Some(0) {
+        return Err(anyhow!("health_timeout_ms must be at least 1"));
+    }
+    if config.palettize_timeout_ms == Some(0) {
+        return Err(anyhow!("palettize_timeout_ms must be at least 1"));
+    }
+    if config.connect_timeout_secs == Some(0) {
+        return Err(anyhow!("connect_timeout_secs must be at least 1"));
+    }
+    if config.read_timeout_secs == Some(0) {
+        return Err(anyhow!("read_timeout_secs must be at least 1"));
+    }
+
+    for path in [&config.tls.cert_path, &config.tls.key_path]
+        .into_iter()
+        .flatten()
+    {
+        std::fs::File::open(path)
+            .with_context(|| format!("could not read TLS file {}", path.display()))?;
+    }
+
+    Ok(tera)
+}
+
+static HEALTH_ENDPOINT: LazyLock<String> = LazyLock::new(|| format!("{}/health", CONFIG.root));
+static CONFIG_SCHEMA_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/config/schema", CONFIG.root));
 
 static PALETTIZE_ENDPOINT: LazyLock<String> =
     LazyLock::new(|| format!("{}/palettize/", CONFIG.root));
+static PALETTIZE_TIMED_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/timed", CONFIG.root));
+static PALETTIZE_PREVIEW_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/preview", CONFIG.root));
+static PALETTIZE_BULK_PREVIEW_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/bulk-preview", CONFIG.root));
+static PALETTIZE_FROM_URL_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/from-url", CONFIG.root));
+static PALETTIZE_ASYNC_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/async", CONFIG.root));
+static PALETTIZE_DIFF_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/diff", CONFIG.root));
+static PALETTIZE_THUMBNAIL_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/thumbnail", CONFIG.root));
+static PALETTIZE_COMPARE_METRICS_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/compare-metrics", CONFIG.root));
+static PALETTIZE_STITCH_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/stitch", CONFIG.root));
+static PALETTIZE_RECOLOR_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/recolor", CONFIG.root));
+static PALETTIZE_WATERMARK_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/watermark", CONFIG.root));
+static PALETTIZE_ANIMATION_LOOP_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/animation-loop", CONFIG.root));
+static PALETTIZE_CONSTRAINTS_CHECK_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/constraints-check", CONFIG.root));
+static PALETTIZE_TILESET_DEDUP_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/tileset-dedup", CONFIG.root));
+static PALETTIZE_WITH_ANNOTATIONS_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/with-annotations", CONFIG.root));
+static PALETTIZE_WITH_GRID_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/with-grid", CONFIG.root));
+static PALETTIZE_VIDEO_FRAME_SEQUENCE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palettize/video-frame-sequence", CONFIG.root));
+
+static VALIDATE_PALETTE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/validate-palette", CONFIG.root));
+static PALETTE_VALIDATE_HARDWARE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/validate-hardware", CONFIG.root));
+static PALETTE_HARMONY_CHECK_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/harmony-check", CONFIG.root));
+static PALETTE_FROM_IMAGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/from-image", CONFIG.root));
+static PALETTE_HISTOGRAM_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/histogram", CONFIG.root));
+static PALETTE_CHECK_CONTRAST_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/check-contrast", CONFIG.root));
+static PALETTE_COMPARE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/compare", CONFIG.root));
+static PALETTE_MERGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/merge", CONFIG.root));
+static PALETTE_INVERT_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/invert", CONFIG.root));
+static PALETTE_SHIFT_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/shift", CONFIG.root));
+static PALETTE_DESATURATE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/desaturate", CONFIG.root));
+static PALETTE_COMPLEMENT_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/complement", CONFIG.root));
+static PALETTE_REARRANGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/rearrange", CONFIG.root));
+static PALETTE_SORT_BY_IMAGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/sort-by-image", CONFIG.root));
+static PALETTE_SORT_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/sort", CONFIG.root));
+static PALETTE_GENERATE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/generate", CONFIG.root));
+static PALETTE_INTERPOLATE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/interpolate", CONFIG.root));
+static PALETTE_TO_IMAGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/to-image", CONFIG.root));
+static PALETTE_PREVIEW_IMAGE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/preview-image", CONFIG.root));
+static PALETTE_TO_ASEPRITE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/to-aseprite", CONFIG.root));
+static PALETTE_CONVERT_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/convert", CONFIG.root));
+static PALETTE_COMPRESS_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/compress", CONFIG.root));
+static PALETTE_OPTIMIZE_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/palette/optimize", CONFIG.root));
+static OPENAPI_ENDPOINT: LazyLock<String> =
+    LazyLock::new(|| format!("{}/api-docs/openapi.json", CONFIG.root));
+
+static MAX_UPLOAD_BYTES: usize = 8_000_000;
+static CALLBACK_ATTEMPTS: u32 = 3;
+static CALLBACK_FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+static NEXT_JOB: AtomicU64 = AtomicU64::new(0);
+/// Body bytes of every request and response, for `/health`.
+static TOTAL_BYTES_IN: AtomicU64 = AtomicU64::new(0);
+static TOTAL_BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+/// How many chunks of a streamed response can be waiting to be sent.
+static STREAM_CHUNKS: usize = 16;
+
+static PREVIEW_SIZE: u32 = 200;
+/// What /palettize/with-grid draws with, if it's in the palette.
+static DEFAULT_GRID_COLOR: [u8; 3] = [255, 0, 0];
+static MAX_STRIP_HEIGHT: u32 = 64;
+/// How many of the image's colors /palette/histogram lists.
+static HISTOGRAM_COLORS: usize = 256;
+/// Pixels closer than this to their palette color count towards coverage.
+static COVERAGE_MAX_ERROR: u64 = 10;
+static DEFAULT_MAX_BULK_PALETTES: usize = 8;
+static DEFAULT_THUMBNAIL_SIZE: u32 = 128;
+static DEFAULT_THUMBNAIL_QUALITY: u8 = 80;
+static DEFAULT_OUTPUT_FILENAME: &str = "palettized";
+static DEFAULT_ROW_TIMEOUT_MS: u64 = 5000;
+static DEFAULT_MIN_PALETTE_COLORS: usize = 2;
+static DEFAULT_CACHE_CAPACITY: usize = 32;
+static DEFAULT_CACHE_MAX_BYTES_PER_ENTRY: usize = 4_000_000;
+static DEFAULT_SESSION_IMAGE_MAX_BYTES: usize = 8_000_000;
+static DEFAULT_SESSION_STORE_MAX_BYTES: usize = 256_000_000;
+static DEFAULT_HEALTH_TIMEOUT_MS: u64 = 100;
+static DEFAULT_PALETTIZE_TIMEOUT_MS: u64 = 60_000;
+static DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+static DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+/// Idle connections kept open to each host downloads and callbacks go to.
+static HTTP_POOL_MAX_IDLE_PER_HOST: usize = 8;
+
+/// For downloads and callbacks, so connections to the same host get reused.
+/// A redirect could go anywhere, including places that aren't allowed, so
+/// they're never followed.
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .pool_max_idle_per_host(HTTP_POOL_MAX_IDLE_PER_HOST)
+        .connect_timeout(Duration::from_secs(
+            CONFIG
+                .connect_timeout_secs
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+        .read_timeout(Duration::from_secs(
+            CONFIG
+                .read_timeout_secs
+                .unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+        ))
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("could not make an HTTP client")
+});
+
+/// Sessions are only kept in memory, so don't keep them long.
+static SESSION_INACTIVE_MINUTES: i64 = 30;
+static SESSION_IMAGE_KEY: &str = "image";
+/// The store only notices a session has expired when it's loaded, which an
+/// abandoned one never is again, so they're swept up this often.
+static SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+static SESSION_STORE: LazyLock<BoundedMemoryStore> = LazyLock::new(|| {
+    BoundedMemoryStore::new(
+        CONFIG
+            .session_store_max_bytes
+            .unwrap_or(DEFAULT_SESSION_STORE_MAX_BYTES),
+    )
+});
+
+static CACHE: LazyLock<ResponseCache> = LazyLock::new(|| {
+    ResponseCache::new(
+        CONFIG.cache_capacity.unwrap_or(DEFAULT_CACHE_CAPACITY),
+        CONFIG
+            .cache_max_bytes_per_entry
+            .unwrap_or(DEFAULT_CACHE_MAX_BYTES_PER_ENTRY),
+    )
+});
 
 static DEFAULT_CONTEXT: LazyLock<Context> = LazyLock::new(|| {
     let mut context = Context::new();
@@ -82,7 +619,7 @@ fn render_error(err: AppError) -> Html<String> {
     context.insert("text", &format!("{}", err.0));
     context.insert("backtrace", &format!("{}", err.0.backtrace()));
 
-    match TEMPLATES.render(ERROR_TEMPLATE, &context) {
+    match templates().render(ERROR_TEMPLATE, &context) {
         Ok(rendered) => Html::from(rendered),
         Err(err) => Html::from(format!("couldn't render error template {}", err)),
     }
@@ -92,6 +629,16 @@ struct AppError(anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        if let Some(timeout) = self.0.downcast_ref::<RowTimeout>() {
+            tracing::warn!("{}", timeout);
+            return (StatusCode::REQUEST_TIMEOUT, timeout.to_string()).into_response();
+        }
+
+        if let Some(Rejection(status, text)) = self.0.downcast_ref::<Rejection>() {
+            tracing::debug!("rejected: {}", text);
+            return (*status, text.clone()).into_response();
+        }
+
         tracing::error!("{}: {}", self.0, self.0.backtrace());
         (StatusCode::INTERNAL_SERVER_ERROR, render_error(self)).into_response()
     }
@@ -103,23 +650,388 @@ impl From<anyhow::Error> for AppError {
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+/// Something wrong with the request itself. Sent back as plain text with its
+/// status code instead of rendering the error page.
+#[derive(Debug)]
+struct Rejection(StatusCode, String);
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.1)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+fn bad_request(text: impl Into<String>) -> anyhow::Error {
+    Rejection(StatusCode::BAD_REQUEST, text.into()).into()
+}
+
+fn main() -> anyhow::Result<()> {
+    if std::env::args().nth(1).as_deref() == Some("--print-env-vars") {
+        for field in CONFIG_FIELDS {
+            println!("{}", env_var_name(field));
+        }
+        return Ok(());
+    }
+
     tracing_subscriber::fmt::init();
-    tracing::info!("Bind to {}", CONFIG.bind);
+    let tera = run_startup_checks(&CONFIG)?;
+    TEMPLATES
+        .set(tera)
+        .map_err(|_| anyhow!("templates were already loaded"))?;
+    log_startup_info(&CONFIG);
+
+    // the blocking pool is where images are processed, so it's what actually
+    // needs a limit. tokio would otherwise grow it to 512 threads.
+    let max_blocking_threads = match CONFIG.max_blocking_threads {
+        Some(0) => return Err(anyhow!("max_blocking_threads must be at least 1")),
+        Some(threads) => threads,
+        None => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+    };
+    tracing::debug!("{} blocking threads", max_blocking_threads);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(max_blocking_threads)
+        .build()?
+        .block_on(serve())
+}
+
+/// Run CPU-heavy work on the blocking pool so it doesn't hold up the async
+/// workers.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    tokio::task::spawn_blocking(work).await?
+}
+
+/// Answer from the cache if the exact same form was sent to the endpoint
+/// before, otherwise do the work on the blocking pool and remember the
+/// response.
+async fn cached(
+    endpoint: &str,
+    form: Form,
+    work: impl FnOnce(Form) -> anyhow::Result<Response> + Send + 'static,
+) -> anyhow::Result<Response> {
+    let key = cache_key(endpoint, &form.fields);
+    if let Some(response) = CACHE.get(&key) {
+        return Ok(response);
+    }
+
+    let response = blocking(move || work(form)).await?;
+    CACHE.put(key, response).await
+}
+
+/// Every route, with the layers that go around all of them.
+fn app() -> Router {
+    // the work of a request that times out carries on in the blocking pool,
+    // it just isn't waited for
+    let palettize_routes = with_timeout(
+        Router::new()
+            .route(&PALETTIZE_ENDPOINT, post(palettize))
+            .route(&PALETTIZE_TIMED_ENDPOINT, post(palettize_timed))
+            .route(&PALETTIZE_PREVIEW_ENDPOINT, post(palettize_preview))
+            .route(
+                &PALETTIZE_BULK_PREVIEW_ENDPOINT,
+                post(palettize_bulk_preview),
+            )
+            .route(&PALETTIZE_FROM_URL_ENDPOINT, post(palettize_from_url))
+            .route(&PALETTIZE_ASYNC_ENDPOINT, post(palettize_async))
+            .route(&PALETTIZE_DIFF_ENDPOINT, post(palettize_diff))
+            .route(&PALETTIZE_THUMBNAIL_ENDPOINT, post(palettize_thumbnail))
+            .route(
+                &PALETTIZE_COMPARE_METRICS_ENDPOINT,
+                post(palettize_compare_metrics),
+            )
+            .route(&PALETTIZE_STITCH_ENDPOINT, post(palettize_stitch))
+            .route(&PALETTIZE_RECOLOR_ENDPOINT, post(palettize_recolor))
+            .route(&PALETTIZE_WATERMARK_ENDPOINT, post(palettize_watermark))
+            .route(
+                &PALETTIZE_ANIMATION_LOOP_ENDPOINT,
+                post(palettize_animation_loop),
+            )
+            .route(
+                &PALETTIZE_CONSTRAINTS_CHECK_ENDPOINT,
+                post(palettize_constraints_check),
+            )
+            .route(
+                &PALETTIZE_TILESET_DEDUP_ENDPOINT,
+                post(palettize_tileset_dedup),
+            )
+            .route(
+                &PALETTIZE_WITH_ANNOTATIONS_ENDPOINT,
+                post(palettize_with_annotations),
+            )
+            .route(&PALETTIZE_WITH_GRID_ENDPOINT, post(palettize_with_grid))
+            .route(
+                &PALETTIZE_VIDEO_FRAME_SEQUENCE_ENDPOINT,
+                post(palettize_video_frame_sequence),
+            ),
+        CONFIG
+            .palettize_timeout_ms
+            .unwrap_or(DEFAULT_PALETTIZE_TIMEOUT_MS),
+    );
+    let health_routes = with_timeout(
+        Router::new().route(&HEALTH_ENDPOINT, get(health)),
+        CONFIG
+            .health_timeout_ms
+            .unwrap_or(DEFAULT_HEALTH_TIMEOUT_MS),
+    );
 
     let app = Router::new()
         .route(&CONFIG.root, get(index))
-        .route(&PALETTIZE_ENDPOINT, post(palettize))
+        .merge(health_routes)
+        .route(&CONFIG_SCHEMA_ENDPOINT, get(config_schema))
+        .merge(palettize_routes)
+        .route(&VALIDATE_PALETTE_ENDPOINT, post(validate_palette))
+        .route(
+            &PALETTE_VALIDATE_HARDWARE_ENDPOINT,
+            post(palette_validate_hardware),
+        )
+        .route(&PALETTE_HARMONY_CHECK_ENDPOINT, post(palette_harmony_check))
+        .route(&PALETTE_OPTIMIZE_ENDPOINT, post(palette_optimize))
+        .route(&PALETTE_COMPRESS_ENDPOINT, post(palette_compress))
+        .route(&PALETTE_FROM_IMAGE_ENDPOINT, post(palette_from_image))
+        .route(&PALETTE_HISTOGRAM_ENDPOINT, post(palette_histogram))
+        .route(&PALETTE_COMPARE_ENDPOINT, post(palette_compare))
+        .route(
+            &PALETTE_CHECK_CONTRAST_ENDPOINT,
+            post(palette_check_contrast),
+        )
+        .route(&PALETTE_MERGE_ENDPOINT, post(palette_merge))
+        .route(&PALETTE_COMPLEMENT_ENDPOINT, post(palette_complement))
+        .route(&PALETTE_INVERT_ENDPOINT, post(palette_invert))
+        .route(&PALETTE_SHIFT_ENDPOINT, post(palette_shift))
+        .route(&PALETTE_DESATURATE_ENDPOINT, post(palette_desaturate))
+        .route(&PALETTE_SORT_ENDPOINT, post(palette_sort))
+        .route(&PALETTE_SORT_BY_IMAGE_ENDPOINT, post(palette_sort_by_image))
+        .route(&PALETTE_REARRANGE_ENDPOINT, post(palette_rearrange))
+        .route(&PALETTE_GENERATE_ENDPOINT, post(palette_generate))
+        .route(&PALETTE_INTERPOLATE_ENDPOINT, post(palette_interpolate))
+        .route(&PALETTE_TO_IMAGE_ENDPOINT, post(palette_to_image))
+        .route(&PALETTE_TO_ASEPRITE_ENDPOINT, post(palette_to_aseprite))
+        .route(&PALETTE_PREVIEW_IMAGE_ENDPOINT, post(palette_preview_image))
+        .route(&PALETTE_CONVERT_ENDPOINT, post(palette_convert))
+        .merge(
+            SwaggerUi::new(format!("{}/api-docs/swagger-ui", CONFIG.root))
+                .url(OPENAPI_ENDPOINT.as_str(), api_docs::openapi()),
+        )
         .fallback(not_found)
-        .layer(DefaultBodyLimit::max(8_000_000));
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES))
+        // unlike the default, this compresses PNGs too. palettized ones have
+        // few colors and shrink a lot. JPEG and WebP are already as small as
+        // they'll get
+        .layer(
+            CompressionLayer::new().compress_when(
+                SizeAbove::default()
+                    .and(NotForContentType::const_new("image/jpeg"))
+                    .and(NotForContentType::const_new("image/webp")),
+            ),
+        )
+        .layer(
+            SessionManagerLayer::new(SESSION_STORE.clone())
+                .with_secure(CONFIG.tls.cert_path.is_some())
+                .with_expiry(Expiry::OnInactivity(time::Duration::minutes(
+                    SESSION_INACTIVE_MINUTES,
+                ))),
+        );
+    // outside of compression, so it's what actually goes over the wire that's
+    // counted
+    let app = app.layer(middleware::from_fn(count_bytes)).layer(
+        TraceLayer::new_for_http().make_span_with(|request: &Request| {
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                bytes_in = tracing::field::Empty,
+                bytes_out = tracing::field::Empty,
+            )
+        }),
+    );
+    if CONFIG.access_log_format.is_some() {
+        app.layer(middleware::from_fn(access_log))
+    } else {
+        app
+    }
+}
+
+async fn serve() -> anyhow::Result<()> {
+    tracing::info!("Bind to {}", CONFIG.bind);
+
+    let app = app();
+    tokio::spawn(delete_expired_sessions());
 
-    let listener = tokio::net::TcpListener::bind(CONFIG.bind).await?;
-    axum::serve(listener, app).await?;
+    match (&CONFIG.tls.cert_path, &CONFIG.tls.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            tracing::info!("Using TLS");
+            let tls = RustlsConfig::from_pem_file(cert_path, key_path).await?;
+            axum_server::bind_rustls(CONFIG.bind, tls)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        (None, None) => {
+            let listener = tokio::net::TcpListener::bind(CONFIG.bind).await?;
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?;
+        }
+        _ => return Err(anyhow!("need both tls.cert_path and tls.key_path for TLS")),
+    }
 
     Ok(())
 }
 
+async fn delete_expired_sessions() {
+    let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = SESSION_STORE.delete_expired().await {
+            tracing::error!("could not delete expired sessions: {}", err);
+        }
+    }
+}
+
+/// Add up body bytes as they're read and sent, for `/health`, and record them
+/// on the request's span.
+async fn count_bytes(request: Request, next: Next) -> Response {
+    let span = tracing::Span::current();
+    let bytes_in = Arc::new(AtomicU64::new(0));
+
+    let request = {
+        let (span, bytes_in) = (span.clone(), bytes_in.clone());
+        request.map(|body| {
+            CountedBody::wrap(body, move |bytes| {
+                TOTAL_BYTES_IN.fetch_add(bytes, Ordering::Relaxed);
+                bytes_in.store(bytes, Ordering::Relaxed);
+                span.record("bytes_in", bytes);
+            })
+        })
+    };
+    let response = next.run(request).await;
+
+    response.map(|body| {
+        CountedBody::wrap(body, move |bytes_out| {
+            TOTAL_BYTES_OUT.fetch_add(bytes_out, Ordering::Relaxed);
+            span.record("bytes_out", bytes_out);
+            tracing::debug!(
+                parent: &span,
+                bytes_in = bytes_in.load(Ordering::Relaxed),
+                bytes_out,
+                "sent response"
+            );
+        })
+    })
+}
+
+/// Print a line about each request in the configured `access_log_format`,
+/// once the whole response has been sent and its size is known.
+async fn access_log(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().to_string();
+    let version = request.version();
+
+    let bytes_in = Arc::new(AtomicU64::new(0));
+    let request = {
+        let bytes_in = bytes_in.clone();
+        request.map(|body| {
+            CountedBody::wrap(body, move |bytes| bytes_in.store(bytes, Ordering::Relaxed))
+        })
+    };
+
+    let response = next.run(request).await;
+    let status = response.status().as_u16();
+
+    response.map(|body| {
+        CountedBody::wrap(body, move |bytes| {
+            let now = chrono::Local::now();
+            let bytes_in = bytes_in.load(Ordering::Relaxed);
+            match CONFIG.access_log_format.as_deref() {
+                Some("clf") => println!(
+                    "{} - - [{}] \"{} {} {:?}\" {} {}",
+                    addr.ip(),
+                    now.format("%d/%b/%Y:%H:%M:%S %z"),
+                    method,
+                    path,
+                    version,
+                    status,
+                    bytes,
+                ),
+
+                Some("json") => println!(
+                    "{}",
+                    serde_json::json!({
+                        "ip": addr.ip().to_string(),
+                        "time": now.to_rfc3339(),
+                        "method": method.as_str(),
+                        "path": path,
+                        "version": format!("{:?}", version),
+                        "status": status,
+                        "bytes": bytes,
+                        "bytes_in": bytes_in,
+                        "bytes_out": bytes,
+                        "duration_ms": start.elapsed().as_millis() as u64,
+                    })
+                ),
+
+                _ => {}
+            }
+        })
+    })
+}
+
+/// Give up on each of the routes with 504 once they take longer than this.
+fn with_timeout(routes: Router, milliseconds: u64) -> Router {
+    routes.route_layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(timed_out))
+            .layer(TimeoutLayer::new(Duration::from_millis(milliseconds))),
+    )
+}
+
+/// A whole request took longer than its route allows. Not the same as a row
+/// taking too long, which is 408.
+async fn timed_out(_: BoxError) -> (StatusCode, &'static str) {
+    (StatusCode::GATEWAY_TIMEOUT, "the request took too long")
+}
+
+#[derive(Serialize, ToSchema)]
+struct Health {
+    status: &'static str,
+    cache_hits: u64,
+    cache_misses: u64,
+    total_bytes_in: u64,
+    total_bytes_out: u64,
+}
+
+#[utoipa::path(get, path = "/health", responses((status = 200, body = Health)))]
+async fn health() -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        cache_hits: CACHE.hits(),
+        cache_misses: CACHE.misses(),
+        total_bytes_in: TOTAL_BYTES_IN.load(Ordering::Relaxed),
+        total_bytes_out: TOTAL_BYTES_OUT.load(Ordering::Relaxed),
+    })
+}
+
+/// A JSON Schema for the config file, for checking one before deploying it.
+#[utoipa::path(
+    get,
+    path = "/config/schema",
+    responses((status = 200, description = "the config file's JSON Schema", content_type = "application/json"))
+)]
+async fn config_schema() -> Json<serde_json::Value> {
+    Json(config_schema::config_schema())
+}
+
 async fn not_found() -> Result<Html<String>, AppError> {
     Err(AppError(anyhow!("not found")))
 }
@@ -130,94 +1042,4359 @@ async fn index() -> Result<Html<String>, AppError> {
 
 async fn render_index() -> anyhow::Result<Html<String>> {
     Ok(Html::from(
-        TEMPLATES.render(INDEX_TEMPLATE, &DEFAULT_CONTEXT)?,
+        templates().render(INDEX_TEMPLATE, &DEFAULT_CONTEXT)?,
     ))
 }
 
-async fn palettize(form: Multipart) -> Result<Response, AppError> {
-    Ok(do_palettize(form).await?)
+#[utoipa::path(
+    post,
+    path = "/palettize/",
+    request_body(content = api_docs::PalettizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palettized image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize(session: Session, form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize(session, form).await?)
 }
 
-async fn do_palettize(mut form: Multipart) -> anyhow::Result<Response> {
-    let mut images = HashMap::new();
+async fn do_palettize(session: Session, form: Multipart) -> anyhow::Result<Response> {
+    let mut form = Form::read(form).await?;
+    use_session_image(&session, &mut form).await?;
+    if form.flag("stream", false)? {
+        return stream_response(form).await;
+    }
+    // a remembered multipart response would have the first one's
+    // processing_time_ms
+    if form.flag("return_multipart", false)? {
+        return blocking(move || palettize_response(&form, &mut Timings::default())).await;
+    }
 
-    while let Some(part) = form.next_field().await? {
-        let Some(name) = part.name() else {
-            // this sucks
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from("need a name"))
-                .unwrap());
-        };
-        let name = name.to_owned();
+    cached(&PALETTIZE_ENDPOINT, form, move |form| {
+        palettize_response(&form, &mut Timings::default())
+    })
+    .await
+}
+
+/// How long each part of palettizing took. Modes other than plain
+/// palettizing aren't broken down.
+#[derive(Default)]
+struct Timings {
+    decode: Duration,
+    palette_extract: Duration,
+    map: Duration,
+    encode: Duration,
+}
+
+/// Everything `/palettize/` does once the form is read.
+fn palettize_response(form: &Form, timings: &mut Timings) -> anyhow::Result<Response> {
+    match form.text("mode")? {
+        Some("animated_gif") => return animated_gif_response(form),
+        Some("nearest_n") => return nearest_n_response(form),
+        _ => {}
+    }
+    match form.text("multi_palette_selection")? {
+        None => {}
+        Some("auto") => return multi_palette_response(form),
+        Some(_) => return Err(bad_request("multi_palette_selection must be auto")),
+    }
+    match form.text("hardware")? {
+        None => {}
+        Some("nds") => return nds_response(form),
+        Some(_) => return Err(bad_request("hardware must be nds")),
+    }
+
+    let start = Instant::now();
+    let mut the_image = input_image(form)?;
+    let decoded = Instant::now();
+    timings.decode = decoded - start;
+    let colors = mode_palette(form)?;
+    let extracted = Instant::now();
+    timings.palette_extract = extracted - decoded;
+    let stats = process_image(&mut the_image, form, colors.as_deref())?;
+    let mapped = Instant::now();
+    timings.map = mapped - extracted;
 
-        use bytes::Buf;
-        let bytes = part.bytes().await?;
-        let mut data = Vec::with_capacity(bytes.len());
-        std::io::copy(&mut bytes.reader(), &mut data)?;
+    let response = if form.flag("return_multipart", false)? {
+        multipart_response(&the_image, form, start.elapsed())?
+    } else {
+        image_response(&the_image, form)?
+    };
+    let response = tile_align_response(response, form, &the_image)?;
+    let response = used_colors_response(response, form, &the_image, colors.as_deref())?;
+    let response = tile_config_response(response, None)?;
+    timings.encode = mapped.elapsed();
+    diff_stats_response(response, stats)
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/timed",
+    request_body(content = api_docs::PalettizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palettized image, with how long each part took in headers", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_timed(session: Session, form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_timed(session, form).await?)
+}
+
+/// The same as `/palettize/`, but never from the cache, and with milliseconds
+/// spent decoding, reading the palette, palettizing, and encoding in headers.
+async fn do_palettize_timed(session: Session, form: Multipart) -> anyhow::Result<Response> {
+    let mut form = Form::read(form).await?;
+    use_session_image(&session, &mut form).await?;
+    blocking(move || {
+        let start = Instant::now();
+        let mut timings = Timings::default();
+        let mut response = palettize_response(&form, &mut timings)?;
+        let total = start.elapsed();
+
+        let headers = response.headers_mut();
+        for (name, duration) in [
+            ("x-palettizer-decode-ms", timings.decode),
+            ("x-palettizer-palette-extract-ms", timings.palette_extract),
+            ("x-palettizer-map-ms", timings.map),
+            ("x-palettizer-encode-ms", timings.encode),
+            ("x-palettizer-total-ms", total),
+        ] {
+            headers.insert(
+                name,
+                HeaderValue::from_str(&format!("{:.3}", duration.as_secs_f64() * 1000.0))?,
+            );
+        }
+        Ok(response)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct ImageStats {
+    color_count: usize,
+    colors: Vec<String>,
+    dimensions: [u32; 2],
+    processing_time_ms: u128,
+}
+
+/// The image and some stats about it in one `multipart/mixed` response.
+fn multipart_response(
+    image: &DynamicImage,
+    form: &Form,
+    processing_time: Duration,
+) -> anyhow::Result<Response> {
+    let format = OutputFormat::from_form(form)?;
+    let colors = frequency_sorted(palette_colors(image), form, image)?;
+    let stats = ImageStats {
+        color_count: colors.len(),
+        colors: colors.into_iter().map(hex_color).collect(),
+        dimensions: [image.width(), image.height()],
+        processing_time_ms: processing_time.as_millis(),
+    };
+
+    // long and unlikely enough that it won't turn up in the image by accident
+    let boundary = format!(
+        "palettizer-{:x}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+    let mut body = Vec::new();
+    write!(
+        body,
+        "--{}\r\nContent-Type: {}\r\n\r\n",
+        boundary,
+        format.content_type()
+    )?;
+    body.extend_from_slice(&format.encode(image)?);
+    write!(
+        body,
+        "\r\n--{}\r\nContent-Type: application/json\r\n\r\n",
+        boundary
+    )?;
+    serde_json::to_writer(&mut body, &stats)?;
+    write!(body, "\r\n--{}--\r\n", boundary)?;
+
+    Ok(Response::builder()
+        .header(
+            "Content-Type",
+            format!("multipart/mixed; boundary={}", boundary),
+        )
+        .body(Body::from(body))?)
+}
 
-        tracing::debug!("got {}", name);
+/// Remember the uploaded image if the form asks to, or use the one from
+/// before, so it doesn't have to be uploaded again for every palette that's
+/// tried.
+async fn use_session_image(session: &Session, form: &mut Form) -> anyhow::Result<()> {
+    if form.flag("clear_session", false)? {
+        session.remove::<String>(SESSION_IMAGE_KEY).await?;
+    }
+
+    if form.flag("use_session_image", false)? {
+        let Some(image) = session.get::<String>(SESSION_IMAGE_KEY).await? else {
+            return Err(bad_request("there's no image in the session"));
+        };
+        let image = BASE64_STANDARD.decode(image)?;
+        form.fields
+            .insert(String::from("image"), Bytes::from(image));
+        return Ok(());
+    }
+
+    if !form.flag("keep_session_image", false)? {
+        return Ok(());
+    }
+    let max_bytes = CONFIG
+        .session_image_max_bytes
+        .unwrap_or(DEFAULT_SESSION_IMAGE_MAX_BYTES);
+    if let Some(image) = form.fields.get("image") {
+        if image.len() <= max_bytes {
+            // the session is stored as JSON, where base64 is much smaller than
+            // an array of numbers
+            session
+                .insert(SESSION_IMAGE_KEY, BASE64_STANDARD.encode(image))
+                .await?;
+        }
+    }
+    Ok(())
+}
 
-        images.insert(name, image::load_from_memory(&data));
+/// Say how far pixels landed from their palette colors, if they were
+/// palettized.
+fn diff_stats_response(
+    mut response: Response,
+    stats: Option<DiffStats>,
+) -> anyhow::Result<Response> {
+    if let Some(stats) = stats {
+        let headers = response.headers_mut();
+        headers.insert(
+            "x-palettizer-min-diff",
+            HeaderValue::from_str(&stats.min().to_string())?,
+        );
+        headers.insert(
+            "x-palettizer-max-diff",
+            HeaderValue::from_str(&stats.max().to_string())?,
+        );
+        headers.insert(
+            "x-palettizer-mean-diff",
+            HeaderValue::from_str(&format!("{:.2}", stats.mean()))?,
+        );
     }
+    Ok(response)
+}
+
+/// Start sending the palettized PNG as soon as its first rows are done, rather
+/// than once the whole image is. Only plain palettizing works this way.
+async fn stream_response(form: Form) -> anyhow::Result<Response> {
+    let (the_image, colors, options, form) = blocking(move || {
+        if form.text("mode")?.is_some_and(|mode| mode != "palettize")
+            || form.text("multi_palette_selection")?.is_some()
+            || form.text("return_format")?.is_some()
+            || form
+                .text("output_format")?
+                .is_some_and(|format| format != "png")
+        {
+            return Err(bad_request("stream only works for palettizing to a PNG"));
+        }
+
+        let options = options_from_form(&form)?;
+        if options.smooth_noise {
+            return Err(bad_request("smooth_noise doesn't work with stream"));
+        }
+        if options.dither.is_some() || options.per_channel_dither {
+            return Err(bad_request("dither doesn't work with stream"));
+        }
+        if options.channels != Channels::Rgb {
+            return Err(bad_request("channels doesn't work with stream"));
+        }
+
+        let the_image = input_image(&form)?;
+        let colors = input_palette(&form)?;
+        check_pins(&options, &colors)?;
+        Ok((the_image, colors, options, form))
+    })
+    .await?;
+
+    let (sender, receiver) = mpsc::channel(STREAM_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = stream_png(&the_image, &colors, &options, ChannelWriter(sender)) {
+            tracing::warn!("stopped streaming: {}", err);
+        }
+    });
+
+    file_response(
+        Body::from_stream(ReceiverStream::new(receiver)),
+        "image/png",
+        "png",
+        &form,
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/preview",
+    request_body(content = api_docs::PalettizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palettized center of the image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_preview(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_preview(form).await?)
+}
+
+async fn do_palettize_preview(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    cached(&PALETTIZE_PREVIEW_ENDPOINT, form, move |form| {
+        let mut preview = center_crop(&input_image(&form)?, PREVIEW_SIZE, PREVIEW_SIZE);
+        process_image(&mut preview, &form, mode_palette(&form)?.as_deref())?;
+        image_response(&preview, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/thumbnail",
+    request_body(content = api_docs::PalettizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the shrunken, palettized image", content_type = "image/jpeg", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_thumbnail(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_thumbnail(form).await?)
+}
+
+/// Shrink the whole image to fit in a square, then palettize it. Shrinking
+/// first means there are far fewer pixels to palettize.
+async fn do_palettize_thumbnail(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    cached(&PALETTIZE_THUMBNAIL_ENDPOINT, form, move |form| {
+        let size = CONFIG.thumbnail_size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+        let the_image = input_image(&form)?;
+        // only ever shrink, small images are already thumbnail sized
+        let mut thumbnail = if the_image.width() <= size && the_image.height() <= size {
+            the_image
+        } else {
+            the_image.resize(size, size, FilterType::CatmullRom)
+        };
+        process_image(&mut thumbnail, &form, mode_palette(&form)?.as_deref())?;
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(
+            &mut jpeg,
+            CONFIG
+                .thumbnail_quality
+                .unwrap_or(DEFAULT_THUMBNAIL_QUALITY),
+        )
+        .encode_image(&thumbnail.to_rgb8())?;
+
+        file_response(jpeg, "image/jpeg", "jpg", &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/bulk-preview",
+    request_body(content = api_docs::BulkPreviewForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a base64 PNG preview per palette", body = Vec<String>),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_bulk_preview(form: Multipart) -> Result<Json<Vec<String>>, AppError> {
+    Ok(do_palettize_bulk_preview(form).await?)
+}
+
+/// Preview the image against each of `palette_0`, `palette_1`, ... and send
+/// the results back as base64 PNGs in the same order.
+async fn do_palettize_bulk_preview(form: Multipart) -> anyhow::Result<Json<Vec<String>>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = center_crop(&input_image(&form)?, PREVIEW_SIZE, PREVIEW_SIZE);
+
+        let palettes = input_palettes(&form)?;
+        let options = options_from_form(&form)?;
+        let mut previews = Vec::with_capacity(palettes.len());
+        for colors in palettes {
+            check_pins(&options, &colors)?;
+            let mut preview = the_image.clone();
+            palettize_image(&mut preview, &colors, &options)?;
+            previews.push(BASE64_STANDARD.encode(encode_png(&preview)?));
+        }
+
+        Ok(Json(previews))
+    })
+    .await
+}
+
+#[derive(Deserialize, ToSchema)]
+struct FromUrl {
+    image_url: String,
+    palette_url: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/from-url",
+    request_body(content = FromUrl, content_type = "application/json"),
+    responses(
+        (status = 200, description = "the palettized image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+        (status = 502, description = "a download failed", body = String),
+    )
+)]
+async fn palettize_from_url(Json(urls): Json<FromUrl>) -> Result<Response, AppError> {
+    Ok(do_palettize_from_url(urls).await?)
+}
+
+async fn do_palettize_from_url(urls: FromUrl) -> anyhow::Result<Response> {
+    let (image, palette) =
+        tokio::join!(fetch_image(&urls.image_url), fetch_image(&urls.palette_url));
+    let form = Form {
+        fields: HashMap::from([
+            (String::from("image"), image?),
+            (String::from("palette"), palette?),
+        ]),
+    };
+
+    blocking(move || {
+        let mut the_image = input_image(&form)?;
+        let stats = process_image(&mut the_image, &form, mode_palette(&form)?.as_deref())?;
+        diff_stats_response(image_response(&the_image, &form)?, stats)
+    })
+    .await
+}
 
-    let Some(maybe_image) = images.remove("image") else {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::from("need an image"))
-            .unwrap());
+/// Download an image from one of the hosts in `url_allowlist`.
+async fn fetch_image(url: &str) -> anyhow::Result<Bytes> {
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return Err(bad_request(format!("invalid url {}", url)));
     };
-    let Ok(mut the_image) = maybe_image else {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::from("image is invalid"))
-            .unwrap());
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(bad_request(format!("{} is not an http url", url)));
+    }
+    let allowed = parsed.host_str().is_some_and(|host| {
+        CONFIG
+            .url_allowlist
+            .iter()
+            .flatten()
+            .any(|allowed| allowed == host)
+    });
+    if !allowed {
+        return Err(Rejection(
+            StatusCode::FORBIDDEN,
+            format!("{} is not on the url allowlist", url),
+        )
+        .into());
+    }
+
+    let bad_gateway = |text: String| Rejection(StatusCode::BAD_GATEWAY, text);
+
+    let mut response = match HTTP_CLIENT.get(parsed).send().await {
+        Ok(response) => response,
+        Err(err) => return Err(bad_gateway(format!("could not fetch {}: {}", url, err)).into()),
     };
+    if !response.status().is_success() {
+        return Err(bad_gateway(format!("{} returned {}", url, response.status())).into());
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with("image/") {
+        return Err(Rejection(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("{} is {:?}, expected an image", url, content_type),
+        )
+        .into());
+    }
 
-    let Some(maybe_palette) = images.remove("palette") else {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::from("need a palette"))
-            .unwrap());
+    let too_large = || {
+        Rejection(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("{} is larger than {} bytes", url, MAX_UPLOAD_BYTES),
+        )
     };
-    let Ok(the_palette) = maybe_palette else {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .body(Body::from("palette is invalid"))
-            .unwrap());
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_UPLOAD_BYTES as u64)
+    {
+        return Err(too_large().into());
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if data.len() + chunk.len() > MAX_UPLOAD_BYTES {
+            return Err(too_large().into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(data))
+}
+
+#[derive(Serialize, ToSchema)]
+struct AsyncJob {
+    job_id: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/async",
+    request_body(content = api_docs::AsyncForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 202, description = "the job was started, and the image will be sent to callback_url", body = AsyncJob),
+        (status = 400, description = "a field is missing or invalid", body = String),
+        (status = 403, description = "callback_url is not on the callback allowlist", body = String),
+    )
+)]
+async fn palettize_async(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_async(form).await?)
+}
+
+async fn do_palettize_async(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    let Some(callback_url) = form.text("callback_url")? else {
+        return Err(bad_request("missing callback_url"));
     };
+    let callback_url = callback_url_from(callback_url)?;
+
+    let job_id = format!(
+        "{:x}-{}",
+        chrono::Utc::now().timestamp_millis(),
+        NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+    );
+    tracing::debug!("starting job {} for {}", job_id, callback_url);
+
+    let job = job_id.clone();
+    tokio::spawn(async move {
+        let result = blocking(move || {
+            let mut the_image = input_image(&form)?;
+            process_image(&mut the_image, &form, mode_palette(&form)?.as_deref())?;
+            encode_png(&the_image)
+        })
+        .await;
+
+        match result {
+            Ok(png) => send_callback(&job, callback_url, png).await,
+            Err(err) => tracing::warn!("job {} failed: {}", job, err),
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(AsyncJob { job_id })).into_response())
+}
 
-    let mut colors = BTreeSet::new();
-    for (_, _, color) in the_palette.pixels() {
-        colors.insert([color.0[0], color.0[1], color.0[2], 255]);
+/// Only https urls to hosts in `callback_allowlist`.
+fn callback_url_from(url: &str) -> anyhow::Result<reqwest::Url> {
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return Err(bad_request(format!("invalid callback_url {}", url)));
+    };
+    if parsed.scheme() != "https" {
+        return Err(bad_request(format!("{} is not an https url", url)));
+    }
+    let allowed = parsed.host_str().is_some_and(|host| {
+        CONFIG
+            .callback_allowlist
+            .iter()
+            .flatten()
+            .any(|allowed| allowed == host)
+    });
+    if !allowed {
+        return Err(Rejection(
+            StatusCode::FORBIDDEN,
+            format!("{} is not on the callback allowlist", url),
+        )
+        .into());
     }
-    tracing::debug!("{} colors", colors.len());
+    Ok(parsed)
+}
 
-    for y in 0..the_image.height() {
-        tracing::trace!("row {}", y);
-        for x in 0..the_image.width() {
-            let color = the_image.get_pixel(x, y);
-            let mut min_diff = 255;
-            let mut min_color = [0u8, 0, 0, color.0[3]];
-            for palette_color in colors.iter() {
-                let diff = color.0[0].abs_diff(palette_color[0]) as u64
-                    + color.0[1].abs_diff(palette_color[1]) as u64
-                    + color.0[2].abs_diff(palette_color[2]) as u64;
-                if diff < min_diff {
-                    min_diff = diff;
-                    min_color = *palette_color;
-                }
+/// Post the finished image to the callback, waiting twice as long after each
+/// failed attempt.
+async fn send_callback(job_id: &str, url: reqwest::Url, png: Vec<u8>) {
+    let mut delay = CALLBACK_FIRST_RETRY_DELAY;
+    for attempt in 1..=CALLBACK_ATTEMPTS {
+        let part = reqwest::multipart::Part::bytes(png.clone())
+            .file_name(format!("{}.png", DEFAULT_OUTPUT_FILENAME))
+            .mime_str("image/png")
+            .expect("image/png is a valid mime type");
+        let result = HTTP_CLIENT
+            .post(url.clone())
+            .header("X-Palettizer-Job-Id", job_id)
+            .multipart(reqwest::multipart::Form::new().part("image", part))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => {
+                tracing::debug!("sent job {} to {}", job_id, url);
+                return;
             }
-            min_color[3] = color.0[3];
-            the_image.put_pixel(x, y, *image::Rgba::from_slice(&min_color));
+            Err(err) => tracing::warn!(
+                "attempt {} of sending job {} to {} failed: {}",
+                attempt,
+                job_id,
+                url,
+                err
+            ),
+        }
+
+        if attempt < CALLBACK_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
         }
     }
 
-    let mut data = std::io::Cursor::new(Vec::new());
-    the_image.write_to(&mut data, image::ImageFormat::Png)?;
+    tracing::error!("gave up sending job {} to {}", job_id, url);
+}
 
-    Ok(Response::builder()
-        .header("Content-Type", "image/png")
-        .body(Body::from(data.into_inner()))?
-        .into_response())
+#[utoipa::path(
+    post,
+    path = "/palettize/recolor",
+    request_body(content = api_docs::RecolorForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the recolored and palettized image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_recolor(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_recolor(form).await?)
+}
+
+async fn do_palettize_recolor(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut the_image = input_image(&form)?;
+        let colors = input_palette(&form)?;
+        let rules = recolor_rules_from_form(&form)?;
+        let options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        recolor_image(&mut the_image, &colors, &rules, &options)?;
+        image_response(&the_image, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/watermark",
+    request_body(content = api_docs::WatermarkForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the watermarked and palettized image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_watermark(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_watermark(form).await?)
+}
+
+async fn do_palettize_watermark(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut the_image = input_image(&form)?;
+        let watermark = form
+            .image("watermark")?
+            .ok_or_else(|| bad_request("need a watermark"))?;
+        let position = (
+            form.parse("watermark_x")?.unwrap_or(0),
+            form.parse("watermark_y")?.unwrap_or(0),
+        );
+        let alpha = form.parse("watermark_alpha")?.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(bad_request("watermark_alpha must be between 0 and 1"));
+        }
+
+        overlay_watermark(&mut the_image, &watermark, position, alpha);
+        let stats = process_image(&mut the_image, &form, mode_palette(&form)?.as_deref())?;
+        diff_stats_response(image_response(&the_image, &form)?, stats)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/animation-loop",
+    request_body(content = api_docs::AnimationLoopForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a looping two frame GIF", content_type = "image/gif", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_animation_loop(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_animation_loop(form).await?)
+}
+
+/// Palettize the image with `palette_a`, then make a second frame where the
+/// colors at `swap_entries` are `palette_b`'s instead, like palette cycling
+/// for water or lava.
+async fn do_palettize_animation_loop(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        // swap_entries count entries in the order they're drawn
+        let palette_a = form
+            .ordered_palette("palette_a")?
+            .ok_or_else(|| bad_request("need a palette_a"))?;
+        let palette_b = form
+            .ordered_palette("palette_b")?
+            .ok_or_else(|| bad_request("need a palette_b"))?;
+        if palette_a.len() != palette_b.len() {
+            return Err(bad_request(format!(
+                "palette_a has {} colors but palette_b has {}",
+                palette_a.len(),
+                palette_b.len()
+            )));
+        }
+
+        let swap_entries = match form.text("swap_entries")? {
+            None => (0..palette_a.len()).collect(),
+            Some(entries) => entries
+                .split(',')
+                .map(|entry| entry.trim().parse::<usize>())
+                .collect::<Result<HashSet<_>, _>>()
+                .map_err(|_| bad_request("swap_entries must be comma separated indices"))?,
+        };
+        if let Some(entry) = swap_entries.iter().find(|&&entry| entry >= palette_a.len()) {
+            return Err(bad_request(format!(
+                "swap_entries has {}, but the palettes only have {} colors",
+                entry,
+                palette_a.len()
+            )));
+        }
+
+        // both frames share the GIF's color table
+        let colors = dedup_colors(
+            palette_a
+                .iter()
+                .copied()
+                .chain(swap_entries.iter().map(|&entry| palette_b[entry])),
+        );
+        if colors.len() > 255 {
+            return Err(bad_request(
+                "animated GIFs can use a palette of at most 255 colors",
+            ));
+        }
+
+        let options = options_from_form(&form)?;
+        check_pins(&options, &palette_a)?;
+        let mut frame_a = input_image(&form)?;
+        palettize_image(&mut frame_a, &palette_a, &options)?;
+
+        let swaps = swap_entries
+            .iter()
+            .map(|&entry| (palette_a[entry], palette_b[entry]))
+            .collect::<HashMap<_, _>>();
+        let mut frame_b = frame_a.clone();
+        remap_image(&mut frame_b, &swaps);
+
+        let delays = frame_delays_from_form(&form)?;
+        let data = encode_gif(&[frame_a, frame_b], &delays, &colors, 0)?;
+        file_response(data, "image/gif", "gif", &form)
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct ConstraintsCheck {
+    ok: bool,
+    /// What would be wrong with palettizing this, in the same words the
+    /// rejection would use.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/constraints-check",
+    request_body(content = api_docs::PalettizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = ConstraintsCheck),
+    )
+)]
+async fn palettize_constraints_check(form: Multipart) -> Result<Json<ConstraintsCheck>, AppError> {
+    Ok(do_palettize_constraints_check(form).await?)
+}
+
+/// Check the image, palette, and options the way palettizing would, without
+/// palettizing.
+async fn do_palettize_constraints_check(form: Multipart) -> anyhow::Result<Json<ConstraintsCheck>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut errors = Vec::new();
+        // only problems with the request are collected, anything else is
+        // still an error of its own
+        let mut check = |result: anyhow::Result<()>| match result {
+            Ok(()) => Ok(()),
+            Err(err) => match err.downcast::<Rejection>() {
+                Ok(Rejection(_, text)) => {
+                    errors.push(text);
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            },
+        };
+
+        let colors = input_palette(&form);
+        check(input_image(&form).map(|_| ()))?;
+        check(options_from_form(&form).and_then(|options| match &colors {
+            Ok(colors) => check_pins(&options, colors),
+            Err(_) => Ok(()),
+        }))?;
+        check(colors.map(|_| ()))?;
+
+        Ok(Json(ConstraintsCheck {
+            ok: errors.is_empty(),
+            errors,
+        }))
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct DedupedTileset {
+    /// The palettized image as a base64 PNG.
+    image: String,
+    /// Which unique tile each tile is, by row and then column. Unique tiles
+    /// are numbered in the order they first show up.
+    tile_map: Vec<Vec<usize>>,
+    unique_tiles: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/tileset-dedup",
+    request_body(content = api_docs::TilesetDedupForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = DedupedTileset),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_tileset_dedup(form: Multipart) -> Result<Json<DedupedTileset>, AppError> {
+    Ok(do_palettize_tileset_dedup(form).await?)
+}
+
+/// Palettize a sprite sheet and work out which of its tiles are the same,
+/// since palettizing often makes tiles that were nearly alike identical.
+async fn do_palettize_tileset_dedup(form: Multipart) -> anyhow::Result<Json<DedupedTileset>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        let (tile_width, tile_height) = tile_size_from_form(&form)?;
+        let options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        let mut the_image = input_image(&form)?;
+        palettize_image(&mut the_image, &colors, &options)?;
+        let (tile_map, unique_tiles) = dedup_tiles(&the_image, tile_width, tile_height);
+
+        Ok(Json(DedupedTileset {
+            image: BASE64_STANDARD.encode(encode_png(&the_image)?),
+            tile_map,
+            unique_tiles,
+        }))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/with-annotations",
+    request_body(content = api_docs::WithAnnotationsForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palettized image with each tile's palette index written over it", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_with_annotations(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_with_annotations(form).await?)
+}
+
+async fn do_palettize_with_annotations(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        let options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        let mut the_image = input_image(&form)?;
+        palettize_image(&mut the_image, &colors, &options)?;
+        annotate_indices(&mut the_image, &colors);
+        file_response(encode_png(&the_image)?, "image/png", "png", &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/with-grid",
+    request_body(content = api_docs::WithGridForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palettized image with a grid drawn over it", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_with_grid(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_with_grid(form).await?)
+}
+
+async fn do_palettize_with_grid(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        let grid_width = form
+            .parse::<u32>("grid_width")?
+            .ok_or_else(|| bad_request("need a grid_width"))?;
+        let grid_height = form
+            .parse::<u32>("grid_height")?
+            .ok_or_else(|| bad_request("need a grid_height"))?;
+        if grid_width == 0 || grid_height == 0 {
+            return Err(bad_request("grid_width and grid_height must be at least 1"));
+        }
+        let grid_color = match palette_color_from_form(&form, "grid_color", &colors)? {
+            Some(color) => color,
+            None if colors.contains(&DEFAULT_GRID_COLOR) => DEFAULT_GRID_COLOR,
+            None => {
+                return Err(bad_request(
+                    "need a grid_color, since #FF0000 isn't one of the palette's colors",
+                ))
+            }
+        };
+        let options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        let mut the_image = input_image(&form)?;
+        palettize_image(&mut the_image, &colors, &options)?;
+        draw_grid(&mut the_image, grid_width, grid_height, grid_color);
+        file_response(encode_png(&the_image)?, "image/png", "png", &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/video-frame-sequence",
+    request_body(content = api_docs::VideoFrameSequenceForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a ZIP of the palettized frames", content_type = "application/zip", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_video_frame_sequence(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_video_frame_sequence(form).await?)
+}
+
+/// Palettize `frame_0`, `frame_1`, ... all with the same palette and options,
+/// so colors don't flicker from one frame to the next.
+async fn do_palettize_video_frame_sequence(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        let options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        // the PNGs are already compressed
+        let zip_options =
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut size = None;
+        let mut frames = 0;
+        while let Some(mut frame) = form.image(&format!("frame_{}", frames))? {
+            if *size.get_or_insert(frame.dimensions()) != frame.dimensions() {
+                return Err(bad_request("frames must all be the same size"));
+            }
+
+            palettize_image(&mut frame, &colors, &options)?;
+            zip.start_file(format!("frame_{}.png", frames), zip_options)?;
+            zip.write_all(&encode_png(&frame)?)?;
+            frames += 1;
+        }
+        if frames == 0 {
+            return Err(bad_request("need a frame_0"));
+        }
+
+        file_response(zip.finish()?.into_inner(), "application/zip", "zip", &form)
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct RecolorRuleJson {
+    from: String,
+    tolerance: u64,
+    to: String,
+}
+
+fn recolor_rules_from_form(form: &Form) -> anyhow::Result<Vec<RecolorRule>> {
+    let json = form
+        .text("recolor_rules")?
+        .ok_or_else(|| bad_request("need recolor_rules"))?;
+    let Ok(rules) = serde_json::from_str::<Vec<RecolorRuleJson>>(json) else {
+        return Err(bad_request(
+            "recolor_rules must be an array of {\"from\": hex, \"tolerance\": number, \"to\": hex}",
+        ));
+    };
+
+    rules
+        .into_iter()
+        .map(
+            |RecolorRuleJson {
+                 from,
+                 tolerance,
+                 to,
+             }| {
+                match (parse_hex_color(&from), parse_hex_color(&to)) {
+                    (Some(from), Some(to)) => Ok(RecolorRule {
+                        from,
+                        tolerance,
+                        to,
+                    }),
+                    _ => Err(bad_request(format!(
+                        "invalid color in recolor_rules: {} -> {}",
+                        from, to
+                    ))),
+                }
+            },
+        )
+        .collect()
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/diff",
+    request_body(content = api_docs::DiffForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "how different the two results are", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_diff(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_diff(form).await?)
+}
+
+/// Palettize the image with `palette_a` and `palette_b` and send back how
+/// different the results are.
+async fn do_palettize_diff(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = input_image(&form)?;
+        let palette_a = form
+            .palette("palette_a")?
+            .ok_or_else(|| bad_request("need a palette_a"))?;
+        let palette_b = form
+            .palette("palette_b")?
+            .ok_or_else(|| bad_request("need a palette_b"))?;
+
+        let options = options_from_form(&form)?;
+        check_pins(&options, &palette_a)?;
+        check_pins(&options, &palette_b)?;
+        let mut image_a = the_image.clone();
+        palettize_image(&mut image_a, &palette_a, &options)?;
+        let mut image_b = the_image;
+        palettize_image(&mut image_b, &palette_b, &options)?;
+
+        let mut diff = DynamicImage::new_rgba8(image_a.width(), image_a.height());
+        let mut diff_pixels = 0u64;
+        for (x, y, a) in image_a.pixels() {
+            let b = image_b.get_pixel(x, y);
+            if a != b {
+                diff_pixels += 1;
+            }
+            diff.put_pixel(
+                x,
+                y,
+                Rgba([
+                    a.0[0].abs_diff(b.0[0]),
+                    a.0[1].abs_diff(b.0[1]),
+                    a.0[2].abs_diff(b.0[2]),
+                    255,
+                ]),
+            );
+        }
+
+        let mut response = image_response(&diff, &form)?;
+        response
+            .headers_mut()
+            .insert("x-palettizer-diff-pixels", HeaderValue::from(diff_pixels));
+        Ok(response)
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct MetricReport {
+    metric: &'static str,
+    /// Missing if palettizing changed nothing.
+    psnr: Option<f64>,
+    ssim: f64,
+    changed_pixels: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/compare-metrics",
+    request_body(content = api_docs::OptimizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = Vec<MetricReport>),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_compare_metrics(form: Multipart) -> Result<Json<Vec<MetricReport>>, AppError> {
+    Ok(do_palettize_compare_metrics(form).await?)
+}
+
+/// Palettize the image with each distance and see how close each result is
+/// to the original.
+async fn do_palettize_compare_metrics(form: Multipart) -> anyhow::Result<Json<Vec<MetricReport>>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = input_image(&form)?;
+        let colors = input_palette(&form)?;
+        let mut options = options_from_form(&form)?;
+        check_pins(&options, &colors)?;
+
+        let mut reports = Vec::with_capacity(Distance::ALL.len());
+        for distance in Distance::ALL {
+            options.distance = distance;
+            let mut palettized = the_image.clone();
+            palettize_image(&mut palettized, &colors, &options)?;
+
+            reports.push(MetricReport {
+                metric: distance.name(),
+                psnr: psnr(&the_image, &palettized),
+                ssim: ssim(&the_image, &palettized),
+                changed_pixels: changed_pixels(&the_image, &palettized),
+            });
+        }
+
+        Ok(Json(reports))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palettize/stitch",
+    request_body(content = api_docs::StitchForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the stitched image", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palettize_stitch(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palettize_stitch(form).await?)
+}
+
+/// Put `tile_0_0`, `tile_0_1`, ... back together by row and column. Tiles in
+/// the last row and column may be smaller than the rest, like the ones on the
+/// edges when an image doesn't divide evenly.
+async fn do_palettize_stitch(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let tile_width = form
+            .parse::<u32>("tile_width")?
+            .ok_or_else(|| bad_request("need a tile_width"))?;
+        let tile_height = form
+            .parse::<u32>("tile_height")?
+            .ok_or_else(|| bad_request("need a tile_height"))?;
+        if tile_width == 0 || tile_height == 0 {
+            return Err(bad_request("tile_width and tile_height must be at least 1"));
+        }
+
+        let mut rows = Vec::new();
+        while let Some(first) = form.image(&format!("tile_{}_0", rows.len()))? {
+            let mut row = vec![first];
+            while let Some(tile) = form.image(&format!("tile_{}_{}", rows.len(), row.len()))? {
+                row.push(tile);
+            }
+            rows.push(row);
+        }
+        if rows.is_empty() {
+            return Err(bad_request("need a tile_0_0"));
+        }
+
+        let columns = rows[0].len();
+        for (r, row) in rows.iter().enumerate() {
+            if row.len() != columns {
+                return Err(bad_request(format!(
+                    "row {} has {} tiles, but row 0 has {}",
+                    r,
+                    row.len(),
+                    columns
+                )));
+            }
+
+            for (c, tile) in row.iter().enumerate() {
+                let fits = |size: u32, tile_size: u32, last: bool| {
+                    if last {
+                        size <= tile_size
+                    } else {
+                        size == tile_size
+                    }
+                };
+                if !fits(tile.width(), tile_width, c == columns - 1)
+                    || !fits(tile.height(), tile_height, r == rows.len() - 1)
+                {
+                    return Err(bad_request(format!(
+                        "tile_{}_{} is {}x{}, which doesn't fit a {}x{} tile",
+                        r,
+                        c,
+                        tile.width(),
+                        tile.height(),
+                        tile_width,
+                        tile_height
+                    )));
+                }
+
+                // the edge tiles can be smaller, but they still have to line up
+                // with each other
+                if tile.width() != rows[0][c].width() {
+                    return Err(bad_request(format!(
+                        "tile_{}_{} is {} wide, but tile_0_{} in the same column is {}",
+                        r,
+                        c,
+                        tile.width(),
+                        c,
+                        rows[0][c].width()
+                    )));
+                }
+                if tile.height() != row[0].height() {
+                    return Err(bad_request(format!(
+                        "tile_{}_{} is {} tall, but tile_{}_0 in the same row is {}",
+                        r,
+                        c,
+                        tile.height(),
+                        r,
+                        row[0].height()
+                    )));
+                }
+            }
+        }
+
+        // every tile but the last in each row and column is full sized, and
+        // the edges all match, so the last tile decides the size
+        let last_row = rows.last().expect("there is a row");
+        let width = (columns as u32 - 1) * tile_width + last_row[columns - 1].width();
+        let height = (rows.len() as u32 - 1) * tile_height + last_row[0].height();
+
+        let mut stitched = DynamicImage::new_rgba8(width, height);
+        for (r, row) in rows.iter().enumerate() {
+            for (c, tile) in row.iter().enumerate() {
+                stitched.copy_from(tile, c as u32 * tile_width, r as u32 * tile_height)?;
+            }
+        }
+
+        image_response(&stitched, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/validate-palette",
+    request_body(content = api_docs::PaletteForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = PaletteAudit),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn validate_palette(form: Multipart) -> Result<Json<PaletteAudit>, AppError> {
+    Ok(do_validate_palette(form).await?)
+}
+
+async fn do_validate_palette(form: Multipart) -> anyhow::Result<Json<PaletteAudit>> {
+    let form = Form::read(form).await?;
+    blocking(move || Ok(Json(audit_palette(&input_palette(&form)?)))).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/compare",
+    request_body(content = api_docs::ComparePalettesForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = PaletteComparison),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_compare(form: Multipart) -> Result<Json<PaletteComparison>, AppError> {
+    Ok(do_palette_compare(form).await?)
+}
+
+async fn do_palette_compare(form: Multipart) -> anyhow::Result<Json<PaletteComparison>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let palette_a = form
+            .palette("palette_a")?
+            .ok_or_else(|| bad_request("need a palette_a"))?;
+        let palette_b = form
+            .palette("palette_b")?
+            .ok_or_else(|| bad_request("need a palette_b"))?;
+        Ok(Json(compare_palettes(&palette_a, &palette_b)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/check-contrast",
+    request_body(content = api_docs::CheckContrastForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the pairs of colors with too little contrast", body = Vec<LowContrastPair>),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_check_contrast(form: Multipart) -> Result<Json<Vec<LowContrastPair>>, AppError> {
+    Ok(do_palette_check_contrast(form).await?)
+}
+
+async fn do_palette_check_contrast(form: Multipart) -> anyhow::Result<Json<Vec<LowContrastPair>>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let min_ratio = form.parse::<f64>("min_ratio")?.unwrap_or(4.5);
+        if !(1.0..=21.0).contains(&min_ratio) {
+            return Err(bad_request("min_ratio must be between 1 and 21"));
+        }
+        Ok(Json(low_contrast_pairs(&input_palette(&form)?, min_ratio)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/validate-hardware",
+    request_body(content = api_docs::ValidateHardwareForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "whether the palette meets each of the hardware's limits", body = Vec<ConstraintCheck>),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_validate_hardware(
+    form: Multipart,
+) -> Result<Json<Vec<ConstraintCheck>>, AppError> {
+    Ok(do_palette_validate_hardware(form).await?)
+}
+
+async fn do_palette_validate_hardware(
+    form: Multipart,
+) -> anyhow::Result<Json<Vec<ConstraintCheck>>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let hardware = match form.text("hardware")? {
+            Some("nes") => Hardware::Nes,
+            Some("gameboy") => Hardware::GameBoy,
+            Some("snes") => Hardware::Snes,
+            Some("gba") => Hardware::Gba,
+            Some("nds") => Hardware::Nds,
+            _ => {
+                return Err(bad_request(
+                    "hardware must be nes, gameboy, snes, gba, or nds",
+                ))
+            }
+        };
+        Ok(Json(check_hardware(&input_palette(&form)?, hardware)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/harmony-check",
+    request_body(content = api_docs::PaletteForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, body = HarmonyReport),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_harmony_check(form: Multipart) -> Result<Json<HarmonyReport>, AppError> {
+    Ok(do_palette_harmony_check(form).await?)
+}
+
+async fn do_palette_harmony_check(form: Multipart) -> anyhow::Result<Json<HarmonyReport>> {
+    let form = Form::read(form).await?;
+    blocking(move || Ok(Json(check_harmony(&input_palette(&form)?)))).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/optimize",
+    request_body(content = api_docs::OptimizeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the optimized palette as #RRGGBB colors", body = Vec<String>),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_optimize(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_optimize(form).await?)
+}
+
+/// Swap palette colors for ones from the image to bring the quantization error
+/// down, see [`optimize_palette`].
+async fn do_palette_optimize(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = input_image(&form)?;
+        let colors = input_palette(&form)?;
+
+        let sample = histogram(&optimization_sample(&the_image));
+        let mse_before = mean_squared_error(&sample, &colors);
+        let optimized = optimize_palette(&the_image, colors);
+        let mse_after = mean_squared_error(&sample, &optimized);
+
+        let mut response =
+            Json(optimized.into_iter().map(hex_color).collect::<Vec<_>>()).into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            "x-palettizer-mse-before",
+            HeaderValue::from_str(&format!("{:.2}", mse_before))?,
+        );
+        headers.insert(
+            "x-palettizer-mse-after",
+            HeaderValue::from_str(&format!("{:.2}", mse_after))?,
+        );
+        Ok(response)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/compress",
+    request_body(content = api_docs::CompressForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the compressed palette in return_format", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_compress(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_compress(form).await?)
+}
+
+/// Merge the palette's most alike colors until it's `target_size`, see
+/// [`compress_palette`]. With an `image`, colors more of it palettizes to pull
+/// merged colors toward them.
+async fn do_palette_compress(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        let target = form
+            .parse::<usize>("target_size")?
+            .ok_or_else(|| bad_request("need a target_size"))?;
+        if target == 0 {
+            return Err(bad_request("target_size must be at least 1"));
+        }
+
+        let weights = match form.image("image")? {
+            Some(the_image) => {
+                let sample = histogram(&optimization_sample(&the_image));
+                Some(palette_usage(&sample, &colors, 0).0)
+            }
+            None => None,
+        };
+        let compressed = compress_palette(&colors, weights.as_deref(), target);
+        palette_response(&compressed, &form)
+    })
+    .await
+}
+
+#[derive(Serialize, ToSchema)]
+struct ColorCount {
+    color: String,
+    count: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Histograms {
+    /// The image's most common colors, most common first.
+    image_histogram: Vec<ColorCount>,
+    /// How many pixels palettize to each of the palette's colors, in order.
+    palette_histogram: Option<Vec<ColorCount>>,
+    /// The fraction of pixels less than 10 away from their palette color by L1
+    /// distance.
+    coverage: Option<f64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/histogram",
+    request_body(content = api_docs::HistogramForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "how often colors show up in the image", body = Histograms),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_histogram(form: Multipart) -> Result<Json<Histograms>, AppError> {
+    Ok(do_palette_histogram(form).await?)
+}
+
+/// Count the image's colors, and how well the palette covers them if there is
+/// one.
+async fn do_palette_histogram(form: Multipart) -> anyhow::Result<Json<Histograms>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = input_image(&form)?;
+        let counts = histogram(&the_image);
+
+        let mut image_histogram = counts.iter().collect::<Vec<_>>();
+        image_histogram.sort_by(|(color_a, a), (color_b, b)| b.cmp(a).then(color_a.cmp(color_b)));
+        image_histogram.truncate(HISTOGRAM_COLORS);
+        let image_histogram = image_histogram
+            .into_iter()
+            .map(|(&color, &count)| ColorCount {
+                color: hex_color(color),
+                count,
+            })
+            .collect();
+
+        let has_palette = form.fields.contains_key("palette")
+            || form.fields.contains_key("palette_from_gradient")
+            || form.fields.contains_key("palette_cube");
+        let (palette_histogram, coverage) = if has_palette {
+            let colors = input_palette(&form)?;
+            let (uses, covered) = palette_usage(&counts, &colors, COVERAGE_MAX_ERROR);
+            let pixels = the_image.width() as u64 * the_image.height() as u64;
+            let palette_histogram = colors
+                .iter()
+                .zip(uses)
+                .map(|(&color, count)| ColorCount {
+                    color: hex_color(color),
+                    count,
+                })
+                .collect();
+            (
+                Some(palette_histogram),
+                Some(covered as f64 / pixels.max(1) as f64),
+            )
+        } else {
+            (None, None)
+        };
+
+        Ok(Json(Histograms {
+            image_histogram,
+            palette_histogram,
+            coverage,
+        }))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/merge",
+    request_body(content = api_docs::MergeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "every color of the palettes, in return_format", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_merge(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_merge(form).await?)
+}
+
+/// Every color in `palette_0`, `palette_1`, ... once, in the order they first
+/// show up, or at most `max_colors` picked from them by median cut.
+async fn do_palette_merge(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let merged = dedup_colors(input_palettes(&form)?.into_iter().flatten());
+        let original_size = merged.len();
+
+        let colors = match form.parse::<usize>("max_colors")? {
+            None => merged,
+            Some(0) => return Err(bad_request("max_colors must be at least 1")),
+            Some(max_colors) if max_colors >= original_size => merged,
+            Some(max_colors) => {
+                // each color counts the same, however many palettes have it
+                let histogram = merged.into_iter().map(|color| (color, 1)).collect();
+                dedup_colors(median_cut(&histogram, max_colors))
+            }
+        };
+
+        let mut response = palette_response(&colors, &form)?;
+        response.headers_mut().insert(
+            "x-palettizer-original-size",
+            HeaderValue::from(original_size),
+        );
+        Ok(response)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/from-image",
+    request_body(content = api_docs::FromImageForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palette, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_from_image(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_from_image(form).await?)
+}
+
+/// Pick `n` colors to represent the image.
+async fn do_palette_from_image(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let the_image = input_image(&form)?;
+        let n = form
+            .parse::<usize>("n")?
+            .ok_or_else(|| bad_request("need an n"))?;
+        if !(1..=256).contains(&n) {
+            return Err(bad_request("n must be between 1 and 256"));
+        }
+
+        let colors = match form.text("algorithm")? {
+            None | Some("median_cut") => median_cut(&histogram(&the_image), n),
+            Some("kmeans") => kmeans(&the_image, n),
+            Some(_) => return Err(bad_request("algorithm must be median_cut or kmeans")),
+        };
+
+        palette_response(&dedup_colors(colors), &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/complement",
+    request_body(content = api_docs::ComplementForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the complementary palette, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_complement(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_complement(form).await?)
+}
+
+async fn do_palette_complement(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || palette_response(&complement_palette(&input_palette(&form)?), &form)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/invert",
+    request_body(content = api_docs::InvertForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the inverted palette, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_invert(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_invert(form).await?)
+}
+
+async fn do_palette_invert(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = invert_palette(&input_palette(&form)?);
+        if form.text("output_format")?.is_some() {
+            let format = palette_format_from_form(&form, "output_format")?;
+            return palette_file_response(&colors, format, &form);
+        }
+        palette_response(&colors, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/shift",
+    request_body(content = api_docs::ShiftForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the shifted palette, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_shift(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_shift(form).await?)
+}
+
+async fn do_palette_shift(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let shift = |name: &str| -> anyhow::Result<i16> {
+            let shift = form.parse::<i16>(name)?.unwrap_or(0);
+            if !(-255..=255).contains(&shift) {
+                return Err(bad_request(format!(
+                    "{} must be between -255 and 255",
+                    name
+                )));
+            }
+            Ok(shift)
+        };
+        let (dr, dg, db) = (shift("shift_r")?, shift("shift_g")?, shift("shift_b")?);
+        let wrap = form.flag("wrap", false)?;
+        palette_response(
+            &shift_palette(&input_palette(&form)?, dr, dg, db, wrap),
+            &form,
+        )
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/desaturate",
+    request_body(content = api_docs::DesaturateForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palette turned to one hue, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_desaturate(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_desaturate(form).await?)
+}
+
+async fn do_palette_desaturate(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let hue = form
+            .parse::<f32>("target_hue")?
+            .ok_or_else(|| bad_request("need a target_hue"))?;
+        if !(0.0..=360.0).contains(&hue) {
+            return Err(bad_request("target_hue must be between 0 and 360"));
+        }
+        palette_response(&monochrome_palette(&input_palette(&form)?, hue), &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/sort-by-image",
+    request_body(content = api_docs::SortByImageForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the sorted palette in return_format", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_sort_by_image(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_sort_by_image(form).await?)
+}
+
+/// Put the colors most of the image palettizes to first.
+async fn do_palette_sort_by_image(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut colors = input_palette(&form)?;
+        let the_image = input_image(&form)?;
+        sort_by_frequency(&mut colors, &histogram(&the_image));
+        palette_response(&colors, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/rearrange",
+    request_body(content = api_docs::RearrangeForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the reordered palette in return_format", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_rearrange(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_rearrange(form).await?)
+}
+
+/// Reorder the palette so neighboring entries are alike, for hardware where
+/// that's cheaper to program.
+async fn do_palette_rearrange(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let rearrange = match form.text("strategy")? {
+            Some("gray_code") => Rearrange::GrayCode,
+            Some("tsp") => Rearrange::Tsp,
+            _ => return Err(bad_request("strategy must be gray_code or tsp")),
+        };
+        let mut colors = input_palette(&form)?;
+        rearrange_palette(&mut colors, rearrange);
+        palette_response(&colors, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/sort",
+    request_body(content = api_docs::SortForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the sorted palette as a one pixel tall strip, or in return_format", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_sort(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_sort(form).await?)
+}
+
+async fn do_palette_sort(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut colors = input_palette(&form)?;
+
+        let by_frequency =
+            match (
+                form.text("sort_by")?,
+                form.flag("palette_frequency_sort", false)?,
+            ) {
+                (Some("frequency"), _) | (None, true) => true,
+                (Some(_), true) => return Err(bad_request(
+                    "palette_frequency_sort is sort_by=frequency, so it can't have another sort_by",
+                )),
+                (_, false) => false,
+            };
+        if by_frequency {
+            let the_image = input_image(&form)?;
+            sort_by_frequency(&mut colors, &histogram(&the_image));
+        } else {
+            let sort_by = match form.text("sort_by")? {
+                Some("hue") => SortBy::Hue,
+                Some("luminance") => SortBy::Luminance,
+                Some("saturation") => SortBy::Saturation,
+                Some("r") => SortBy::Red,
+                Some("g") => SortBy::Green,
+                Some("b") => SortBy::Blue,
+                _ => {
+                    return Err(bad_request(
+                        "sort_by must be hue, luminance, saturation, r, g, b, or frequency",
+                    ))
+                }
+            };
+            sort_palette(&mut colors, sort_by);
+        }
+
+        match form.text("return_format")? {
+            None | Some("svg") => {
+                let columns = strip_columns_from_form(&form, &colors)?;
+                image_response(&palette_strip(&colors, columns, 1), &form)
+            }
+            Some(_) => palette_response(&colors, &form),
+        }
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/generate",
+    request_body(content = api_docs::GenerateForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the generated palette, as JSON unless asked for otherwise", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_generate(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_generate(form).await?)
+}
+
+/// Make up a palette of `n` colors from nothing.
+async fn do_palette_generate(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let n = form
+            .parse::<usize>("n")?
+            .ok_or_else(|| bad_request("need an n"))?;
+        if !(2..=256).contains(&n) {
+            return Err(bad_request("n must be between 2 and 256"));
+        }
+
+        let colors = match form.text("method")? {
+            None | Some("oklab_uniform") => oklab_uniform_palette(n),
+            Some(_) => return Err(bad_request("method must be oklab_uniform")),
+        };
+
+        palette_response(&colors, &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/interpolate",
+    request_body(content = api_docs::InterpolateForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a ZIP of palette strips step_0.png, step_1.png, ...", content_type = "application/zip", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_interpolate(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_interpolate(form).await?)
+}
+
+/// Fade `palette_a` into `palette_b` over `n` steps, and send each step back
+/// as a palette strip.
+async fn do_palette_interpolate(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        // colors are paired up in the order they're drawn
+        let palette_a = form
+            .ordered_palette("palette_a")?
+            .ok_or_else(|| bad_request("need a palette_a"))?;
+        let palette_b = form
+            .ordered_palette("palette_b")?
+            .ok_or_else(|| bad_request("need a palette_b"))?;
+        if palette_a.len() != palette_b.len() {
+            return Err(bad_request(format!(
+                "palette_a has {} colors but palette_b has {}",
+                palette_a.len(),
+                palette_b.len()
+            )));
+        }
+
+        let n = form
+            .parse::<usize>("n")?
+            .ok_or_else(|| bad_request("need an n"))?;
+        if !(2..=256).contains(&n) {
+            return Err(bad_request("n must be between 2 and 256"));
+        }
+
+        let steps = interpolate_palettes(&palette_a, &palette_b, n, illuminant_from_form(&form)?);
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        // the PNGs are already compressed
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (i, step) in steps.iter().enumerate() {
+            zip.start_file(format!("step_{}.png", i), options)?;
+            zip.write_all(&encode_png(&palette_strip(step, step.len(), 1))?)?;
+        }
+
+        file_response(zip.finish()?.into_inner(), "application/zip", "zip", &form)
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/to-image",
+    request_body(content = api_docs::ToImageForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palette as a strip", content_type = "image/png", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_to_image(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_to_image(form).await?)
+}
+
+/// Draw the palette as a strip, for palettes that are kept as text.
+async fn do_palette_to_image(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let mut colors = input_palette(&form)?;
+        let height = form.parse("strip_height")?.unwrap_or(1);
+        if !(1..=MAX_STRIP_HEIGHT).contains(&height) {
+            return Err(bad_request(format!(
+                "strip_height must be between 1 and {}",
+                MAX_STRIP_HEIGHT
+            )));
+        }
+
+        // the image palettized by L1 is what the counts are of
+        if form.flag("palette_frequency_sort", false)? {
+            sort_by_frequency(&mut colors, &histogram(&input_image(&form)?));
+        }
+
+        let columns = strip_columns_from_form(&form, &colors)?;
+        image_response(&palette_strip(&colors, columns, height), &form)
+    })
+    .await
+}
+
+/// The colors with the ones the image has the most pixels of first, if the
+/// form asks for `palette_frequency_sort`. The image is the palettized one, so
+/// the counts are of exactly the colors it ended up with.
+fn frequency_sorted(
+    mut colors: Vec<[u8; 3]>,
+    form: &Form,
+    image: &DynamicImage,
+) -> anyhow::Result<Vec<[u8; 3]>> {
+    if form.flag("palette_frequency_sort", false)? {
+        sort_by_frequency(&mut colors, &histogram(image));
+    }
+    Ok(colors)
+}
+
+/// How many colors go in each row of a strip, all of them by default. Rows
+/// wider than the palette would only be padding, so that's as wide as they
+/// get.
+fn strip_columns_from_form(form: &Form, colors: &[[u8; 3]]) -> anyhow::Result<usize> {
+    let all = colors.len().max(1);
+    match form.parse::<usize>("strip_cols")? {
+        None => Ok(all),
+        Some(0) => Err(bad_request("strip_cols must be at least 1")),
+        Some(columns) => Ok(columns.min(all)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/preview-image",
+    request_body(content = api_docs::PreviewImageForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a page of the palette's colors", content_type = "text/html", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_preview_image(form: Multipart) -> Result<Html<String>, AppError> {
+    Ok(do_palette_preview_image(form).await?)
+}
+
+async fn do_palette_preview_image(form: Multipart) -> anyhow::Result<Html<String>> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        Ok(Html(swatch_page(&colors, form.flag("show_names", true)?)))
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/to-aseprite",
+    request_body(content = api_docs::ToAsepriteForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "a blank Aseprite sprite with the palette", content_type = "application/octet-stream", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_to_aseprite(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_to_aseprite(form).await?)
+}
+
+async fn do_palette_to_aseprite(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = input_palette(&form)?;
+        if colors.len() > aseprite::MAX_COLORS {
+            return Err(bad_request(format!(
+                "Aseprite palettes can have at most {} colors",
+                aseprite::MAX_COLORS
+            )));
+        }
+
+        file_response(
+            aseprite_palette(&colors),
+            "application/octet-stream",
+            "aseprite",
+            &form,
+        )
+    })
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/palette/convert",
+    request_body(content = api_docs::ConvertForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the palette in output_format", content_type = "application/json", body = String),
+        (status = 400, description = "a field is missing or invalid", body = String),
+    )
+)]
+async fn palette_convert(form: Multipart) -> Result<Response, AppError> {
+    Ok(do_palette_convert(form).await?)
+}
+
+/// Read a palette in whatever format it's in and write it out in another.
+async fn do_palette_convert(form: Multipart) -> anyhow::Result<Response> {
+    let form = Form::read(form).await?;
+    blocking(move || {
+        let colors = form
+            .palette("palette")?
+            .ok_or_else(|| bad_request("need a palette"))?;
+        let format = palette_format_from_form(&form, "output_format")?;
+        palette_file_response(&colors, format, &form)
+    })
+    .await
+}
+
+/// A form field naming a format to send back a palette in.
+fn palette_format_from_form(form: &Form, name: &str) -> anyhow::Result<PaletteFormat> {
+    match form.text(name)? {
+        None | Some("json") => Ok(PaletteFormat::Json),
+        Some("css_vars") => Ok(PaletteFormat::CssVars),
+        Some("gpl") => Ok(PaletteFormat::Gpl),
+        Some("act") => Ok(PaletteFormat::Act),
+        Some("riff_pal") => Ok(PaletteFormat::RiffPal),
+        Some("hex") => Ok(PaletteFormat::Hex),
+        Some("hex_list") => Ok(PaletteFormat::HexList),
+        Some(_) => Err(bad_request(format!(
+            "{} must be json, css_vars, gpl, act, riff_pal, hex, or hex_list",
+            name
+        ))),
+    }
+}
+
+/// The palette in the form's `return_format`.
+fn palette_response(colors: &[[u8; 3]], form: &Form) -> anyhow::Result<Response> {
+    let format = palette_format_from_form(form, "return_format")?;
+    palette_file_response(colors, format, form)
+}
+
+fn palette_file_response(
+    colors: &[[u8; 3]],
+    format: PaletteFormat,
+    form: &Form,
+) -> anyhow::Result<Response> {
+    file_response(
+        format.serialize(colors)?,
+        format.content_type(),
+        format.extension(),
+        form,
+    )
+}
+
+/// Turn away uploads that obviously aren't images before they get to the
+/// decoder, which has much less helpful errors. Anything that can't be
+/// identified is let through, since not every format the decoder supports has
+/// a signature to check for. Palettes can also be text.
+fn check_upload_type(name: &str, data: &[u8]) -> anyhow::Result<()> {
+    if name != "image" && name != "palette" {
+        return Ok(());
+    }
+
+    let detected_mime = match infer::get(data) {
+        Some(kind) if kind.matcher_type() == infer::MatcherType::Image => return Ok(()),
+        Some(kind) => kind.mime_type(),
+        None if name == "image" && std::str::from_utf8(data).is_ok() => "text/plain",
+        None => return Ok(()),
+    };
+
+    Err(Rejection(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        format!(
+            "uploaded file is of type {}, expected an image",
+            detected_mime
+        ),
+    )
+    .into())
+}
+
+/// The fields of a multipart form, keyed by name.
+struct Form {
+    fields: HashMap<String, Bytes>,
+}
+
+impl Form {
+    async fn read(mut form: Multipart) -> anyhow::Result<Form> {
+        let mut fields = HashMap::new();
+
+        while let Some(part) = form.next_field().await? {
+            let Some(name) = part.name() else {
+                return Err(bad_request("need a name"));
+            };
+            let name = name.to_owned();
+            let bytes = part.bytes().await?;
+
+            tracing::debug!("got {}", name);
+
+            check_upload_type(&name, &bytes)?;
+            fields.insert(name, bytes);
+        }
+
+        Ok(Form { fields })
+    }
+
+    fn image(&self, name: &str) -> anyhow::Result<Option<DynamicImage>> {
+        self.image_with_orientation(name, true)
+    }
+
+    /// Decode an image field, turning it upright according to its EXIF
+    /// orientation if `upright` is set.
+    fn image_with_orientation(
+        &self,
+        name: &str,
+        upright: bool,
+    ) -> anyhow::Result<Option<DynamicImage>> {
+        let Some(data) = self.fields.get(name) else {
+            return Ok(None);
+        };
+        // the decoder would quietly take the first frame, when all of them
+        // were probably meant to be palettized
+        if name == "image" && is_animated_gif(data) {
+            return Err(bad_request(
+                "animated GIFs are not supported for the image field; use /palettize/animation-loop instead",
+            ));
+        }
+        match decode_image(data, upright) {
+            Ok(image) => Ok(Some(image)),
+            Err(_) => Err(bad_request(format!("{} is invalid", name))),
+        }
+    }
+
+    /// A palette image, or a RIFF palette file. The colors of an image come
+    /// out sorted.
+    fn palette(&self, name: &str) -> anyhow::Result<Option<Vec<[u8; 3]>>> {
+        self.read_palette(name, palette_colors)
+    }
+
+    /// Like [`Form::palette`], but the colors of an image stay in the order
+    /// they're drawn in, for fields where which entry is which matters.
+    fn ordered_palette(&self, name: &str) -> anyhow::Result<Option<Vec<[u8; 3]>>> {
+        self.read_palette(name, palette_colors_in_order)
+    }
+
+    fn read_palette(
+        &self,
+        name: &str,
+        image_colors: fn(&DynamicImage) -> Vec<[u8; 3]>,
+    ) -> anyhow::Result<Option<Vec<[u8; 3]>>> {
+        let Some(data) = self.fields.get(name) else {
+            return Ok(None);
+        };
+
+        let colors = if is_riff_pal(data) {
+            match parse_riff_pal(data) {
+                Ok(entries) => dedup_colors(entries.iter().map(|c| [c.0[0], c.0[1], c.0[2]])),
+                Err(err) => return Err(bad_request(format!("{} is invalid: {}", name, err))),
+            }
+        } else {
+            // anything that isn't an image might be a palette written as text
+            match decode_image(data, true) {
+                Ok(palette) => image_colors(&palette),
+                Err(_) => match std::str::from_utf8(data).map(parse_text_palette) {
+                    Ok(Ok(colors)) => dedup_colors(colors),
+                    // color tables have no signature, so they're only tried
+                    // once nothing else fits
+                    _ if is_act(data) => dedup_colors(parse_act(data)?),
+                    Ok(Err(err)) => {
+                        return Err(bad_request(format!("{} is invalid: {}", name, err)))
+                    }
+                    Err(_) => return Err(bad_request(format!("{} is invalid", name))),
+                },
+            }
+        };
+        if colors.is_empty() {
+            return Err(bad_request(format!("{} has no colors", name)));
+        }
+        let min_colors = CONFIG
+            .min_palette_colors
+            .unwrap_or(DEFAULT_MIN_PALETTE_COLORS);
+        if colors.len() < min_colors {
+            return Err(bad_request(format!(
+                "{} must contain at least {} distinct colors",
+                name, min_colors
+            )));
+        }
+        tracing::debug!("{} colors in {}", colors.len(), name);
+
+        Ok(Some(colors))
+    }
+
+    fn text(&self, name: &str) -> anyhow::Result<Option<&str>> {
+        let Some(data) = self.fields.get(name) else {
+            return Ok(None);
+        };
+        match std::str::from_utf8(data) {
+            Ok(text) => Ok(Some(text)),
+            Err(_) => Err(bad_request(format!("{} must be text", name))),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, name: &str) -> anyhow::Result<Option<T>> {
+        let Some(text) = self.text(name)? else {
+            return Ok(None);
+        };
+        match text.trim().parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(bad_request(format!("{} is invalid", name))),
+        }
+    }
+
+    /// A `1` or `0` field.
+    fn flag(&self, name: &str, default: bool) -> anyhow::Result<bool> {
+        match self.text(name)? {
+            None => Ok(default),
+            Some("1") => Ok(true),
+            Some("0") => Ok(false),
+            Some(_) => Err(bad_request(format!("{} must be 1 or 0", name))),
+        }
+    }
+}
+
+/// `palette_0`, `palette_1`, ... up to the configured limit.
+fn input_palettes(form: &Form) -> anyhow::Result<Vec<Vec<[u8; 3]>>> {
+    let max_palettes = CONFIG
+        .max_bulk_palettes
+        .unwrap_or(DEFAULT_MAX_BULK_PALETTES);
+    let mut palettes = Vec::new();
+    while let Some(colors) = form.palette(&format!("palette_{}", palettes.len()))? {
+        if palettes.len() == max_palettes {
+            return Err(bad_request(format!(
+                "at most {} palettes are allowed",
+                max_palettes
+            )));
+        }
+        palettes.push(colors);
+    }
+    if palettes.is_empty() {
+        return Err(bad_request("need a palette_0"));
+    }
+
+    Ok(palettes)
+}
+
+fn input_image(form: &Form) -> anyhow::Result<DynamicImage> {
+    let upright = form.flag("exif_rotate", true)?;
+    let mut image = form
+        .image_with_orientation("image", upright)?
+        .ok_or_else(|| bad_request("need an image"))?;
+
+    let fill = match background_from_form(form)? {
+        Some([r, g, b]) => Rgba([r, g, b, 255]),
+        None => Rgba([0, 0, 0, 0]),
+    };
+    if form.flag("force_square", false)? {
+        image = pad_to_square(&image, fill);
+    }
+    if form.flag("force_tile_align", false)? {
+        let (tile_width, tile_height) = tile_size_from_form(form)?;
+        let width = image.width().div_ceil(tile_width) * tile_width;
+        let height = image.height().div_ceil(tile_height) * tile_height;
+        image = pad_to_size(&image, width, height, fill);
+    }
+
+    Ok(image)
+}
+
+fn tile_size_from_form(form: &Form) -> anyhow::Result<(u32, u32)> {
+    let tile_width = form
+        .parse::<u32>("tile_width")?
+        .ok_or_else(|| bad_request("need a tile_width"))?;
+    let tile_height = form
+        .parse::<u32>("tile_height")?
+        .ok_or_else(|| bad_request("need a tile_height"))?;
+    if tile_width == 0 || tile_height == 0 {
+        return Err(bad_request("tile_width and tile_height must be at least 1"));
+    }
+    Ok((tile_width, tile_height))
+}
+
+/// List the palette colors the palettized image uses, if the form asks for
+/// `optimize_palette`. `colors` is the palette from `mode_palette`, if it was
+/// already read.
+fn used_colors_response(
+    mut response: Response,
+    form: &Form,
+    image: &DynamicImage,
+    colors: Option<&[[u8; 3]]>,
+) -> anyhow::Result<Response> {
+    if form.flag("optimize_palette", false)? {
+        let used = match colors {
+            Some(colors) => used_colors(image, colors),
+            None => used_colors(image, &input_palette(form)?),
+        };
+        let used = frequency_sorted(used, form, image)?;
+        response.headers_mut().insert(
+            "x-palettizer-used-colors",
+            HeaderValue::from_str(
+                &used
+                    .into_iter()
+                    .map(hex_color)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )?,
+        );
+    }
+    Ok(response)
+}
+
+/// Say what size tiles the image was palettized in, or `none` if it was
+/// palettized all at once, so caches can tell responses apart without reading
+/// the request.
+fn tile_config_response(
+    mut response: Response,
+    tile_size: Option<(u32, u32)>,
+) -> anyhow::Result<Response> {
+    let config = match tile_size {
+        Some((width, height)) => format!("{}x{}", width, height),
+        None => String::from("none"),
+    };
+    response
+        .headers_mut()
+        .insert("x-palettizer-tile-config", HeaderValue::from_str(&config)?);
+    Ok(response)
+}
+
+/// Say how big the image was padded to, if it was padded to fit its tiles.
+fn tile_align_response(
+    mut response: Response,
+    form: &Form,
+    image: &DynamicImage,
+) -> anyhow::Result<Response> {
+    if form.flag("force_tile_align", false)? {
+        let headers = response.headers_mut();
+        headers.insert(
+            "x-palettizer-padded-width",
+            HeaderValue::from(image.width()),
+        );
+        headers.insert(
+            "x-palettizer-padded-height",
+            HeaderValue::from(image.height()),
+        );
+    }
+    Ok(response)
+}
+
+fn decode_image(data: &[u8], upright: bool) -> image::ImageResult<DynamicImage> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    if reader.format().is_none() {
+        // some formats, like TGA, have no magic bytes to guess from, so see if
+        // anything can make sense of it
+        let format = image::ImageFormat::all()
+            .filter(|format| format.reading_enabled())
+            .find(|&format| {
+                ImageReader::with_format(Cursor::new(data), format)
+                    .into_decoder()
+                    .is_ok()
+            });
+        if let Some(format) = format {
+            reader.set_format(format);
+        }
+    }
+
+    let mut decoder = reader.into_decoder()?;
+    // image doesn't look at EXIF on its own, so phone pictures come out sideways
+    let orientation = decoder.orientation()?;
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    if upright {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(image)
+}
+
+fn input_palette(form: &Form) -> anyhow::Result<Vec<[u8; 3]>> {
+    if let Some(steps) = form.parse::<u8>("palette_cube")? {
+        if !(1..=8).contains(&steps) {
+            return Err(bad_request("palette_cube must be between 1 and 8"));
+        }
+        return Ok(cube_palette(steps));
+    }
+
+    if let Some(gradient) = form.text("palette_from_gradient")? {
+        return gradient_from_form(form, gradient);
+    }
+
+    form.palette("palette")?
+        .ok_or_else(|| bad_request("need a palette"))
+}
+
+fn gradient_from_form(form: &Form, gradient: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let Some((start, end, count)) = parse_gradient(gradient) else {
+        return Err(bad_request(
+            "palette_from_gradient must look like #RRGGBB-#RRGGBB:N",
+        ));
+    };
+    if !(2..=256).contains(&count) {
+        return Err(bad_request(
+            "palette_from_gradient needs between 2 and 256 colors",
+        ));
+    }
+
+    let space = match form.text("gradient_space")? {
+        None | Some("srgb") => GradientSpace::Srgb,
+        Some("lab") => GradientSpace::Lab(illuminant_from_form(form)?),
+        Some(_) => return Err(bad_request("gradient_space must be srgb or lab")),
+    };
+
+    Ok(gradient_palette(start, end, count, space))
+}
+
+fn illuminant_from_form(form: &Form) -> anyhow::Result<Illuminant> {
+    match form.text("lab_illuminant")? {
+        None | Some("d65") => Ok(Illuminant::D65),
+        Some("d50") => Ok(Illuminant::D50),
+        Some(_) => Err(bad_request("lab_illuminant must be d50 or d65")),
+    }
+}
+
+/// The palette the form's mode palettizes to, if it has one, for passing to
+/// `process_image`.
+fn mode_palette(form: &Form) -> anyhow::Result<Option<Vec<[u8; 3]>>> {
+    match form.text("mode")? {
+        None | Some("palettize") | Some("flood_fill_palette") => Ok(Some(input_palette(form)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Palettize or remap the image as the form asks, to `colors` from
+/// `mode_palette`. Only palettizing has diff stats.
+fn process_image(
+    image: &mut DynamicImage,
+    form: &Form,
+    colors: Option<&[[u8; 3]]>,
+) -> anyhow::Result<Option<DiffStats>> {
+    let palette = || colors.ok_or_else(|| anyhow!("mode_palette didn't read a palette"));
+    match form.text("mode")? {
+        None | Some("palettize") => {
+            let colors = palette()?;
+            let shadow = palette_color_from_form(form, "shadow_color", colors)?;
+            let shadow_offset = (
+                form.parse("shadow_offset_x")?.unwrap_or(1),
+                form.parse("shadow_offset_y")?.unwrap_or(1),
+            );
+            let outline = palette_color_from_form(form, "outline_color", colors)?;
+            let transparent = transparent_color_from_form(form, colors)?;
+
+            let options = options_from_form(form)?;
+            check_pins(&options, colors)?;
+            let strip_unused = form.flag("strip_unused", false)?;
+            if strip_unused && !form.flag("optimize_palette", false)? {
+                return Err(bad_request("strip_unused needs optimize_palette"));
+            }
+
+            let original = strip_unused.then(|| image.clone());
+            let mut stats = palettize_image(image, colors, &options)?;
+            if let Some(original) = original {
+                // pixels whose nearest color went unused aren't affected by
+                // losing it, but dithering might still come out differently
+                let used = used_colors(image, colors);
+                check_pins(&options, &used)?;
+                *image = original;
+                stats = palettize_image(image, &used, &options)?;
+            }
+            if let Some(color) = transparent {
+                make_transparent(image, color);
+            }
+            if let Some(color) = shadow {
+                add_drop_shadow(image, color, shadow_offset);
+            }
+            if let Some(color) = outline {
+                add_outline(image, color);
+            }
+            Ok(Some(stats))
+        }
+
+        Some("remap") => {
+            let color_map = input_color_map(form)?;
+            remap_image(image, &color_map);
+            Ok(None)
+        }
+
+        Some("flood_fill_palette") => {
+            let colors = palette()?;
+            let options = options_from_form(form)?;
+            check_pins(&options, colors)?;
+            let seeds = seeds_from_form(form, image)?;
+            let tolerance = form.parse("fill_tolerance")?.unwrap_or(0);
+
+            // palettizing the whole thing keeps dithering the same as it
+            // would be otherwise, then only the filled pixels are kept
+            let mask = flood_fill_mask(image, &seeds, tolerance);
+            let mut palettized = image.clone();
+            palettize_image(&mut palettized, colors, &options)?;
+            for (i, (x, y, color)) in palettized.pixels().enumerate() {
+                if mask[i] {
+                    image.put_pixel(x, y, color);
+                }
+            }
+            Ok(None)
+        }
+
+        Some(_) => Err(bad_request(
+            "mode must be palettize, remap, or flood_fill_palette",
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct Seed {
+    x: u32,
+    y: u32,
+}
+
+/// The `seed_pixels` to flood fill from, which have to be inside the image.
+fn seeds_from_form(form: &Form, image: &DynamicImage) -> anyhow::Result<Vec<(u32, u32)>> {
+    let Some(json) = form.text("seed_pixels")? else {
+        return Err(bad_request("flood_fill_palette needs seed_pixels"));
+    };
+    let Ok(seeds) = serde_json::from_str::<Vec<Seed>>(json) else {
+        return Err(bad_request(
+            "seed_pixels must be an array of {\"x\": x, \"y\": y}",
+        ));
+    };
+    if let Some(seed) = seeds
+        .iter()
+        .find(|seed| seed.x >= image.width() || seed.y >= image.height())
+    {
+        return Err(bad_request(format!(
+            "seed ({}, {}) is outside the {}x{} image",
+            seed.x,
+            seed.y,
+            image.width(),
+            image.height()
+        )));
+    }
+    Ok(seeds.into_iter().map(|seed| (seed.x, seed.y)).collect())
+}
+
+/// The palette color `transparency_mode` says to make transparent.
+fn transparent_color_from_form(form: &Form, colors: &[[u8; 3]]) -> anyhow::Result<Option<[u8; 3]>> {
+    match form.text("transparency_mode")? {
+        None => Ok(None),
+        // palettes are lists of distinct colors, so these are the same
+        Some("index_0") | Some("first_color") => Ok(Some(colors[0])),
+        Some("explicit") => match palette_color_from_form(form, "transparent_color", colors)? {
+            Some(color) => Ok(Some(color)),
+            None => Err(bad_request(
+                "transparency_mode=explicit needs a transparent_color",
+            )),
+        },
+        Some(_) => Err(bad_request(
+            "transparency_mode must be index_0, first_color, or explicit",
+        )),
+    }
+}
+
+/// A `#RRGGBB` field which has to be one of the palette's colors, for drawing
+/// on top of the palettized image without adding colors to it.
+fn palette_color_from_form(
+    form: &Form,
+    name: &str,
+    colors: &[[u8; 3]],
+) -> anyhow::Result<Option<[u8; 3]>> {
+    let Some(text) = form.text(name)? else {
+        return Ok(None);
+    };
+    let Some(color) = parse_hex_color(text) else {
+        return Err(bad_request(format!("{} must look like #RRGGBB", name)));
+    };
+    if !colors.contains(&color) {
+        return Err(bad_request(format!(
+            "{} must be one of the palette's colors",
+            name
+        )));
+    }
+    Ok(Some(color))
+}
+
+fn options_from_form(form: &Form) -> anyhow::Result<Options> {
+    let simulate = match form.text("simulate")? {
+        None => None,
+        Some("protanopia") => Some(ColorBlindness::Protanopia),
+        Some("deuteranopia") => Some(ColorBlindness::Deuteranopia),
+        Some("tritanopia") => Some(ColorBlindness::Tritanopia),
+        Some(_) => {
+            return Err(bad_request(
+                "simulate must be protanopia, deuteranopia, or tritanopia",
+            ))
+        }
+    };
+
+    let quantize_bits = form.parse("quantize_bits")?.unwrap_or(8);
+    if !(1..=8).contains(&quantize_bits) {
+        return Err(bad_request("quantize_bits must be between 1 and 8"));
+    }
+
+    let hue_rotate = form.parse("hue_rotate")?.unwrap_or(0.0);
+    if !(0.0..=360.0).contains(&hue_rotate) {
+        return Err(bad_request("hue_rotate must be between 0 and 360"));
+    }
+
+    let saturation = form.parse("saturation")?.unwrap_or(1.0);
+    if !(0.0..=5.0).contains(&saturation) {
+        return Err(bad_request("saturation must be between 0 and 5"));
+    }
+
+    let color_temp = match form.parse::<u32>("color_temp")? {
+        None => None,
+        Some(kelvin) if (1000..=12000).contains(&kelvin) => {
+            Some(color::color_temperature_multipliers(kelvin))
+        }
+        Some(_) => return Err(bad_request("color_temp must be between 1000 and 12000")),
+    };
+
+    // Still check background_color, since it pads too.
+    let background = background_from_form(form)?;
+    let background = if form.flag("skip_alpha_composite", false)? {
+        None
+    } else {
+        background
+    };
+
+    let distance = match form.text("distance")? {
+        None | Some("l1") => Distance::L1,
+        Some("cie76") => Distance::Cie76,
+        Some("ciede2000") => Distance::Ciede2000,
+        Some("minkowski") => {
+            let p = form.parse::<f32>("minkowski_p")?.unwrap_or(2.0);
+            if p.is_nan() || p < 1.0 {
+                return Err(bad_request("minkowski_p must be at least 1"));
+            }
+            Distance::Minkowski(p)
+        }
+        Some(_) => {
+            return Err(bad_request(
+                "distance must be l1, cie76, ciede2000, or minkowski",
+            ))
+        }
+    };
+
+    let dither = match form.text("dither")? {
+        None | Some("none") => None,
+        Some("floyd_steinberg") => Some(Dither::FloydSteinberg),
+        Some("stucki") => Some(Dither::Stucki),
+        Some(_) => {
+            return Err(bad_request(
+                "dither must be none, floyd_steinberg, or stucki",
+            ))
+        }
+    };
+    let channels = match form.text("channels")? {
+        None | Some("rgb" | "rgb_only") => Channels::Rgb,
+        Some("alpha_only") => Channels::AlphaOnly,
+        Some("all") => Channels::All,
+        Some(_) => {
+            return Err(bad_request(
+                "channels must be rgb, rgb_only, alpha_only, or all",
+            ))
+        }
+    };
+
+    let per_channel_dither = form.flag("per_channel_dither", false)?;
+    if per_channel_dither && dither.is_some() {
+        return Err(bad_request("per_channel_dither can't be used with dither"));
+    }
+    if channels == Channels::All && (dither.is_some() || per_channel_dither) {
+        return Err(bad_request("channels=all can't be dithered"));
+    }
+
+    let alpha_levels = if form.flag("alpha_dither", false)? {
+        if dither.is_none() {
+            return Err(bad_request("alpha_dither needs dither"));
+        }
+        if channels != Channels::Rgb {
+            return Err(bad_request("alpha_dither only works with channels=rgb"));
+        }
+        Some(alpha_levels_from_form(form)?)
+    } else {
+        None
+    };
+
+    Ok(Options {
+        color_temp,
+        hue_rotate,
+        saturation,
+        distance,
+        illuminant: illuminant_from_form(form)?,
+        lab_clamp: form.flag("lab_clamp", false)?,
+        min_diff: form.parse("min_diff")?.unwrap_or(0),
+        simulate,
+        quantize_bits,
+        dither,
+        serpentine: form.flag("serpentine", false)?,
+        alpha_levels,
+        per_channel_dither,
+        exact_match_passthrough: form.flag("exact_match_passthrough", false)?,
+        smooth_noise: form.flag("smooth_noise", false)?,
+        background,
+        row_timeout: Duration::from_millis(CONFIG.row_timeout_ms.unwrap_or(DEFAULT_ROW_TIMEOUT_MS)),
+        pins: pins_from_form(form)?,
+        channels,
+    })
+}
+
+fn alpha_levels_from_form(form: &Form) -> anyhow::Result<Vec<u8>> {
+    let Some(levels) = form.text("alpha_levels")? else {
+        return Err(bad_request("alpha_dither needs alpha_levels"));
+    };
+    let mut levels = levels
+        .split(',')
+        .map(|level| level.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad_request("alpha_levels must be comma separated numbers from 0 to 255"))?;
+    levels.sort_unstable();
+    levels.dedup();
+    Ok(levels)
+}
+
+#[derive(Deserialize)]
+struct Pin {
+    input: String,
+    output: String,
+}
+
+fn pins_from_form(form: &Form) -> anyhow::Result<HashMap<[u8; 3], [u8; 3]>> {
+    let Some(json) = form.text("pin_colors")? else {
+        return Ok(HashMap::new());
+    };
+    let Ok(pins) = serde_json::from_str::<Vec<Pin>>(json) else {
+        return Err(bad_request(
+            "pin_colors must be an array of {\"input\": hex, \"output\": hex}",
+        ));
+    };
+
+    let mut pinned = HashMap::new();
+    for Pin { input, output } in pins {
+        let (Some(input_color), Some(output_color)) =
+            (parse_hex_color(&input), parse_hex_color(&output))
+        else {
+            return Err(bad_request(format!(
+                "invalid color in pin_colors: {} -> {}",
+                input, output
+            )));
+        };
+        pinned.insert(input_color, output_color);
+    }
+
+    Ok(pinned)
+}
+
+/// Pinned colors have to come out as one of the palette's colors, like
+/// everything else.
+fn check_pins(options: &Options, colors: &[[u8; 3]]) -> anyhow::Result<()> {
+    match options
+        .pins
+        .values()
+        .find(|output| !colors.contains(output))
+    {
+        Some(&output) => Err(bad_request(format!(
+            "{} is pinned to but isn't one of the palette's colors",
+            hex_color(output)
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn background_from_form(form: &Form) -> anyhow::Result<Option<[u8; 3]>> {
+    let Some(color) = form.text("background_color")? else {
+        return Ok(None);
+    };
+    parse_hex_color(color)
+        .map(Some)
+        .ok_or_else(|| bad_request("background_color must look like #RRGGBB"))
+}
+
+/// Tiles on the DS are always this big.
+static NDS_TILE_SIZE: u32 = 8;
+
+#[derive(Serialize, ToSchema)]
+struct NdsImage {
+    /// Colors 1 to 15 of each subpalette. Color 0 is always transparent.
+    subpalettes: Vec<Vec<String>>,
+    /// Which subpalette each tile got, by row and then column.
+    tile_assignments: Vec<Vec<usize>>,
+    /// The palettized image as a base64 PNG.
+    image: String,
+}
+
+/// Palettize the image the way it would have to look on a Nintendo DS: the
+/// palette split into 16 subpalettes of 15 colors, one per 8x8 tile, and every
+/// pixel either opaque or transparent.
+fn nds_response(form: &Form) -> anyhow::Result<Response> {
+    let colors = input_palette(form)?;
+    let Some(subpalettes) = nds_subpalettes(&colors) else {
+        return Err(bad_request(format!(
+            "hardware=nds palettes may have at most {} colors, this one has {}",
+            NDS_SUBPALETTES * NDS_SUBPALETTE_COLORS,
+            colors.len()
+        )));
+    };
+
+    let options = options_from_form(form)?;
+    check_pins(&options, &colors)?;
+
+    let mut the_image = input_image(form)?;
+    let tile_assignments = palettize_tiles(
+        &mut the_image,
+        &subpalettes,
+        NDS_TILE_SIZE,
+        NDS_TILE_SIZE,
+        &options,
+    );
+    for y in 0..the_image.height() {
+        for x in 0..the_image.width() {
+            let mut color = the_image.get_pixel(x, y);
+            if color.0[3] != 0 {
+                color.0[3] = 255;
+                the_image.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    let response = Json(NdsImage {
+        subpalettes: subpalettes
+            .iter()
+            .map(|colors| colors.iter().copied().map(hex_color).collect())
+            .collect(),
+        tile_assignments,
+        image: BASE64_STANDARD.encode(encode_png(&the_image)?),
+    })
+    .into_response();
+    tile_config_response(response, Some((NDS_TILE_SIZE, NDS_TILE_SIZE)))
+}
+
+/// The biggest region `mode=nearest_n` looks at, on each side.
+static NEAREST_N_MAX_ROI: u32 = 10;
+
+#[derive(Serialize)]
+struct Candidate {
+    color: String,
+    dist: f32,
+}
+
+#[derive(Serialize)]
+struct PixelCandidates {
+    x: u32,
+    y: u32,
+    input: String,
+    candidates: Vec<Candidate>,
+}
+
+/// The `n` nearest palette colors of each pixel in a small region, as JSON,
+/// for working out why a pixel got the color it did.
+fn nearest_n_response(form: &Form) -> anyhow::Result<Response> {
+    let colors = input_palette(form)?;
+    let options = options_from_form(form)?;
+    let the_image = input_image(form)?;
+
+    let n = form.parse::<usize>("n")?.unwrap_or(3);
+    if n == 0 {
+        return Err(bad_request("n must be at least 1"));
+    }
+
+    let roi_x = form.parse("roi_x")?.unwrap_or(0);
+    let roi_y = form.parse("roi_y")?.unwrap_or(0);
+    let roi_w = form.parse("roi_w")?.unwrap_or(NEAREST_N_MAX_ROI);
+    let roi_h = form.parse("roi_h")?.unwrap_or(NEAREST_N_MAX_ROI);
+    if !(1..=NEAREST_N_MAX_ROI).contains(&roi_w) || !(1..=NEAREST_N_MAX_ROI).contains(&roi_h) {
+        return Err(bad_request(format!(
+            "roi_w and roi_h must be between 1 and {}",
+            NEAREST_N_MAX_ROI
+        )));
+    }
+    if roi_x.saturating_add(roi_w) > the_image.width()
+        || roi_y.saturating_add(roi_h) > the_image.height()
+    {
+        return Err(bad_request(format!(
+            "the region must be inside the {}x{} image",
+            the_image.width(),
+            the_image.height()
+        )));
+    }
+
+    let candidates = nearest_candidates(
+        &the_image,
+        &colors,
+        &options,
+        (roi_x, roi_y, roi_w, roi_h),
+        n,
+    );
+    Ok(Json(
+        candidates
+            .into_iter()
+            .map(|pixel| PixelCandidates {
+                x: pixel.x,
+                y: pixel.y,
+                input: hex_color(pixel.input),
+                candidates: pixel
+                    .nearest
+                    .into_iter()
+                    .map(|(color, dist)| Candidate {
+                        color: hex_color(color),
+                        dist,
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>(),
+    )
+    .into_response())
+}
+
+/// Palettize each tile with the best fitting of `palette_0`, `palette_1`, ...
+/// and say which one each tile got in a header.
+fn multi_palette_response(form: &Form) -> anyhow::Result<Response> {
+    let palettes = input_palettes(form)?;
+    let (tile_width, tile_height) = tile_size_from_form(form)?;
+
+    // every tile has to be able to use a pinned color, whichever palette it
+    // gets
+    let options = options_from_form(form)?;
+    for colors in &palettes {
+        check_pins(&options, colors)?;
+    }
+
+    let mut the_image = input_image(form)?;
+    let assignments = palettize_tiles(&mut the_image, &palettes, tile_width, tile_height, &options);
+
+    let mut response = image_response(&the_image, form)?;
+    response.headers_mut().insert(
+        "x-palettizer-tile-assignments",
+        HeaderValue::from_str(&serde_json::to_string(&assignments)?)?,
+    );
+    let response = tile_config_response(response, Some((tile_width, tile_height)))?;
+    tile_align_response(response, form, &the_image)
+}
+
+/// Palettize `frame_0`, `frame_1`, ... and put them together as an animated
+/// GIF, with the palette as its global color table.
+fn animated_gif_response(form: &Form) -> anyhow::Result<Response> {
+    let colors = input_palette(form)?;
+    if colors.len() > 255 {
+        return Err(bad_request(
+            "animated GIFs can use a palette of at most 255 colors",
+        ));
+    }
+    let options = options_from_form(form)?;
+    check_pins(&options, &colors)?;
+
+    let mut frames = Vec::new();
+    while let Some(mut frame) = form.image(&format!("frame_{}", frames.len()))? {
+        palettize_image(&mut frame, &colors, &options)?;
+        frames.push(frame);
+    }
+    if frames.is_empty() {
+        return Err(bad_request("need a frame_0"));
+    }
+
+    let delays = frame_delays_from_form(form)?;
+    let loop_count = form.parse("loop_count")?.unwrap_or(0);
+
+    let data = encode_gif(&frames, &delays, &colors, loop_count)?;
+    file_response(data, "image/gif", "gif", form)
+}
+
+fn frame_delays_from_form(form: &Form) -> anyhow::Result<Vec<u32>> {
+    match form.text("frame_delays")? {
+        None => Ok(Vec::new()),
+        Some(delays) => delays
+            .split(',')
+            .map(|delay| delay.trim().parse())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| bad_request("frame_delays must be comma separated milliseconds")),
+    }
+}
+
+/// Parse `color_map_json`, an object mapping `#RRGGBB` colors to their
+/// replacements.
+fn input_color_map(form: &Form) -> anyhow::Result<HashMap<[u8; 3], [u8; 3]>> {
+    let json = form
+        .text("color_map_json")?
+        .ok_or_else(|| bad_request("need a color_map_json"))?;
+    let Ok(map) = serde_json::from_str::<HashMap<String, String>>(json) else {
+        return Err(bad_request(
+            "color_map_json must be an object of hex colors",
+        ));
+    };
+
+    let mut color_map = HashMap::new();
+    for (from, to) in map {
+        let (Some(from_color), Some(to_color)) = (parse_hex_color(&from), parse_hex_color(&to))
+        else {
+            return Err(bad_request(format!(
+                "invalid color in color_map_json: {} -> {}",
+                from, to
+            )));
+        };
+        color_map.insert(from_color, to_color);
+    }
+
+    Ok(color_map)
+}
+
+/// The name to suggest for the downloaded file, without an extension.
+fn output_filename(form: &Form) -> anyhow::Result<String> {
+    let Some(name) = form.text("output_filename")? else {
+        return Ok(String::from(DEFAULT_OUTPUT_FILENAME));
+    };
+    if name.chars().count() > 255 {
+        return Err(bad_request(
+            "output_filename must be at most 255 characters",
+        ));
+    }
+
+    // also keeps path separators and quotes out of the header
+    let name = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect::<String>();
+    if name.is_empty() {
+        Ok(String::from(DEFAULT_OUTPUT_FILENAME))
+    } else {
+        Ok(name)
+    }
+}
+
+fn encode_png(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, image::ImageFormat::Png)?;
+    Ok(data.into_inner())
+}
+
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Png,
+    WebpLossless,
+}
+
+impl OutputFormat {
+    fn from_form(form: &Form) -> anyhow::Result<OutputFormat> {
+        match form.text("output_format")? {
+            None | Some("png") => Ok(OutputFormat::Png),
+            Some("webp_lossless") => Ok(OutputFormat::WebpLossless),
+            Some("webp") => Err(bad_request(
+                "lossy webp is not supported, use webp_lossless",
+            )),
+            Some(_) => Err(bad_request("output_format must be png or webp_lossless")),
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::WebpLossless => "image/webp",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::WebpLossless => "webp",
+        }
+    }
+
+    fn encode(self, image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+        match self {
+            OutputFormat::Png => encode_png(image),
+            OutputFormat::WebpLossless => {
+                let mut data = Vec::new();
+                image
+                    .to_rgba8()
+                    .write_with_encoder(WebPEncoder::new_lossless(&mut data))?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Encode the image however the form asks for it.
+fn image_response(image: &DynamicImage, form: &Form) -> anyhow::Result<Response> {
+    match form.text("return_format")? {
+        None => {}
+        Some("svg") => {
+            let pixel_size = form.parse("svg_pixel_size")?.unwrap_or(1);
+            if pixel_size == 0 {
+                return Err(bad_request("svg_pixel_size must be at least 1"));
+            }
+            // every coordinate is at most these
+            if image.width().checked_mul(pixel_size).is_none()
+                || image.height().checked_mul(pixel_size).is_none()
+            {
+                return Err(bad_request(
+                    "svg_pixel_size is too big for an image this size",
+                ));
+            }
+            let group_by_color = form.flag("svg_group_by_color", false)?;
+
+            let svg = image_to_svg(image, pixel_size, group_by_color);
+            return file_response(svg.into_bytes(), "image/svg+xml", "svg", form);
+        }
+        Some("sprite_atlas_json") => {
+            let sprites = sprite_atlas(image, form)?;
+            return file_response(
+                serde_json::to_vec(&sprites)?,
+                "application/json",
+                "json",
+                form,
+            );
+        }
+        Some(_) => {
+            return Err(bad_request(
+                "return_format must be svg or sprite_atlas_json",
+            ))
+        }
+    }
+
+    let format = OutputFormat::from_form(form)?;
+    file_response(
+        format.encode(image)?,
+        format.content_type(),
+        format.extension(),
+        form,
+    )
+}
+
+#[derive(Serialize)]
+struct Sprite {
+    id: String,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    dominant_color: String,
+    color_count: usize,
+}
+
+/// Cut the image into `sprite_w` by `sprite_h` sprites, by row and then
+/// column. Sprites on the right and bottom edges may be smaller.
+fn sprite_atlas(image: &DynamicImage, form: &Form) -> anyhow::Result<Vec<Sprite>> {
+    let sprite_width = form
+        .parse::<u32>("sprite_w")?
+        .ok_or_else(|| bad_request("need a sprite_w"))?;
+    let sprite_height = form
+        .parse::<u32>("sprite_h")?
+        .ok_or_else(|| bad_request("need a sprite_h"))?;
+    if sprite_width == 0 || sprite_height == 0 {
+        return Err(bad_request("sprite_w and sprite_h must be at least 1"));
+    }
+
+    let mut sprites = Vec::new();
+    for (row, y) in (0..image.height())
+        .step_by(sprite_height as usize)
+        .enumerate()
+    {
+        let h = sprite_height.min(image.height() - y);
+        for (column, x) in (0..image.width())
+            .step_by(sprite_width as usize)
+            .enumerate()
+        {
+            let w = sprite_width.min(image.width() - x);
+            let (dominant, color_count) = region_colors(image, x, y, w, h);
+            sprites.push(Sprite {
+                id: format!("sprite_{}_{}", row, column),
+                x,
+                y,
+                w,
+                h,
+                dominant_color: hex_color(dominant),
+                color_count,
+            });
+        }
+    }
+
+    Ok(sprites)
+}
+
+/// Send back a file, named after the form's `output_filename`.
+fn file_response(
+    data: impl Into<Body>,
+    content_type: &str,
+    extension: &str,
+    form: &Form,
+) -> anyhow::Result<Response> {
+    let filename = output_filename(form)?;
+
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", filename, extension),
+        )
+        .body(data.into())?
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{to_bytes, HttpBody as _},
+        http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, COOKIE, SET_COOKIE},
+    };
+    use tower::ServiceExt as _;
+
+    static BOUNDARY: &str = "palettizer-test";
+
+    /// The app with its templates loaded, the way main sets it up.
+    fn test_app() -> Router {
+        TEMPLATES.get_or_init(|| load_templates(&CONFIG).expect("the example templates load"));
+        app()
+    }
+
+    /// A `multipart/form-data` POST with a part per `(name, content type,
+    /// data)`.
+    fn form_request(uri: &str, fields: &[(&str, &str, &[u8])]) -> Request {
+        let mut body = Vec::new();
+        for (name, content_type, data) in fields {
+            write!(
+                body,
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                BOUNDARY, name, name, content_type
+            )
+            .unwrap();
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        write!(body, "--{}--\r\n", BOUNDARY).unwrap();
+
+        axum::http::Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn get_request(uri: &str) -> Request {
+        axum::http::Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn send(request: Request) -> Response {
+        test_app().oneshot(request).await.unwrap()
+    }
+
+    async fn body_bytes(response: Response) -> Bytes {
+        to_bytes(response.into_body(), usize::MAX).await.unwrap()
+    }
+
+    /// A PNG palette strip of the colors.
+    fn palette_png(colors: &[[u8; 3]]) -> Vec<u8> {
+        encode_png(&palette_strip(colors, colors.len(), 1)).unwrap()
+    }
+
+    static BLACK_AND_WHITE: &[[u8; 3]] = &[[0, 0, 0], [255, 255, 255]];
+
+    #[tokio::test]
+    async fn preview_is_the_center_200x200() {
+        let image = encode_png(&DynamicImage::new_rgb8(1000, 1000)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_PREVIEW_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let preview = image::load_from_memory(&body_bytes(response).await).unwrap();
+        assert_eq!(preview.dimensions(), (PREVIEW_SIZE, PREVIEW_SIZE));
+    }
+
+    #[tokio::test]
+    async fn timed_headers_add_up_to_the_total() {
+        let image = encode_png(&DynamicImage::new_rgb8(64, 64)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_TIMED_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let milliseconds = |name: &str| -> f64 {
+            response.headers()[name]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap_or_else(|_| panic!("{} is a number", name))
+        };
+        let parts = [
+            "x-palettizer-decode-ms",
+            "x-palettizer-palette-extract-ms",
+            "x-palettizer-map-ms",
+            "x-palettizer-encode-ms",
+        ]
+        .map(milliseconds)
+        .iter()
+        .sum::<f64>();
+        let total = milliseconds("x-palettizer-total-ms");
+        // each is rounded to a microsecond, and picking the mode isn't timed
+        assert!(parts <= total + 0.01, "{} vs {}", parts, total);
+        assert!(total - parts < 5.0, "{} vs {}", parts, total);
+    }
+
+    /// The alpha of a black pixel and a white one palettized with these
+    /// extra fields.
+    async fn black_and_white_alpha(fields: &[(&str, &str, &[u8])]) -> [u8; 2] {
+        let two_pixels = image::RgbImage::from_fn(2, 1, |x, _| image::Rgb([x as u8 * 255; 3]));
+        let image = encode_png(&DynamicImage::ImageRgb8(two_pixels)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let mut all_fields = vec![
+            ("image", "image/png", &image[..]),
+            ("palette", "image/png", &palette[..]),
+        ];
+        all_fields.extend_from_slice(fields);
+        let response = send(form_request(&PALETTIZE_ENDPOINT, &all_fields)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let output = image::load_from_memory(&body_bytes(response).await)
+            .unwrap()
+            .to_rgba8();
+        [output.get_pixel(0, 0).0[3], output.get_pixel(1, 0).0[3]]
+    }
+
+    #[tokio::test]
+    async fn skip_alpha_composite_keeps_transparent_pixels() {
+        // transparent white, then opaque black
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgba([255, 255, 255, 0])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let image = encode_png(&DynamicImage::ImageRgba8(image)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let palettized = |skip: &'static [u8]| {
+            let request = form_request(
+                &PALETTIZE_ENDPOINT,
+                &[
+                    ("image", "image/png", &image[..]),
+                    ("palette", "image/png", &palette[..]),
+                    ("background_color", "text/plain", b"#000000"),
+                    ("skip_alpha_composite", "text/plain", skip),
+                ],
+            );
+            async {
+                let response = send(request).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                image::load_from_memory(&body_bytes(response).await)
+                    .unwrap()
+                    .to_rgba8()
+            }
+        };
+
+        let composited = palettized(b"0").await;
+        assert_eq!(composited.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        let skipped = palettized(b"1").await;
+        assert_eq!(skipped.get_pixel(0, 0), &Rgba([255, 255, 255, 0]));
+        assert_eq!(skipped.get_pixel(1, 0), composited.get_pixel(1, 0));
+    }
+
+    #[tokio::test]
+    async fn index_0_is_transparent() {
+        let alpha = black_and_white_alpha(&[("transparency_mode", "text/plain", b"index_0")]).await;
+        assert_eq!(alpha, [0, 255]);
+    }
+
+    #[tokio::test]
+    async fn first_color_is_transparent() {
+        let alpha =
+            black_and_white_alpha(&[("transparency_mode", "text/plain", b"first_color")]).await;
+        assert_eq!(alpha, [0, 255]);
+    }
+
+    #[tokio::test]
+    async fn explicit_color_is_transparent() {
+        let alpha = black_and_white_alpha(&[
+            ("transparency_mode", "text/plain", b"explicit"),
+            ("transparent_color", "text/plain", b"#FFFFFF"),
+        ])
+        .await;
+        assert_eq!(alpha, [255, 0]);
+    }
+
+    #[tokio::test]
+    async fn health_answers_in_time() {
+        let response = send(get_request(&HEALTH_ENDPOINT)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn slow_routes_time_out_with_504() {
+        // the same layer /health gets, around something slower than it allows
+        let slow = with_timeout(
+            Router::new().route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "too late"
+                }),
+            ),
+            DEFAULT_HEALTH_TIMEOUT_MS,
+        );
+        let start = Instant::now();
+        let response = slow.oneshot(get_request("/slow")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn bytes_are_counted_as_they_go_by() {
+        let image = encode_png(&DynamicImage::new_rgb8(40, 30)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let request = form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ],
+        );
+        let sent = request.body().size_hint().exact().unwrap();
+
+        // other tests count their bytes too, so these only ever go up by at
+        // least as much as this one sends
+        let (in_before, out_before) = (
+            TOTAL_BYTES_IN.load(Ordering::Relaxed),
+            TOTAL_BYTES_OUT.load(Ordering::Relaxed),
+        );
+        let response = send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let received = body_bytes(response).await.len() as u64;
+
+        assert!(TOTAL_BYTES_IN.load(Ordering::Relaxed) - in_before >= sent);
+        assert!(TOTAL_BYTES_OUT.load(Ordering::Relaxed) - out_before >= received);
+    }
+
+    #[tokio::test]
+    async fn pngs_sent_as_octet_streams_decode() {
+        let image = encode_png(&DynamicImage::new_rgb8(3, 2)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "application/octet-stream", &image[..]),
+                ("palette", "application/octet-stream", &palette[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let output = image::load_from_memory(&body_bytes(response).await).unwrap();
+        assert_eq!(output.dimensions(), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn one_color_palettes_are_a_bad_request() {
+        let image = encode_png(&DynamicImage::new_rgb8(4, 4)).unwrap();
+        let red = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            1,
+            1,
+            image::Rgb([255, 0, 0]),
+        )))
+        .unwrap();
+        let response = send(form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &red[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_bytes(response).await,
+            format!(
+                "palette must contain at least {} distinct colors",
+                DEFAULT_MIN_PALETTE_COLORS
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn openapi_json_has_a_version() {
+        let response = send(get_request(&OPENAPI_ENDPOINT)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let docs = serde_json::from_slice::<serde_json::Value>(&body_bytes(response).await)
+            .expect("the docs are JSON");
+        let version = docs["openapi"]
+            .as_str()
+            .expect("there's an openapi version");
+        assert!(version.starts_with("3."), "{}", version);
+        assert!(docs["paths"]
+            .as_object()
+            .is_some_and(|paths| paths.contains_key("/palettize/thumbnail")));
+    }
+
+    #[tokio::test]
+    async fn streamed_pngs_decode() {
+        let gradient =
+            image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 128]));
+        let image = encode_png(&DynamicImage::ImageRgb8(gradient)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+                ("stream", "text/plain", b"1"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let png = image::load_from_memory_with_format(
+            &body_bytes(response).await,
+            image::ImageFormat::Png,
+        )
+        .expect("the streamed PNG decodes");
+        assert_eq!(png.dimensions(), (64, 48));
+        assert!(png
+            .to_rgb8()
+            .pixels()
+            .all(|p| BLACK_AND_WHITE.contains(&p.0)));
+    }
+
+    /// The Content-Encoding of the response to `uri` with the gradient,
+    /// asking for `encoding`.
+    async fn content_encoding(uri: &str, encoding: Option<&'static str>) -> Option<HeaderValue> {
+        let gradient =
+            image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 128]));
+        let image = encode_png(&DynamicImage::ImageRgb8(gradient)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let mut request = form_request(
+            uri,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ],
+        );
+        if let Some(encoding) = encoding {
+            request
+                .headers_mut()
+                .insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        let response = send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers().get(CONTENT_ENCODING).cloned()
+    }
+
+    #[tokio::test]
+    async fn pngs_are_compressed_when_asked() {
+        assert_eq!(content_encoding(&PALETTIZE_ENDPOINT, None).await, None);
+        for encoding in ["gzip", "zstd"] {
+            assert_eq!(
+                content_encoding(&PALETTIZE_ENDPOINT, Some(encoding)).await,
+                Some(HeaderValue::from_static(encoding))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn jpegs_are_not_compressed() {
+        assert_eq!(
+            content_encoding(&PALETTIZE_THUMBNAIL_ENDPOINT, Some("gzip")).await,
+            None
+        );
+    }
+
+    /// Palettize with the fields plus the black and white palette, sending
+    /// `cookie` if there is one, and give back the status and any cookie set.
+    async fn palettize_in_session(
+        fields: &[(&str, &str, &[u8])],
+        cookie: Option<&str>,
+    ) -> (StatusCode, Option<String>) {
+        let palette = palette_png(BLACK_AND_WHITE);
+        let mut fields = fields.to_vec();
+        fields.push(("palette", "image/png", &palette[..]));
+        let mut request = form_request(&PALETTIZE_ENDPOINT, &fields);
+        if let Some(cookie) = cookie {
+            request
+                .headers_mut()
+                .insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        }
+        let response = send(request).await;
+        let cookie = response.headers().get(SET_COOKIE).map(|cookie| {
+            let cookie = cookie.to_str().unwrap();
+            cookie.split(';').next().unwrap().to_string()
+        });
+        (response.status(), cookie)
+    }
+
+    #[tokio::test]
+    async fn session_images_are_only_kept_when_asked() {
+        let image = encode_png(&DynamicImage::new_rgb8(4, 4)).unwrap();
+        let (status, cookie) =
+            palettize_in_session(&[("image", "image/png", &image[..])], None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cookie, None);
+
+        let (status, cookie) = palettize_in_session(
+            &[
+                ("image", "image/png", &image[..]),
+                ("keep_session_image", "text/plain", &b"1"[..]),
+            ],
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let cookie = cookie.expect("keeping the image starts a session");
+
+        let (status, _) = palettize_in_session(
+            &[("use_session_image", "text/plain", &b"1"[..])],
+            Some(&cookie),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = palettize_in_session(
+            &[
+                ("clear_session", "text/plain", &b"1"[..]),
+                ("use_session_image", "text/plain", &b"1"[..]),
+            ],
+            Some(&cookie),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// The used colors of a mostly white image, and the `colors` of its
+    /// multipart JSON, with the given fields.
+    async fn listed_colors(fields: &[(&str, &str, &[u8])]) -> (String, serde_json::Value) {
+        let mut image = image::RgbImage::from_pixel(4, 4, image::Rgb([250, 250, 250]));
+        image.put_pixel(0, 0, image::Rgb([5, 5, 5]));
+        let image = encode_png(&DynamicImage::ImageRgb8(image)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let mut fields = fields.to_vec();
+        fields.push(("image", "image/png", &image[..]));
+        fields.push(("palette", "image/png", &palette[..]));
+        fields.push(("optimize_palette", "text/plain", &b"1"[..]));
+        fields.push(("return_multipart", "text/plain", &b"1"[..]));
+
+        let response = send(form_request(&PALETTIZE_ENDPOINT, &fields)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let used = response.headers()["x-palettizer-used-colors"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = body_bytes(response).await;
+        let body = String::from_utf8_lossy(&body);
+        let json = body
+            .split("Content-Type: application/json\r\n\r\n")
+            .nth(1)
+            .and_then(|rest| rest.split("\r\n--").next())
+            .expect("there's a JSON part");
+        let stats = serde_json::from_str::<serde_json::Value>(json).unwrap();
+        (used, stats["colors"].clone())
+    }
+
+    #[tokio::test]
+    async fn palette_frequency_sort_puts_the_most_used_first() {
+        let (used, colors) = listed_colors(&[]).await;
+        assert_eq!(used, "#000000,#FFFFFF");
+        assert_eq!(colors, serde_json::json!(["#000000", "#FFFFFF"]));
+
+        let (used, colors) =
+            listed_colors(&[("palette_frequency_sort", "text/plain", &b"1"[..])]).await;
+        assert_eq!(used, "#FFFFFF,#000000");
+        assert_eq!(colors, serde_json::json!(["#FFFFFF", "#000000"]));
+    }
+
+    #[tokio::test]
+    async fn strips_are_no_wider_than_the_palette() {
+        let response = send(form_request(
+            &PALETTE_TO_IMAGE_ENDPOINT,
+            &[
+                ("palette", "text/plain", &b"#000000\n#FF0000\n#00FF00"[..]),
+                ("strip_cols", "text/plain", &b"5000000000"[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let strip = image::load_from_memory(&body_bytes(response).await).unwrap();
+        assert_eq!(strip.dimensions(), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn svg_pixel_sizes_that_overflow_are_rejected() {
+        let image = encode_png(&DynamicImage::new_rgb8(4, 4)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let svg = |pixel_size: &'static [u8]| {
+            send(form_request(
+                &PALETTIZE_ENDPOINT,
+                &[
+                    ("image", "image/png", &image[..]),
+                    ("palette", "image/png", &palette[..]),
+                    ("return_format", "text/plain", &b"svg"[..]),
+                    ("svg_pixel_size", "text/plain", pixel_size),
+                ],
+            ))
+        };
+        assert_eq!(svg(b"1000").await.status(), StatusCode::OK);
+        assert_eq!(svg(b"2000000000").await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn animation_loop_frames_differ_at_swapped_entries() {
+        use image::{codecs::gif::GifDecoder, AnimationDecoder as _};
+
+        let two_pixels = image::RgbImage::from_fn(2, 1, |x, _| image::Rgb([x as u8 * 255; 3]));
+        let image = encode_png(&DynamicImage::ImageRgb8(two_pixels)).unwrap();
+        // entry 0 is white as drawn, but black if the colors were sorted
+        let palette_a = palette_png(&[[255, 255, 255], [0, 0, 0]]);
+        let palette_b = palette_png(&[[255, 0, 0], [0, 0, 0]]);
+        let response = send(form_request(
+            &PALETTIZE_ANIMATION_LOOP_ENDPOINT,
+            &[
+                ("image", "image/png", &image),
+                ("palette_a", "image/png", &palette_a),
+                ("palette_b", "image/png", &palette_b),
+                ("swap_entries", "text/plain", b"0"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let gif = body_bytes(response).await;
+        let frames = GifDecoder::new(Cursor::new(gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        let (a, b) = (frames[0].buffer(), frames[1].buffer());
+        assert_ne!(a, b);
+        assert_eq!(a.get_pixel(0, 0), b.get_pixel(0, 0));
+        assert_eq!(a.get_pixel(1, 0).0, [255, 255, 255, 255]);
+        assert_eq!(b.get_pixel(1, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[tokio::test]
+    async fn interpolation_pairs_colors_as_drawn() {
+        use std::io::Read as _;
+
+        let palette_a = palette_png(BLACK_AND_WHITE);
+        let palette_b = palette_png(&[[255, 255, 255], [0, 0, 0]]);
+        let response = send(form_request(
+            &PALETTE_INTERPOLATE_ENDPOINT,
+            &[
+                ("palette_a", "image/png", &palette_a),
+                ("palette_b", "image/png", &palette_b),
+                ("n", "text/plain", b"3"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(body_bytes(response).await)).unwrap();
+        let mut step = |i: usize| {
+            let mut png = Vec::new();
+            zip.by_name(&format!("step_{}.png", i))
+                .unwrap()
+                .read_to_end(&mut png)
+                .unwrap();
+            image::load_from_memory(&png).unwrap().to_rgb8()
+        };
+        // sorted, both palettes would be black then white
+        let first = step(0);
+        assert_eq!(first.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(first.get_pixel(1, 0).0, [255, 255, 255]);
+        let last = step(2);
+        assert_eq!(last.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(last.get_pixel(1, 0).0, [0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn inverted_palettes_take_an_output_format() {
+        let palette = palette_png(&[[0xFF, 0xFF, 0xFF], [0xFF, 0x88, 0x00]]);
+        let response = send(form_request(
+            &PALETTE_INVERT_ENDPOINT,
+            &[
+                ("palette", "image/png", &palette),
+                ("output_format", "text/plain", b"hex_list"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_bytes(response).await;
+        let mut lines = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        lines.sort();
+        assert_eq!(lines, ["#000000", "#0077ff"]);
+    }
+
+    #[tokio::test]
+    async fn multipart_responses_are_not_cached() {
+        let image = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            3,
+            5,
+            image::Rgb([1, 4, 4]),
+        )))
+        .unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        // what the second of two of the same request says about the cache
+        let second_cache_header = |multipart: bool| {
+            let mut fields = vec![
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ];
+            if multipart {
+                fields.push(("return_multipart", "text/plain", &b"1"[..]));
+            }
+            async move {
+                send(form_request(&PALETTIZE_ENDPOINT, &fields)).await;
+                let response = send(form_request(&PALETTIZE_ENDPOINT, &fields)).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                response.headers().get("x-cache").cloned()
+            }
+        };
+
+        assert_eq!(second_cache_header(true).await, None);
+        assert_eq!(second_cache_header(false).await.unwrap(), "HIT");
+    }
+
+    #[tokio::test]
+    async fn palettes_sort_by_luminance() {
+        let palette = palette_png(&[[255, 255, 0], [0, 0, 255], [255, 0, 0], [0, 255, 0]]);
+        let response = send(form_request(
+            &PALETTE_SORT_ENDPOINT,
+            &[
+                ("palette", "image/png", &palette),
+                ("sort_by", "text/plain", b"luminance"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let strip = image::load_from_memory(&body_bytes(response).await)
+            .unwrap()
+            .to_rgb8();
+        let colors = strip.pixels().map(|pixel| pixel.0).collect::<Vec<_>>();
+        assert_eq!(
+            colors,
+            [[0, 0, 255], [255, 0, 0], [0, 255, 0], [255, 255, 0]]
+        );
+        assert!(colors
+            .windows(2)
+            .all(|pair| color::srgb_to_xyz(pair[0])[1] <= color::srgb_to_xyz(pair[1])[1]));
+    }
+
+    #[tokio::test]
+    async fn tile_align_pads_up_to_whole_tiles() {
+        let image = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            7,
+            7,
+            image::Rgb([255, 255, 255]),
+        )))
+        .unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "image/png", &image),
+                ("palette", "image/png", &palette),
+                ("force_tile_align", "text/plain", b"1"),
+                ("tile_width", "text/plain", b"4"),
+                ("tile_height", "text/plain", b"4"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-palettizer-padded-width"], "8");
+        assert_eq!(response.headers()["x-palettizer-padded-height"], "8");
+        let padded = image::load_from_memory(&body_bytes(response).await).unwrap();
+        assert_eq!(padded.dimensions(), (8, 8));
+    }
+
+    #[tokio::test]
+    async fn hex_lists_are_a_line_per_color() {
+        let colors = [[0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00], [0x00, 0x00, 0xFF]];
+        let palette = palette_png(&colors);
+        let response = send(form_request(
+            &PALETTE_SORT_ENDPOINT,
+            &[
+                ("palette", "image/png", &palette),
+                ("sort_by", "text/plain", b"hue"),
+                ("return_format", "text/plain", b"hex_list"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+
+        let body = body_bytes(response).await;
+        let text = std::str::from_utf8(&body).expect("hex lists are UTF-8");
+        assert_eq!(text, text.trim_end());
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines, ["#FF0000", "#00FF00", "#0000FF"]);
+        assert!(lines.iter().all(|line| line.len() == 7));
+    }
+
+    #[tokio::test]
+    async fn bulk_previews_are_one_per_palette() {
+        let image = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            4,
+            4,
+            image::Rgb([200, 60, 60]),
+        )))
+        .unwrap();
+        let palette_0 = palette_png(BLACK_AND_WHITE);
+        let palette_1 = palette_png(&[[255, 0, 0], [0, 0, 255]]);
+        let response = send(form_request(
+            &PALETTIZE_BULK_PREVIEW_ENDPOINT,
+            &[
+                ("image", "image/png", &image),
+                ("palette_0", "image/png", &palette_0),
+                ("palette_1", "image/png", &palette_1),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let previews = serde_json::from_slice::<Vec<String>>(&body_bytes(response).await).unwrap();
+        assert_eq!(previews.len(), 2);
+        let first_pixel = |preview: &str| {
+            let png = BASE64_STANDARD.decode(preview).unwrap();
+            image::load_from_memory(&png)
+                .unwrap()
+                .to_rgb8()
+                .get_pixel(0, 0)
+                .0
+        };
+        assert_eq!(first_pixel(&previews[0]), [0, 0, 0]);
+        assert_eq!(first_pixel(&previews[1]), [255, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn lossless_webp_has_the_same_pixels_as_png() {
+        let image = encode_png(&DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+            8,
+            8,
+            |x, y| image::Rgb([x as u8 * 32, y as u8 * 32, 128]),
+        )))
+        .unwrap();
+        let palette = palette_png(&[[255, 0, 0], [0, 255, 0], [0, 0, 255], [40, 40, 40]]);
+        let palettized = |output_format: &'static [u8]| {
+            let (image, palette) = (image.clone(), palette.clone());
+            async move {
+                let response = send(form_request(
+                    &PALETTIZE_ENDPOINT,
+                    &[
+                        ("image", "image/png", &image),
+                        ("palette", "image/png", &palette),
+                        ("output_format", "text/plain", output_format),
+                    ],
+                ))
+                .await;
+                assert_eq!(response.status(), StatusCode::OK);
+                image::load_from_memory(&body_bytes(response).await)
+                    .unwrap()
+                    .to_rgba8()
+            }
+        };
+
+        let png = palettized(b"png").await;
+        let webp = palettized(b"webp_lossless").await;
+        assert_eq!(webp.dimensions(), (8, 8));
+        assert!(webp == png);
+    }
+
+    /// A 6x4 JPEG whose EXIF says to turn it a quarter clockwise.
+    fn sideways_jpeg() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg)
+            .encode_image(&image::RgbImage::from_pixel(
+                6,
+                4,
+                image::Rgb([200, 200, 200]),
+            ))
+            .unwrap();
+
+        // big endian TIFF with one IFD entry, orientation (0x0112) as a short
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0");
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        app1.extend_from_slice(&exif);
+        // right after the start of image marker
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[tokio::test]
+    async fn exif_rotate_turns_the_image_upright() {
+        let jpeg = sideways_jpeg();
+        let palette = palette_png(BLACK_AND_WHITE);
+        for (exif_rotate, dimensions) in [(&b"1"[..], (4, 6)), (&b"0"[..], (6, 4))] {
+            let response = send(form_request(
+                &PALETTIZE_ENDPOINT,
+                &[
+                    ("image", "image/jpeg", &jpeg),
+                    ("palette", "image/png", &palette),
+                    ("exif_rotate", "text/plain", exif_rotate),
+                ],
+            ))
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let palettized = image::load_from_memory(&body_bytes(response).await).unwrap();
+            assert_eq!(palettized.dimensions(), dimensions);
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_are_named_after_output_filename() {
+        let image = palette_png(BLACK_AND_WHITE);
+        let palette = palette_png(BLACK_AND_WHITE);
+        for (output_filename, disposition) in [
+            (None, "attachment; filename=\"palettized.png\""),
+            (
+                Some(&b"../sunset \"v2\".final"[..]),
+                "attachment; filename=\"..sunsetv2.final.png\"",
+            ),
+        ] {
+            let mut fields = vec![
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ];
+            if let Some(output_filename) = output_filename {
+                fields.push(("output_filename", "text/plain", output_filename));
+            }
+            let response = send(form_request(&PALETTIZE_ENDPOINT, &fields)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-disposition"], disposition);
+        }
+    }
+
+    async fn thumbnail_of(image: DynamicImage) -> DynamicImage {
+        let image = encode_png(&image).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_THUMBNAIL_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/jpeg");
+
+        let jpeg = body_bytes(response).await;
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        image::load_from_memory_with_format(&jpeg, image::ImageFormat::Jpeg).unwrap()
+    }
+
+    #[tokio::test]
+    async fn thumbnails_fit_in_128x128() {
+        let thumbnail = thumbnail_of(DynamicImage::new_rgb8(1000, 600)).await;
+        assert_eq!(thumbnail.dimensions(), (128, 77));
+    }
+
+    #[tokio::test]
+    async fn thumbnails_of_small_images_stay_small() {
+        let thumbnail = thumbnail_of(DynamicImage::new_rgb8(50, 40)).await;
+        assert_eq!(thumbnail.dimensions(), (50, 40));
+    }
+
+    fn stitch_request(tiles: &[(&str, DynamicImage)]) -> Request {
+        let tiles = tiles
+            .iter()
+            .map(|(name, tile)| (*name, encode_png(tile).unwrap()))
+            .collect::<Vec<_>>();
+        let mut fields = tiles
+            .iter()
+            .map(|(name, png)| (*name, "image/png", &png[..]))
+            .collect::<Vec<_>>();
+        fields.push(("tile_width", "text/plain", &b"4"[..]));
+        fields.push(("tile_height", "text/plain", &b"4"[..]));
+        form_request(&PALETTIZE_STITCH_ENDPOINT, &fields)
+    }
+
+    #[tokio::test]
+    async fn two_tiles_stitch_side_by_side() {
+        let left =
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255])));
+        let right =
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255])));
+        let response = send(stitch_request(&[("tile_0_0", left), ("tile_0_1", right)])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let stitched = image::load_from_memory(&body_bytes(response).await).unwrap();
+        assert_eq!(stitched.dimensions(), (8, 4));
+        assert_eq!(stitched.get_pixel(3, 3), Rgba([255, 0, 0, 255]));
+        assert_eq!(stitched.get_pixel(4, 0), Rgba([0, 0, 255, 255]));
+    }
+
+    #[tokio::test]
+    async fn stitching_mismatched_edge_tiles_is_a_bad_request() {
+        let response = send(stitch_request(&[
+            ("tile_0_0", DynamicImage::new_rgba8(4, 4)),
+            ("tile_0_1", DynamicImage::new_rgba8(2, 4)),
+            ("tile_1_0", DynamicImage::new_rgba8(4, 3)),
+            ("tile_1_1", DynamicImage::new_rgba8(3, 3)),
+        ]))
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let message = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+        assert!(message.contains("tile_1_1"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn alpha_dither_feathers_with_on_or_off_alpha() {
+        // opaque on the left, fading to transparent on the right
+        let feathered =
+            image::RgbaImage::from_fn(32, 32, |x, _| Rgba([255, 255, 255, 255 - x as u8 * 8]));
+        let expected_mean = feathered.pixels().map(|p| p.0[3] as f64).sum::<f64>() / (32.0 * 32.0);
+        let image = encode_png(&DynamicImage::ImageRgba8(feathered)).unwrap();
+        let palette = palette_png(BLACK_AND_WHITE);
+        let response = send(form_request(
+            &PALETTIZE_ENDPOINT,
+            &[
+                ("image", "image/png", &image[..]),
+                ("palette", "image/png", &palette[..]),
+                ("dither", "text/plain", b"floyd_steinberg"),
+                ("alpha_dither", "text/plain", b"1"),
+                ("alpha_levels", "text/plain", b"0,255"),
+            ],
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let output = image::load_from_memory(&body_bytes(response).await)
+            .unwrap()
+            .to_rgba8();
+        assert!(output.pixels().all(|p| p.0[3] == 0 || p.0[3] == 255));
+        let middle = (0..32)
+            .map(|y| output.get_pixel(16, y).0[3])
+            .collect::<Vec<_>>();
+        assert!(middle.contains(&0) && middle.contains(&255));
+        let mean = output.pixels().map(|p| p.0[3] as f64).sum::<f64>() / (32.0 * 32.0);
+        assert!(
+            (mean - expected_mean).abs() < 8.0,
+            "{} vs {}",
+            mean,
+            expected_mean
+        );
+    }
+
+    /// A config whose templates are in a fresh directory, with these contents.
+    fn config_with_templates(test: &str, error: Option<&str>, index: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("palettizer-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let error_path = dir.join("error.html");
+        let index_path = dir.join("index.html");
+        let _ = std::fs::remove_file(&error_path);
+        if let Some(error) = error {
+            std::fs::write(&error_path, error).unwrap();
+        }
+        std::fs::write(&index_path, index).unwrap();
+
+        parse_config(&format!(
+            r#"
+            root = "/voronoi"
+            bind = "127.0.0.1:30305"
+
+            [templates]
+            error = {:?}
+            index = {:?}
+            "#,
+            error_path, index_path
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn startup_checks_pass_with_good_templates() {
+        let config = config_with_templates("good", Some("{{ error }}"), "<p>hi</p>");
+        assert!(run_startup_checks(&config).is_ok());
+    }
+
+    #[test]
+    fn startup_checks_catch_a_missing_template() {
+        let config = config_with_templates("missing", None, "<p>hi</p>");
+        let error = run_startup_checks(&config).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("could not read error template"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn startup_checks_catch_a_broken_template() {
+        let config = config_with_templates("broken", Some("{{ error }}"), "{% if %}");
+        let error = run_startup_checks(&config).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("could not parse index template"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn startup_checks_catch_bad_settings() {
+        let mut config = config_with_templates("settings", Some("{{ error }}"), "<p>hi</p>");
+        config.thumbnail_quality = Some(101);
+        assert!(run_startup_checks(&config).is_err());
+    }
+
+    #[test]
+    fn env_var_names_are_what_hydrate_from_env_reads() {
+        // CONFIG reads the environment too, so it has to be loaded before
+        // anything is set
+        LazyLock::force(&CONFIG);
+
+        let mut config = config_with_templates("env", Some("{{ error }}"), "<p>hi</p>");
+        let fields = [
+            ("templates.error", "/from/env/error.html"),
+            ("tls.cert_path", "/from/env/cert.pem"),
+        ];
+        for (field, value) in fields {
+            std::env::set_var(env_var_name(field), value);
+        }
+        config.hydrate_from_env();
+        for (field, _) in fields {
+            std::env::remove_var(env_var_name(field));
+        }
+
+        assert_eq!(
+            config.templates.error,
+            PathBuf::from("/from/env/error.html")
+        );
+        assert_eq!(
+            config.tls.cert_path,
+            Some(PathBuf::from("/from/env/cert.pem"))
+        );
+    }
+
+    #[test]
+    fn config_without_tls_loads() {
+        let config = parse_config(
+            r#"
+            root = "/voronoi"
+            bind = "127.0.0.1:30305"
+
+            [templates]
+            error = "templates/error.html"
+            index = "templates/index.html"
+            "#,
+        )
+        .expect("TLS is optional");
+        assert_eq!(config.tls.cert_path, None);
+        assert_eq!(config.tls.key_path, None);
+    }
+
+    #[test]
+    fn tls_table_is_read() {
+        let config = parse_config(
+            r#"
+            root = "/voronoi"
+            bind = "127.0.0.1:30305"
+
+            [templates]
+            error = "templates/error.html"
+            index = "templates/index.html"
+
+            [tls]
+            cert_path = "cert.pem"
+            key_path = "key.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tls.cert_path, Some(PathBuf::from("cert.pem")));
+        assert_eq!(config.tls.key_path, Some(PathBuf::from("key.pem")));
+    }
 }
@@ -0,0 +1,73 @@
+//! How close a palettized image is to the original.
+
+use image::{DynamicImage, GenericImageView};
+
+/// SSIM compares the images a window at a time.
+const SSIM_WINDOW: u32 = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Peak signal to noise ratio over the color channels, in decibels. None if
+/// the images are the same, which would be infinitely many decibels.
+pub fn psnr(a: &DynamicImage, b: &DynamicImage) -> Option<f64> {
+    let mut squared_error = 0.0;
+    for (x, y, pa) in a.pixels() {
+        let pb = b.get_pixel(x, y);
+        for c in 0..3 {
+            squared_error += (pa.0[c] as f64 - pb.0[c] as f64).powi(2);
+        }
+    }
+
+    let mse = squared_error / (a.width() as f64 * a.height() as f64 * 3.0);
+    (mse > 0.0).then(|| 10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+/// Mean structural similarity of the images' luma, averaged over windows
+/// that don't overlap. 1 means the same.
+pub fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let a = a.to_luma8();
+    let b = b.to_luma8();
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..a.height()).step_by(SSIM_WINDOW as usize) {
+        for wx in (0..a.width()).step_by(SSIM_WINDOW as usize) {
+            let pixels = (wy..(wy + SSIM_WINDOW).min(a.height()))
+                .flat_map(|y| (wx..(wx + SSIM_WINDOW).min(a.width())).map(move |x| (x, y)))
+                .map(|(x, y)| (a.get_pixel(x, y).0[0] as f64, b.get_pixel(x, y).0[0] as f64))
+                .collect::<Vec<_>>();
+            let n = pixels.len() as f64;
+
+            let mean_a = pixels.iter().map(|(pa, _)| pa).sum::<f64>() / n;
+            let mean_b = pixels.iter().map(|(_, pb)| pb).sum::<f64>() / n;
+            let mut var_a = 0.0;
+            let mut var_b = 0.0;
+            let mut covariance = 0.0;
+            for (pa, pb) in pixels.iter() {
+                var_a += (pa - mean_a).powi(2);
+                var_b += (pb - mean_b).powi(2);
+                covariance += (pa - mean_a) * (pb - mean_b);
+            }
+            var_a /= n;
+            var_b /= n;
+            covariance /= n;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// How many pixels' colors differ, ignoring alpha.
+pub fn changed_pixels(a: &DynamicImage, b: &DynamicImage) -> u64 {
+    a.pixels()
+        .filter(|&(x, y, pa)| pa.0[..3] != b.get_pixel(x, y).0[..3])
+        .count() as u64
+}
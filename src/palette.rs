@@ -0,0 +1,821 @@
+use crate::color::{self, Illuminant};
+use anyhow::anyhow;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use utoipa::ToSchema;
+
+/// The distinct colors of a palette image, ignoring alpha.
+pub fn palette_colors(palette: &DynamicImage) -> Vec<[u8; 3]> {
+    let mut colors = BTreeSet::new();
+    for (_, _, color) in palette.pixels() {
+        colors.insert([color.0[0], color.0[1], color.0[2]]);
+    }
+    colors.into_iter().collect()
+}
+
+/// The distinct colors of a palette image, ignoring alpha, in the order
+/// they're drawn: left to right, then top to bottom, the way strips are read.
+pub fn palette_colors_in_order(palette: &DynamicImage) -> Vec<[u8; 3]> {
+    dedup_colors(
+        palette
+            .pixels()
+            .map(|(_, _, color)| [color.0[0], color.0[1], color.0[2]]),
+    )
+}
+
+/// Drop repeated colors, keeping the first of each.
+pub fn dedup_colors(colors: impl IntoIterator<Item = [u8; 3]>) -> Vec<[u8; 3]> {
+    let mut seen = HashSet::new();
+    colors
+        .into_iter()
+        .filter(|&color| seen.insert(color))
+        .collect()
+}
+
+/// Whether the bytes look like a RIFF palette, as written by Windows and a
+/// lot of older game tools.
+pub fn is_riff_pal(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"PAL "
+}
+
+/// Parse a RIFF palette. The data chunk is a `LOGPALETTE`, with a version and
+/// entry count followed by the entries, usually 4 bytes each as red, green,
+/// blue, and flags. Some tools leave off the flags and write 3 bytes per
+/// entry instead.
+pub fn parse_riff_pal(bytes: &[u8]) -> anyhow::Result<Vec<Rgba<u8>>> {
+    if !is_riff_pal(bytes) {
+        return Err(anyhow!("not a RIFF palette"));
+    }
+
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32_at(offset + 4) as usize;
+        let start = offset + 8;
+        let Some(end) = start.checked_add(size).filter(|&end| end <= bytes.len()) else {
+            return Err(anyhow!("chunk {:?} runs past the end of the file", id));
+        };
+
+        if id == b"data" {
+            if size < 4 {
+                return Err(anyhow!("data chunk is too short"));
+            }
+            let count = u16_at(start + 2) as usize;
+            let entries = &bytes[start + 4..end];
+            let entry_size = match count {
+                0 => return Ok(Vec::new()),
+                _ if entries.len() >= count * 4 => 4,
+                _ if entries.len() >= count * 3 => 3,
+                _ => return Err(anyhow!("data chunk is too short for {} colors", count)),
+            };
+
+            return Ok(entries
+                .chunks_exact(entry_size)
+                .take(count)
+                .map(|entry| Rgba([entry[0], entry[1], entry[2], 255]))
+                .collect());
+        }
+
+        // chunks are padded to an even length
+        offset = end + size % 2;
+    }
+
+    Err(anyhow!("no data chunk"))
+}
+
+/// Whether the bytes are the size of an Adobe color table, 256 colors with or
+/// without the trailer. There's no signature, so text that happens to be this
+/// long looks like one too.
+pub fn is_act(bytes: &[u8]) -> bool {
+    bytes.len() == ACT_COLORS * 3 || bytes.len() == ACT_COLORS * 3 + 4
+}
+
+/// Parse an Adobe color table. The trailer, if there is one, says how many of
+/// the 256 colors are used.
+pub fn parse_act(bytes: &[u8]) -> anyhow::Result<Vec<[u8; 3]>> {
+    if !is_act(bytes) {
+        return Err(anyhow!("not an Adobe color table"));
+    }
+
+    let count = match bytes.get(ACT_COLORS * 3..ACT_COLORS * 3 + 2) {
+        Some(&[high, low]) => (u16::from_be_bytes([high, low]) as usize).min(ACT_COLORS),
+        _ => ACT_COLORS,
+    };
+    Ok(bytes[..ACT_COLORS * 3]
+        .chunks_exact(3)
+        .take(count)
+        .map(|color| [color[0], color[1], color[2]])
+        .collect())
+}
+
+/// Parse a palette written out as text, in any of the formats palettes can be
+/// sent back in that are text: a GIMP palette, a JSON array of `#RRGGBB`
+/// strings or of objects with a `hex` field, or one `RRGGBB` per line with an
+/// optional `#`. Lines starting with `;` are comments in hex lists, like in
+/// paint.net's palettes.
+pub fn parse_text_palette(text: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let trimmed = text.trim_start();
+
+    if trimmed.starts_with("GIMP Palette") {
+        let mut colors = Vec::new();
+        for line in trimmed.lines().skip(1) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.contains(':') {
+                continue;
+            }
+
+            let channels = line
+                .split_whitespace()
+                .take(3)
+                .map(|c| c.parse::<u8>())
+                .collect::<Result<Vec<_>, _>>();
+            match channels.as_deref() {
+                Ok(&[r, g, b]) => colors.push([r, g, b]),
+                _ => return Err(anyhow!("invalid GIMP palette line {:?}", line)),
+            }
+        }
+        return Ok(colors);
+    }
+
+    if trimmed.starts_with('[') {
+        let entries = serde_json::from_str::<Vec<serde_json::Value>>(trimmed)?;
+        return entries
+            .iter()
+            .map(|entry| {
+                let hex = match entry {
+                    serde_json::Value::String(hex) => Some(hex.as_str()),
+                    serde_json::Value::Object(object) => {
+                        object.get("hex").and_then(|hex| hex.as_str())
+                    }
+                    _ => None,
+                };
+                hex.and_then(parse_hex_color)
+                    .ok_or_else(|| anyhow!("invalid JSON palette entry {}", entry))
+            })
+            .collect();
+    }
+
+    trimmed
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| parse_hex_color(line).ok_or_else(|| anyhow!("invalid hex color {:?}", line)))
+        .collect()
+}
+
+/// Parse a color written as `#RRGGBB`. The `#` is optional.
+pub fn parse_hex_color(text: &str) -> Option<[u8; 3]> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+#[derive(Clone, Copy)]
+pub enum GradientSpace {
+    Srgb,
+    Lab(Illuminant),
+}
+
+/// Parse `#RRGGBB-#RRGGBB:N` into the start color, end color, and number of
+/// steps.
+pub fn parse_gradient(text: &str) -> Option<([u8; 3], [u8; 3], usize)> {
+    let (colors, count) = text.split_once(':')?;
+    let (start, end) = colors.split_once('-')?;
+    Some((
+        parse_hex_color(start.trim())?,
+        parse_hex_color(end.trim())?,
+        count.trim().parse().ok()?,
+    ))
+}
+
+/// `count` evenly spaced colors from `start` to `end`, inclusive. `count` must
+/// be at least 2.
+pub fn gradient_palette(
+    start: [u8; 3],
+    end: [u8; 3],
+    count: usize,
+    space: GradientSpace,
+) -> Vec<[u8; 3]> {
+    let steps = count - 1;
+    match space {
+        GradientSpace::Srgb => (0..count)
+            .map(|i| {
+                let channel = |c: usize| {
+                    ((start[c] as usize * (steps - i) + end[c] as usize * i) / steps) as u8
+                };
+                [channel(0), channel(1), channel(2)]
+            })
+            .collect(),
+
+        GradientSpace::Lab(illuminant) => {
+            let start = color::srgb_to_lab(start, illuminant);
+            let end = color::srgb_to_lab(end, illuminant);
+            (0..count)
+                .map(|i| {
+                    let t = i as f32 / steps as f32;
+                    let channel = |c: usize| start[c] + (end[c] - start[c]) * t;
+                    color::lab_to_srgb([channel(0), channel(1), channel(2)], illuminant)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Every combination of `steps` evenly spaced values per channel, from black
+/// to white. A single step is just middle gray.
+pub fn cube_palette(steps: u8) -> Vec<[u8; 3]> {
+    let levels = match steps {
+        1 => vec![128],
+        _ => (0..steps as u32)
+            .map(|i| (i * 255 / (steps as u32 - 1)) as u8)
+            .collect::<Vec<_>>(),
+    };
+
+    let mut colors = Vec::with_capacity(levels.len().pow(3));
+    for &r in levels.iter() {
+        for &g in levels.iter() {
+            for &b in levels.iter() {
+                colors.push([r, g, b]);
+            }
+        }
+    }
+    colors
+}
+
+/// `steps` palettes going from `a` to `b`, both included, with each color
+/// moving in a straight line through Lab to the color at the same index.
+pub fn interpolate_palettes(
+    a: &[[u8; 3]],
+    b: &[[u8; 3]],
+    steps: usize,
+    illuminant: Illuminant,
+) -> Vec<Vec<[u8; 3]>> {
+    let gradients = a
+        .iter()
+        .zip(b)
+        .map(|(&start, &end)| gradient_palette(start, end, steps, GradientSpace::Lab(illuminant)))
+        .collect::<Vec<_>>();
+
+    (0..steps)
+        .map(|step| gradients.iter().map(|gradient| gradient[step]).collect())
+        .collect()
+}
+
+/// Spacing of the OKLab grid uniform palettes are picked from.
+const OKLAB_L_STEP: f32 = 0.05;
+const OKLAB_AB_STEP: f32 = 0.02;
+/// a and b stay within this of 0 for every sRGB color.
+const OKLAB_AB_MAX: f32 = 0.4;
+
+/// `n` colors spread as far apart from each other in OKLab as they can be,
+/// starting from black. Each next color is whichever in-gamut point on a grid
+/// is farthest from the ones already picked.
+pub fn oklab_uniform_palette(n: usize) -> Vec<[u8; 3]> {
+    let ab_steps = (2.0 * OKLAB_AB_MAX / OKLAB_AB_STEP).round() as i32;
+    let l_steps = (1.0 / OKLAB_L_STEP).round() as i32;
+
+    let mut candidates = Vec::new();
+    for li in 0..=l_steps {
+        for ai in 0..=ab_steps {
+            for bi in 0..=ab_steps {
+                let lab = [
+                    li as f32 * OKLAB_L_STEP,
+                    ai as f32 * OKLAB_AB_STEP - OKLAB_AB_MAX,
+                    bi as f32 * OKLAB_AB_STEP - OKLAB_AB_MAX,
+                ];
+                if let Some(color) = color::oklab_to_srgb(lab[0], lab[1], lab[2]) {
+                    candidates.push((lab, color));
+                }
+            }
+        }
+    }
+
+    let distance = |p: [f32; 3], q: [f32; 3]| {
+        (p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + (p[2] - q[2]).powi(2)
+    };
+
+    // how close each candidate is to the nearest picked color, black so far
+    let mut nearest = candidates
+        .iter()
+        .map(|&(lab, _)| distance(lab, [0.0, 0.0, 0.0]))
+        .collect::<Vec<_>>();
+    let mut colors = vec![[0, 0, 0]];
+
+    while colors.len() < n {
+        let Some((farthest, _)) = nearest
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            break;
+        };
+
+        let (lab, color) = candidates[farthest];
+        colors.push(color);
+        for (i, &(other, _)) in candidates.iter().enumerate() {
+            nearest[i] = nearest[i].min(distance(lab, other));
+        }
+    }
+
+    dedup_colors(colors)
+}
+
+/// Turn each color's hue halfway around, keeping its saturation and
+/// lightness.
+pub fn complement_palette(colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    colors
+        .iter()
+        .map(|&color| {
+            let [hue, saturation, lightness] = color::srgb_to_hsl(color);
+            color::hsl_to_srgb([hue + 180.0, saturation, lightness])
+        })
+        .collect()
+}
+
+/// Turn each color to the same hue, in degrees, keeping its saturation and
+/// lightness.
+pub fn monochrome_palette(colors: &[[u8; 3]], hue: f32) -> Vec<[u8; 3]> {
+    colors
+        .iter()
+        .map(|&color| {
+            let [_, saturation, lightness] = color::srgb_to_hsl(color);
+            color::hsl_to_srgb([hue, saturation, lightness])
+        })
+        .collect()
+}
+
+/// Turn each color into its negative.
+pub fn invert_palette(colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    colors.iter().map(|&color| color.map(|c| 255 - c)).collect()
+}
+
+/// Add an offset to each channel of each color, either clamping to 0 and
+/// 255 or wrapping around.
+pub fn shift_palette(colors: &[[u8; 3]], dr: i16, dg: i16, db: i16, wrap: bool) -> Vec<[u8; 3]> {
+    let shift = |c: u8, d: i16| {
+        let shifted = c as i16 + d;
+        if wrap {
+            shifted.rem_euclid(256) as u8
+        } else {
+            shifted.clamp(0, 255) as u8
+        }
+    };
+    colors
+        .iter()
+        .map(|&[r, g, b]| [shift(r, dr), shift(g, dg), shift(b, db)])
+        .collect()
+}
+
+/// An image a pixel wide per color, in order, with `columns` colors to a row
+/// and each row `height` pixels tall. The rest of the last row is black.
+pub fn palette_strip(colors: &[[u8; 3]], columns: usize, height: u32) -> DynamicImage {
+    let rows = colors.len().div_ceil(columns);
+    let mut strip = DynamicImage::new_rgb8(columns as u32, rows as u32 * height);
+    for (i, &[r, g, b]) in colors.iter().enumerate() {
+        let x = (i % columns) as u32;
+        let top = (i / columns) as u32 * height;
+        for y in top..top + height {
+            strip.put_pixel(x, y, Rgba([r, g, b, 255]));
+        }
+    }
+    strip
+}
+
+/// How many palettes the Nintendo DS has for tiles, and how many colors each
+/// has besides the transparent one at index 0.
+pub const NDS_SUBPALETTES: usize = 16;
+pub const NDS_SUBPALETTE_COLORS: usize = 15;
+
+/// Split the colors into Nintendo DS subpalettes of up to 15 colors each, in
+/// order. Colors that should end up on the same tiles belong next to each
+/// other.
+pub fn nds_subpalettes(colors: &[[u8; 3]]) -> Option<Vec<Vec<[u8; 3]>>> {
+    if colors.len() > NDS_SUBPALETTES * NDS_SUBPALETTE_COLORS {
+        return None;
+    }
+    Some(
+        colors
+            .chunks(NDS_SUBPALETTE_COLORS)
+            .map(<[_]>::to_vec)
+            .collect(),
+    )
+}
+
+/// Adobe color tables always have room for this many colors.
+const ACT_COLORS: usize = 256;
+
+#[derive(Clone, Copy)]
+pub enum PaletteFormat {
+    /// An array of `{"hex": "#RRGGBB", "rgb": [r, g, b], "css_name": name}`.
+    Json,
+    /// `--color-N` custom properties on `:root`.
+    CssVars,
+    /// GIMP palette.
+    Gpl,
+    /// Adobe color table.
+    Act,
+    /// RIFF palette, with 4 bytes per color.
+    RiffPal,
+    /// One `RRGGBB` per line, like Lospec's .hex files.
+    Hex,
+    /// One `#RRGGBB` per line, with no newline after the last.
+    HexList,
+}
+
+#[derive(Serialize)]
+struct JsonColor {
+    hex: String,
+    rgb: [u8; 3],
+    /// The nearest CSS named color.
+    css_name: &'static str,
+}
+
+impl PaletteFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PaletteFormat::Json => "application/json",
+            PaletteFormat::CssVars => "text/css",
+            PaletteFormat::Gpl | PaletteFormat::Hex | PaletteFormat::HexList => {
+                "text/plain; charset=utf-8"
+            }
+            PaletteFormat::Act | PaletteFormat::RiffPal => "application/octet-stream",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PaletteFormat::Json => "json",
+            PaletteFormat::CssVars => "css",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Act => "act",
+            PaletteFormat::RiffPal => "pal",
+            PaletteFormat::Hex => "hex",
+            PaletteFormat::HexList => "txt",
+        }
+    }
+
+    /// ACT files only have room for 256 colors and RIFF palettes for 65535,
+    /// any more are left out.
+    pub fn serialize(self, colors: &[[u8; 3]]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            PaletteFormat::Json => Ok(serde_json::to_vec(
+                &colors
+                    .iter()
+                    .map(|&color| JsonColor {
+                        hex: hex_color(color),
+                        rgb: color,
+                        css_name: color::nearest_css_name(color),
+                    })
+                    .collect::<Vec<_>>(),
+            )?),
+
+            PaletteFormat::CssVars => {
+                let mut css = String::from(":root {\n");
+                for (i, &color) in colors.iter().enumerate() {
+                    css.push_str(&format!("  --color-{}: {};\n", i, hex_color(color)));
+                }
+                css.push_str("}\n");
+                Ok(css.into_bytes())
+            }
+
+            PaletteFormat::Gpl => {
+                let mut gpl = String::from("GIMP Palette\nName: palettizer\nColumns: 0\n#\n");
+                for &[r, g, b] in colors.iter() {
+                    gpl.push_str(&format!(
+                        "{:3} {:3} {:3}\t{}\n",
+                        r,
+                        g,
+                        b,
+                        hex_color([r, g, b])
+                    ));
+                }
+                Ok(gpl.into_bytes())
+            }
+
+            PaletteFormat::Act => {
+                let count = colors.len().min(ACT_COLORS);
+                let mut act = vec![0; ACT_COLORS * 3];
+                for (i, color) in colors.iter().take(count).enumerate() {
+                    act[i * 3..i * 3 + 3].copy_from_slice(color);
+                }
+                // the optional trailer with the number of colors and no
+                // transparent index
+                act.extend_from_slice(&(count as u16).to_be_bytes());
+                act.extend_from_slice(&0xffffu16.to_be_bytes());
+                Ok(act)
+            }
+
+            PaletteFormat::RiffPal => {
+                let count = colors.len().min(u16::MAX as usize);
+                // a LOGPALETTE: version 0x300, the count, then the entries
+                // with no flags
+                let mut data = Vec::with_capacity(4 + count * 4);
+                data.extend_from_slice(&0x300u16.to_le_bytes());
+                data.extend_from_slice(&(count as u16).to_le_bytes());
+                for &[r, g, b] in colors.iter().take(count) {
+                    data.extend_from_slice(&[r, g, b, 0]);
+                }
+
+                let mut pal = Vec::with_capacity(20 + data.len());
+                pal.extend_from_slice(b"RIFF");
+                pal.extend_from_slice(&(12 + data.len() as u32).to_le_bytes());
+                pal.extend_from_slice(b"PAL data");
+                pal.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                pal.extend_from_slice(&data);
+                Ok(pal)
+            }
+
+            PaletteFormat::Hex => {
+                let mut hex = String::new();
+                for &[r, g, b] in colors.iter() {
+                    hex.push_str(&format!("{:02x}{:02x}{:02x}\n", r, g, b));
+                }
+                Ok(hex.into_bytes())
+            }
+
+            PaletteFormat::HexList => Ok(colors
+                .iter()
+                .map(|&color| hex_color(color))
+                .collect::<Vec<_>>()
+                .join("\n")
+                .into_bytes()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PaletteAudit {
+    pub color_count: usize,
+    pub is_grayscale: bool,
+    pub too_few_colors: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PaletteComparison {
+    /// How many colors both have, out of how many either has.
+    pub jaccard_similarity: f64,
+    /// The mean CIE76 distance from each color in `palette_a` to the nearest
+    /// in `palette_b`.
+    pub mean_distance_a_to_b: f32,
+    /// The same from `palette_b` to `palette_a`.
+    pub mean_distance_b_to_a: f32,
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+}
+
+/// How different two palettes are, for seeing how much a palette changed.
+pub fn compare_palettes(a: &[[u8; 3]], b: &[[u8; 3]]) -> PaletteComparison {
+    let set_a = a.iter().copied().collect::<HashSet<_>>();
+    let set_b = b.iter().copied().collect::<HashSet<_>>();
+    let shared = set_a.intersection(&set_b).count();
+    let union = set_a.union(&set_b).count();
+
+    let lab_a = a
+        .iter()
+        .map(|&color| color::srgb_to_lab(color, Illuminant::D65))
+        .collect::<Vec<_>>();
+    let lab_b = b
+        .iter()
+        .map(|&color| color::srgb_to_lab(color, Illuminant::D65))
+        .collect::<Vec<_>>();
+    let mean_nearest = |from: &[[f32; 3]], to: &[[f32; 3]]| {
+        let total = from
+            .iter()
+            .map(|&lab| {
+                to.iter()
+                    .map(|&other| color::cie76(lab, other))
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .sum::<f32>();
+        total / from.len().max(1) as f32
+    };
+
+    let only = |colors: &[[u8; 3]], other: &HashSet<[u8; 3]>| -> Vec<String> {
+        colors
+            .iter()
+            .filter(|color| !other.contains(*color))
+            .map(|&color| hex_color(color))
+            .collect()
+    };
+
+    PaletteComparison {
+        jaccard_similarity: shared as f64 / union.max(1) as f64,
+        mean_distance_a_to_b: mean_nearest(&lab_a, &lab_b),
+        mean_distance_b_to_a: mean_nearest(&lab_b, &lab_a),
+        only_in_a: only(a, &set_b),
+        only_in_b: only(b, &set_a),
+    }
+}
+
+/// Check a palette for things that are probably mistakes.
+pub fn audit_palette(colors: &[[u8; 3]]) -> PaletteAudit {
+    PaletteAudit {
+        color_count: colors.len(),
+        is_grayscale: colors.iter().all(|[r, g, b]| r == g && g == b),
+        too_few_colors: colors.len() < 2,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LowContrastPair {
+    pub a: String,
+    pub b: String,
+    pub ratio: f64,
+}
+
+/// Every pair of colors whose WCAG contrast ratio is below `min_ratio`, in
+/// palette order.
+pub fn low_contrast_pairs(colors: &[[u8; 3]], min_ratio: f64) -> Vec<LowContrastPair> {
+    let mut pairs = Vec::new();
+    for (i, &a) in colors.iter().enumerate() {
+        for &b in &colors[i + 1..] {
+            let ratio = color::wcag_contrast_ratio(a, b);
+            if ratio < min_ratio {
+                pairs.push(LowContrastPair {
+                    a: hex_color(a),
+                    b: hex_color(b),
+                    ratio,
+                });
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolated_midpoints_are_halfway_in_lab() {
+        let a = [[0, 0, 0], [180, 80, 80], [30, 200, 90]];
+        let b = [[255, 255, 255], [80, 120, 180], [250, 240, 10]];
+        let steps = interpolate_palettes(&a, &b, 3, Illuminant::D65);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0], a);
+        assert_eq!(steps[2], b);
+
+        for i in 0..a.len() {
+            let lab = |color| color::srgb_to_lab(color, Illuminant::D65);
+            let middle = lab(steps[1][i]);
+            let to_a = color::cie76(middle, lab(a[i]));
+            let to_b = color::cie76(middle, lab(b[i]));
+            // the midpoint is rounded to an sRGB color
+            assert!((to_a - to_b).abs() < 1.0, "{}: {} vs {}", i, to_a, to_b);
+        }
+    }
+
+    #[test]
+    fn strips_pad_the_last_row_with_black() {
+        let colors = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+            [0, 255, 255],
+            [255, 0, 255],
+        ];
+        let strip = palette_strip(&colors, 4, 1);
+        assert_eq!(strip.dimensions(), (4, 2));
+        for (i, &[r, g, b]) in colors.iter().enumerate() {
+            let (x, y) = (i as u32 % 4, i as u32 / 4);
+            assert_eq!(strip.get_pixel(x, y), Rgba([r, g, b, 255]));
+        }
+        assert_eq!(strip.get_pixel(2, 1), Rgba([0, 0, 0, 255]));
+        assert_eq!(strip.get_pixel(3, 1), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn three_step_gray_gradient() {
+        let (start, end, count) = parse_gradient("#000000-#FFFFFF:3").unwrap();
+        let colors = gradient_palette(start, end, count, GradientSpace::Srgb);
+        assert_eq!(
+            colors.into_iter().map(hex_color).collect::<Vec<_>>(),
+            ["#000000", "#7F7F7F", "#FFFFFF"]
+        );
+    }
+
+    #[test]
+    fn riff_pal_with_rgbquad_entries() {
+        let colors = parse_riff_pal(include_bytes!("../testdata/rgbquad.pal")).unwrap();
+        assert_eq!(colors.len(), 256);
+        for (i, color) in colors.iter().enumerate() {
+            let i = i as u8;
+            assert_eq!(*color, Rgba([i, 255 - i, i.wrapping_mul(7), 255]));
+        }
+    }
+
+    #[test]
+    fn riff_pal_with_3_byte_entries() {
+        // there's an odd length chunk before the data chunk, too
+        let colors = parse_riff_pal(include_bytes!("../testdata/rgb.pal")).unwrap();
+        assert_eq!(colors.len(), 16);
+        for (i, color) in colors.iter().enumerate() {
+            let i = i as u8 * 16;
+            assert_eq!(*color, Rgba([i, i, 255 - i, 255]));
+        }
+    }
+
+    #[test]
+    fn hex_to_gpl_to_hex_round_trips() {
+        let hex = "; from lospec\nff0044\n#00ff88\n1a2b3c\n";
+        let colors = parse_text_palette(hex).unwrap();
+        assert_eq!(
+            colors,
+            vec![[0xff, 0x00, 0x44], [0x00, 0xff, 0x88], [0x1a, 0x2b, 0x3c]]
+        );
+
+        let gpl = PaletteFormat::Gpl.serialize(&colors).unwrap();
+        let from_gpl = parse_text_palette(std::str::from_utf8(&gpl).unwrap()).unwrap();
+        let hex_again = PaletteFormat::Hex.serialize(&from_gpl).unwrap();
+        let from_hex = parse_text_palette(std::str::from_utf8(&hex_again).unwrap()).unwrap();
+        assert_eq!(from_hex, colors);
+    }
+
+    #[test]
+    fn complement_of_red_is_cyan() {
+        assert_eq!(
+            complement_palette(&[[0xFF, 0x00, 0x00]]),
+            vec![[0x00, 0xFF, 0xFF]]
+        );
+    }
+
+    #[test]
+    fn two_step_cube_is_the_corners() {
+        let colors = cube_palette(2);
+        assert_eq!(colors.len(), 8);
+        assert_eq!(colors.iter().collect::<HashSet<_>>().len(), 8);
+        assert!(colors.iter().flatten().all(|&c| c == 0 || c == 255));
+    }
+
+    #[test]
+    fn inverting() {
+        assert_eq!(
+            invert_palette(&[[0xFF, 0xFF, 0xFF], [0xFF, 0x88, 0x00]]),
+            vec![[0x00, 0x00, 0x00], [0x00, 0x77, 0xFF]]
+        );
+    }
+
+    #[test]
+    fn shifting_clamps() {
+        assert_eq!(
+            shift_palette(&[[250, 10, 128]], 10, -20, 0, false),
+            vec![[255, 0, 128]]
+        );
+    }
+
+    #[test]
+    fn shifting_wraps() {
+        assert_eq!(
+            shift_palette(&[[250, 10, 128]], 10, -20, 255, true),
+            vec![[4, 246, 127]]
+        );
+    }
+
+    #[test]
+    fn monochrome_colors_share_a_hue() {
+        let colors = [
+            [200, 30, 40],
+            [20, 180, 60],
+            [40, 60, 220],
+            [250, 200, 20],
+            [120, 80, 160],
+            [90, 140, 100],
+        ];
+        for (color, original) in monochrome_palette(&colors, 200.0).into_iter().zip(colors) {
+            let [hue, saturation, lightness] = color::srgb_to_hsl(color);
+            let [_, original_saturation, original_lightness] = color::srgb_to_hsl(original);
+            assert!((hue - 200.0).abs() <= 1.0, "{:?} has hue {}", color, hue);
+            assert!((saturation - original_saturation).abs() < 0.02);
+            assert!((lightness - original_lightness).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn riff_pal_round_trips() {
+        let colors = vec![[1, 2, 3], [250, 128, 0]];
+        let pal = PaletteFormat::RiffPal.serialize(&colors).unwrap();
+        assert!(is_riff_pal(&pal));
+        let parsed = parse_riff_pal(&pal).unwrap();
+        assert_eq!(
+            parsed
+                .iter()
+                .map(|c| [c.0[0], c.0[1], c.0[2]])
+                .collect::<Vec<_>>(),
+            colors
+        );
+    }
+}
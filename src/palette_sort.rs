@@ -0,0 +1,108 @@
+//! Putting palettes in order.
+
+use crate::{color, quantize::palette_usage};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy)]
+pub enum SortBy {
+    Hue,
+    /// Relative luminance, the Y of XYZ.
+    Luminance,
+    Saturation,
+    Red,
+    Green,
+    Blue,
+}
+
+/// Sort the colors from lowest to highest. Colors that tie keep their order.
+pub fn sort_palette(colors: &mut [[u8; 3]], sort_by: SortBy) {
+    let key = |color: [u8; 3]| match sort_by {
+        SortBy::Hue => color::srgb_to_hsv(color)[0],
+        SortBy::Luminance => color::srgb_to_xyz(color)[1],
+        SortBy::Saturation => color::srgb_to_hsv(color)[1],
+        SortBy::Red => color[0] as f32,
+        SortBy::Green => color[1] as f32,
+        SortBy::Blue => color[2] as f32,
+    };
+    colors.sort_by(|&a, &b| key(a).total_cmp(&key(b)));
+}
+
+/// Sort the colors by how many pixels of the image would be palettized to
+/// each of them, most first. The counts are the same as `/palette/histogram`
+/// gives.
+pub fn sort_by_frequency(colors: &mut [[u8; 3]], histogram: &HashMap<[u8; 3], u64>) {
+    let (uses, _) = palette_usage(histogram, colors, 0);
+    let mut counted = colors.iter().copied().zip(uses).collect::<Vec<_>>();
+    counted.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    for (color, (sorted, _)) in colors.iter_mut().zip(counted) {
+        *color = sorted;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Rearrange {
+    /// Neighbors differ in as few bits of their 24 bit RGB as possible.
+    GrayCode,
+    /// The shortest path through the colors in RGB.
+    Tsp,
+}
+
+/// Reorder the colors so each is close to the one before it, starting from
+/// the first. Both ways are approximate, since the best order is a traveling
+/// salesman problem.
+pub fn rearrange_palette(colors: &mut [[u8; 3]], rearrange: Rearrange) {
+    match rearrange {
+        Rearrange::GrayCode => nearest_neighbor_path(colors, bit_changes),
+        Rearrange::Tsp => {
+            nearest_neighbor_path(colors, rgb_distance);
+            two_opt(colors);
+        }
+    }
+}
+
+fn bit_changes(a: [u8; 3], b: [u8; 3]) -> f32 {
+    ((a[0] ^ b[0]).count_ones() + (a[1] ^ b[1]).count_ones() + (a[2] ^ b[2]).count_ones()) as f32
+}
+
+fn rgb_distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+    let d = |c: usize| a[c] as f32 - b[c] as f32;
+    (d(0) * d(0) + d(1) * d(1) + d(2) * d(2)).sqrt()
+}
+
+/// Keep picking whichever remaining color is closest to the last one picked.
+fn nearest_neighbor_path(colors: &mut [[u8; 3]], distance: fn([u8; 3], [u8; 3]) -> f32) {
+    for i in 1..colors.len() {
+        let last = colors[i - 1];
+        let nearest = (i..colors.len())
+            .min_by(|&a, &b| distance(last, colors[a]).total_cmp(&distance(last, colors[b])))
+            .unwrap_or(i);
+        colors.swap(i, nearest);
+    }
+}
+
+/// Reverse stretches of the path for as long as doing so makes it shorter.
+/// The path doesn't loop back around, so the first color stays put.
+fn two_opt(colors: &mut [[u8; 3]]) {
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 1..colors.len().saturating_sub(1) {
+            for j in i + 1..colors.len() {
+                // reversing i..=j swaps the edges (i - 1, i) and (j, j + 1)
+                // for (i - 1, j) and (i, j + 1)
+                let before = rgb_distance(colors[i - 1], colors[i])
+                    + colors
+                        .get(j + 1)
+                        .map_or(0.0, |&next| rgb_distance(colors[j], next));
+                let after = rgb_distance(colors[i - 1], colors[j])
+                    + colors
+                        .get(j + 1)
+                        .map_or(0.0, |&next| rgb_distance(colors[i], next));
+                if after + 1e-3 < before {
+                    colors[i..=j].reverse();
+                    improved = true;
+                }
+            }
+        }
+    }
+}
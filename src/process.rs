@@ -0,0 +1,1300 @@
+//! The per-pixel work of palettizing an image.
+
+use crate::{
+    color::{self, ColorBlindness, Illuminant},
+    distance::{Distance, Matcher},
+    dither::{ChannelLevels, Dither},
+};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+pub struct Options {
+    /// Multiplies each channel, to warm up or cool down the image.
+    pub color_temp: Option<[f32; 3]>,
+    /// Degrees to turn each pixel's hue by, for palette swaps.
+    pub hue_rotate: f32,
+    /// Multiplies each pixel's saturation. 0 makes the image gray.
+    pub saturation: f32,
+    /// How to decide which palette color is nearest.
+    pub distance: Distance,
+    /// The white point of Lab distances.
+    pub illuminant: Illuminant,
+    /// Clip the palette's Lab values into the sRGB gamut before measuring.
+    pub lab_clamp: bool,
+    /// Pixels which are already closer than this to their nearest palette
+    /// color keep their original color. It's in the units of `distance`, so
+    /// the same number means a lot more for L1 than for CIEDE2000.
+    pub min_diff: u64,
+    /// Palettize the image as someone with this kind of color blindness
+    /// would see it.
+    pub simulate: Option<ColorBlindness>,
+    /// Only keep this many of the high bits of each channel, like hardware
+    /// with a small color depth. 8 keeps everything.
+    pub quantize_bits: u8,
+    /// Spread each pixel's difference from its palette color onto its
+    /// neighbors this way.
+    pub dither: Option<Dither>,
+    /// When dithering, go right to left on odd rows so the error doesn't all
+    /// drift the same way.
+    pub serpentine: bool,
+    /// Also dither alpha, to the nearest of these values, sorted. Its error is
+    /// spread on its own rather than with the color's.
+    pub alpha_levels: Option<Vec<u8>>,
+    /// Ordered dither each channel between the values the palette has for it,
+    /// instead of finding the nearest color. Good for palettes laid out along
+    /// the channels, like primaries.
+    pub per_channel_dither: bool,
+    /// Pixels which are already one of the palette's colors stay that color.
+    pub exact_match_passthrough: bool,
+    /// Afterwards, fill in single pixels that differ from all four of their
+    /// neighbors.
+    pub smooth_noise: bool,
+    /// Fully transparent pixels become this color, as if the image were on a
+    /// solid background.
+    pub background: Option<[u8; 3]>,
+    /// Give up if a single row takes longer than this.
+    pub row_timeout: Duration,
+    /// Pixels of exactly these colors always become the palette color they're
+    /// pinned to, however near they are to the others.
+    pub pins: HashMap<[u8; 3], [u8; 3]>,
+    pub channels: Channels,
+}
+
+/// Which channels [`palettize_image`] changes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channels {
+    Rgb,
+    /// Each pixel's alpha becomes the nearest of the palette colors'
+    /// luminances, like a grayscale palette for transparency. RGB is left
+    /// alone.
+    AlphaOnly,
+    /// Each palette color gets its luminance as alpha, like with
+    /// [`Channels::AlphaOnly`], and each pixel becomes whichever of them is
+    /// nearest in all four channels at once. Can't be dithered.
+    All,
+}
+
+/// Checking the clock every pixel would slow things down for nothing.
+const TIMEOUT_CHECK_COLUMNS: u32 = 100;
+
+#[derive(Debug)]
+pub struct RowTimeout {
+    pub row: u32,
+}
+
+impl std::fmt::Display for RowTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {} took too long to palettize", self.row)
+    }
+}
+
+impl std::error::Error for RowTimeout {}
+
+/// How far palettized pixels ended up from the colors they were adjusted to,
+/// by the distance they were palettized with. Pixels left alone because of
+/// `min_diff` or pinning count as 0.
+pub struct DiffStats {
+    min: f32,
+    max: f32,
+    total: f64,
+    pixels: u64,
+}
+
+impl DiffStats {
+    fn new() -> DiffStats {
+        DiffStats {
+            min: f32::INFINITY,
+            max: 0.0,
+            total: 0.0,
+            pixels: 0,
+        }
+    }
+
+    fn add(&mut self, diff: f32) {
+        self.min = self.min.min(diff);
+        self.max = self.max.max(diff);
+        self.total += diff as f64;
+        self.pixels += 1;
+    }
+
+    pub fn min(&self) -> f32 {
+        if self.pixels == 0 {
+            0.0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.pixels == 0 {
+            0.0
+        } else {
+            self.total / self.pixels as f64
+        }
+    }
+}
+
+/// Each pass of smoothing can uncover more noise, but not forever.
+const MAX_SMOOTH_PASSES: usize = 8;
+
+/// Everything that happens to a pixel before looking up its palette color.
+fn adjust(color: [u8; 3], options: &Options) -> [u8; 3] {
+    let mut color = color;
+    if let Some(multipliers) = options.color_temp {
+        color =
+            [0, 1, 2].map(|i| (color[i] as f32 * multipliers[i]).round().clamp(0.0, 255.0) as u8);
+    }
+    if options.hue_rotate != 0.0 || options.saturation != 1.0 {
+        let [hue, saturation, lightness] = color::srgb_to_hsl(color);
+        color = color::hsl_to_srgb([
+            hue + options.hue_rotate,
+            (saturation * options.saturation).clamp(0.0, 1.0),
+            lightness,
+        ]);
+    }
+    if let Some(kind) = options.simulate {
+        color = color::simulate_color_blindness(color, kind);
+    }
+    if options.quantize_bits < 8 {
+        let shift = 8 - options.quantize_bits;
+        color = color.map(|c| (c >> shift) << shift);
+    }
+    color
+}
+
+pub fn palettize_image(
+    image: &mut DynamicImage,
+    colors: &[[u8; 3]],
+    options: &Options,
+) -> Result<DiffStats, RowTimeout> {
+    if options.channels == Channels::AlphaOnly {
+        return Ok(palettize_alpha(image, colors));
+    }
+
+    let matcher = matcher(colors, options);
+    let stats = if let Some(dither) = options.dither {
+        diffuse_errors(image, &matcher, dither, options)?
+    } else if options.per_channel_dither {
+        dither_channels(image, &matcher, &ChannelLevels::new(colors), options)?
+    } else if options.channels == Channels::All {
+        let alphas = colors
+            .iter()
+            .map(|&color| color::luminance_level(color))
+            .collect::<Vec<_>>();
+        palettize_pixels(image, options, |color| {
+            palettize_rgba_pixel(color, &matcher, &alphas, options)
+        })?
+    } else {
+        palettize_pixels(image, options, |color| {
+            palettize_pixel(color, &matcher, options)
+        })?
+    };
+
+    if options.smooth_noise {
+        smooth_noise(image);
+    }
+
+    Ok(stats)
+}
+
+/// Snap each pixel's alpha to the nearest palette luminance. The stats are of
+/// how far alpha moved.
+fn palettize_alpha(image: &mut DynamicImage, colors: &[[u8; 3]]) -> DiffStats {
+    let mut levels = colors
+        .iter()
+        .map(|&color| color::luminance_level(color))
+        .collect::<Vec<_>>();
+    levels.sort_unstable();
+    levels.dedup();
+
+    let mut stats = DiffStats::new();
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let mut color = image.get_pixel(x, y);
+            let alpha = color.0[3];
+            let nearest = levels
+                .iter()
+                .copied()
+                .min_by_key(|level| level.abs_diff(alpha))
+                .unwrap_or(alpha);
+            stats.add(nearest.abs_diff(alpha) as f32);
+            color.0[3] = nearest;
+            image.put_pixel(x, y, color);
+        }
+    }
+    stats
+}
+
+fn palettize_pixels(
+    image: &mut DynamicImage,
+    options: &Options,
+    palettize: impl Fn(Rgba<u8>) -> (Rgba<u8>, f32),
+) -> Result<DiffStats, RowTimeout> {
+    let mut stats = DiffStats::new();
+    for y in 0..image.height() {
+        tracing::trace!("row {}", y);
+        let start = Instant::now();
+        for x in 0..image.width() {
+            if x % TIMEOUT_CHECK_COLUMNS == 0 && start.elapsed() > options.row_timeout {
+                return Err(RowTimeout { row: y });
+            }
+
+            let (color, diff) = palettize(image.get_pixel(x, y));
+            image.put_pixel(x, y, color);
+            stats.add(diff);
+        }
+    }
+    Ok(stats)
+}
+
+/// Dither each channel on its own. Whatever combination of channels comes out
+/// is then matched to the nearest palette color, since it might not be one.
+fn dither_channels(
+    image: &mut DynamicImage,
+    matcher: &Matcher,
+    levels: &ChannelLevels,
+    options: &Options,
+) -> Result<DiffStats, RowTimeout> {
+    let mut stats = DiffStats::new();
+    for y in 0..image.height() {
+        tracing::trace!("row {}", y);
+        let start = Instant::now();
+        for x in 0..image.width() {
+            if x % TIMEOUT_CHECK_COLUMNS == 0 && start.elapsed() > options.row_timeout {
+                return Err(RowTimeout { row: y });
+            }
+
+            let color = fill_background(image.get_pixel(x, y), options);
+            let rgb = [color.0[0], color.0[1], color.0[2]];
+            let [r, g, b] = if let Some(&pinned) = options.pins.get(&rgb) {
+                stats.add(0.0);
+                pinned
+            } else {
+                let adjusted = adjust(rgb, options);
+                let (nearest, diff) = matcher.nearest(levels.dither(adjusted, x, y));
+                stats.add(diff);
+                nearest
+            };
+            image.put_pixel(x, y, Rgba([r, g, b, color.0[3]]));
+        }
+    }
+    Ok(stats)
+}
+
+/// Palettize pixels in order, adding the error left over from the ones before
+/// to each before picking its palette color.
+fn diffuse_errors(
+    image: &mut DynamicImage,
+    matcher: &Matcher,
+    dither: Dither,
+    options: &Options,
+) -> Result<DiffStats, RowTimeout> {
+    let mut stats = DiffStats::new();
+    let (width, height) = image.dimensions();
+    let mut errors = vec![[0.0f32; 3]; width as usize * height as usize];
+    let mut alpha_errors = vec![0.0f32; width as usize * height as usize];
+    let index = |x: u32, y: u32| y as usize * width as usize + x as usize;
+
+    for y in 0..height {
+        tracing::trace!("row {}", y);
+        let start = Instant::now();
+        let backwards = options.serpentine && y % 2 == 1;
+
+        for i in 0..width {
+            if i % TIMEOUT_CHECK_COLUMNS == 0 && start.elapsed() > options.row_timeout {
+                return Err(RowTimeout { row: y });
+            }
+
+            let x = if backwards { width - 1 - i } else { i };
+            let mut color = fill_background(image.get_pixel(x, y), options);
+            if let Some(levels) = &options.alpha_levels {
+                let wanted = color.0[3] as f32 + alpha_errors[index(x, y)];
+                let alpha = nearest_level(levels, wanted);
+                color.0[3] = alpha;
+                let error = wanted - alpha as f32;
+                for (nx, ny, weight) in neighbors(dither, backwards, x, y, width, height) {
+                    alpha_errors[index(nx, ny)] += error * weight;
+                }
+            }
+
+            let rgb = [color.0[0], color.0[1], color.0[2]];
+            if let Some(&[r, g, b]) = options.pins.get(&rgb) {
+                image.put_pixel(x, y, Rgba([r, g, b, color.0[3]]));
+                stats.add(0.0);
+                continue;
+            }
+
+            let adjusted = adjust(rgb, options);
+            let carried = errors[index(x, y)];
+            let wanted = [0, 1, 2].map(|c| adjusted[c] as f32 + carried[c]);
+            let (nearest, diff) =
+                matcher.nearest(wanted.map(|c| c.round().clamp(0.0, 255.0) as u8));
+            let [r, g, b] = if diff < options.min_diff as f32 {
+                stats.add(0.0);
+                adjusted
+            } else {
+                stats.add(diff);
+                nearest
+            };
+            image.put_pixel(x, y, Rgba([r, g, b, color.0[3]]));
+
+            let error = [
+                wanted[0] - r as f32,
+                wanted[1] - g as f32,
+                wanted[2] - b as f32,
+            ];
+            for (nx, ny, weight) in neighbors(dither, backwards, x, y, width, height) {
+                let neighbor = &mut errors[index(nx, ny)];
+                for c in 0..3 {
+                    neighbor[c] += error[c] * weight;
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// The pixels in the image that the kernel spreads `(x, y)`'s error onto, and
+/// how much of it each gets.
+fn neighbors(
+    dither: Dither,
+    backwards: bool,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> impl Iterator<Item = (u32, u32, f32)> {
+    dither.kernel().iter().filter_map(move |&(dx, dy, weight)| {
+        let dx = if backwards { -dx } else { dx };
+        let nx = u32::try_from(x as i64 + dx).ok()?;
+        let ny = y + dy;
+        (nx < width && ny < height).then_some((nx, ny, weight))
+    })
+}
+
+/// The level nearest `value`, out of sorted `levels`.
+fn nearest_level(levels: &[u8], value: f32) -> u8 {
+    levels
+        .iter()
+        .copied()
+        .min_by(|&a, &b| {
+            (a as f32 - value)
+                .abs()
+                .total_cmp(&(b as f32 - value).abs())
+        })
+        .unwrap_or(value.round().clamp(0.0, 255.0) as u8)
+}
+
+/// One row of the palettized image as RGBA bytes, leaving the image alone.
+pub fn palettize_row(
+    image: &DynamicImage,
+    y: u32,
+    colors: &[[u8; 3]],
+    options: &Options,
+) -> Result<Vec<u8>, RowTimeout> {
+    let matcher = matcher(colors, options);
+    let start = Instant::now();
+    let mut row = Vec::with_capacity(image.width() as usize * 4);
+    for x in 0..image.width() {
+        if x % TIMEOUT_CHECK_COLUMNS == 0 && start.elapsed() > options.row_timeout {
+            return Err(RowTimeout { row: y });
+        }
+
+        let (color, _) = palettize_pixel(image.get_pixel(x, y), &matcher, options);
+        row.extend_from_slice(&color.0);
+    }
+    Ok(row)
+}
+
+fn fill_background(color: Rgba<u8>, options: &Options) -> Rgba<u8> {
+    match options.background {
+        Some([r, g, b]) if color.0[3] == 0 => Rgba([r, g, b, 255]),
+        _ => color,
+    }
+}
+
+fn matcher<'colors>(colors: &'colors [[u8; 3]], options: &Options) -> Matcher<'colors> {
+    let mut matcher = Matcher::new(colors, options.distance, options.illuminant);
+    if options.lab_clamp {
+        matcher = matcher.clipping_lab();
+    }
+    if options.exact_match_passthrough {
+        matcher.passing_exact_matches()
+    } else {
+        matcher
+    }
+}
+
+/// The palettized pixel, and how far it is from the adjusted color.
+fn palettize_pixel(color: Rgba<u8>, matcher: &Matcher, options: &Options) -> (Rgba<u8>, f32) {
+    let color = fill_background(color, options);
+    if let Some(&[r, g, b]) = options.pins.get(&[color.0[0], color.0[1], color.0[2]]) {
+        return (Rgba([r, g, b, color.0[3]]), 0.0);
+    }
+    let adjusted = adjust([color.0[0], color.0[1], color.0[2]], options);
+    let (nearest, diff) = matcher.nearest(adjusted);
+
+    let ([r, g, b], diff) = if diff < options.min_diff as f32 {
+        (adjusted, 0.0)
+    } else {
+        (nearest, diff)
+    };
+    (Rgba([r, g, b, color.0[3]]), diff)
+}
+
+/// Like [`palettize_pixel`], but alpha is matched along with the color, to
+/// palette colors with `alphas`.
+fn palettize_rgba_pixel(
+    color: Rgba<u8>,
+    matcher: &Matcher,
+    alphas: &[u8],
+    options: &Options,
+) -> (Rgba<u8>, f32) {
+    let color = fill_background(color, options);
+    let alpha = color.0[3];
+    if let Some(&[r, g, b]) = options.pins.get(&[color.0[0], color.0[1], color.0[2]]) {
+        return (Rgba([r, g, b, alpha]), 0.0);
+    }
+    let adjusted = adjust([color.0[0], color.0[1], color.0[2]], options);
+    let ([r, g, b], nearest_alpha, diff) = matcher.nearest_rgba(adjusted, alpha, alphas);
+
+    if diff < options.min_diff as f32 {
+        let [r, g, b] = adjusted;
+        (Rgba([r, g, b, alpha]), 0.0)
+    } else {
+        (Rgba([r, g, b, nearest_alpha]), diff)
+    }
+}
+
+/// The palette colors nearest one pixel, for seeing why it palettized the way
+/// it did.
+pub struct Candidates {
+    pub x: u32,
+    pub y: u32,
+    pub input: [u8; 3],
+    /// Closest first, measured from the input after it's been adjusted.
+    pub nearest: Vec<([u8; 3], f32)>,
+}
+
+/// The `n` nearest palette colors of every pixel in the `width` by `height`
+/// region at `(x, y)`, which has to be inside the image.
+pub fn nearest_candidates(
+    image: &DynamicImage,
+    colors: &[[u8; 3]],
+    options: &Options,
+    (x, y, width, height): (u32, u32, u32, u32),
+    n: usize,
+) -> Vec<Candidates> {
+    let matcher = matcher(colors, options);
+    let mut candidates = Vec::new();
+    for y in y..y + height {
+        for x in x..x + width {
+            let color = image.get_pixel(x, y);
+            let filled = fill_background(color, options);
+            let adjusted = adjust([filled.0[0], filled.0[1], filled.0[2]], options);
+            candidates.push(Candidates {
+                x,
+                y,
+                input: [color.0[0], color.0[1], color.0[2]],
+                nearest: matcher.nearest_n(adjusted, n),
+            });
+        }
+    }
+    candidates
+}
+
+/// Palettize each tile of the image with whichever of the palettes fits it
+/// best, like consoles that give each sprite tile one of a few palettes. Tiles
+/// on the right and bottom edges may be smaller. Returns the index of the
+/// palette each tile got, by row and then column.
+pub fn palettize_tiles(
+    image: &mut DynamicImage,
+    palettes: &[Vec<[u8; 3]>],
+    tile_width: u32,
+    tile_height: u32,
+    options: &Options,
+) -> Vec<Vec<usize>> {
+    let matchers = palettes
+        .iter()
+        .map(|colors| matcher(colors, options))
+        .collect::<Vec<_>>();
+    let mut assignments = Vec::new();
+
+    for tile_y in (0..image.height()).step_by(tile_height as usize) {
+        let mut row = Vec::new();
+        let height = tile_height.min(image.height() - tile_y);
+
+        for tile_x in (0..image.width()).step_by(tile_width as usize) {
+            let width = tile_width.min(image.width() - tile_x);
+            let pixels = || {
+                (tile_y..tile_y + height)
+                    .flat_map(move |y| (tile_x..tile_x + width).map(move |x| (x, y)))
+            };
+
+            // each palette is compared over the same pixels, so the total
+            // error ranks them the same as the mean would
+            let error = |matcher: &Matcher| {
+                pixels()
+                    .map(|(x, y)| {
+                        let color = fill_background(image.get_pixel(x, y), options);
+                        let adjusted = adjust([color.0[0], color.0[1], color.0[2]], options);
+                        matcher.nearest(adjusted).1
+                    })
+                    .sum::<f32>()
+            };
+            let errors = matchers.iter().map(error).collect::<Vec<_>>();
+            let best = (0..errors.len())
+                .min_by(|&a, &b| errors[a].total_cmp(&errors[b]))
+                .expect("need at least one palette");
+
+            for (x, y) in pixels() {
+                let color = image.get_pixel(x, y);
+                image.put_pixel(x, y, palettize_pixel(color, &matchers[best], options).0);
+            }
+            row.push(best);
+        }
+
+        assignments.push(row);
+    }
+
+    if options.smooth_noise {
+        smooth_noise(image);
+    }
+
+    assignments
+}
+
+/// Give pixels whose four neighbors all share a different color that color,
+/// until there are none left. Pixels on the edges are left alone.
+fn smooth_noise(image: &mut DynamicImage) {
+    let rgb = |color: Rgba<u8>| [color.0[0], color.0[1], color.0[2]];
+
+    for pass in 0..MAX_SMOOTH_PASSES {
+        let before = image.clone();
+        let mut changed = false;
+
+        for y in 1..image.height().saturating_sub(1) {
+            for x in 1..image.width().saturating_sub(1) {
+                let up = rgb(before.get_pixel(x, y - 1));
+                let neighbors = [
+                    rgb(before.get_pixel(x - 1, y)),
+                    rgb(before.get_pixel(x + 1, y)),
+                    rgb(before.get_pixel(x, y + 1)),
+                ];
+                let color = before.get_pixel(x, y);
+
+                if neighbors.iter().all(|&neighbor| neighbor == up) && rgb(color) != up {
+                    let [r, g, b] = up;
+                    image.put_pixel(x, y, Rgba([r, g, b, color.0[3]]));
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            tracing::trace!("smoothed noise in {} passes", pass);
+            break;
+        }
+    }
+}
+
+/// Draw the color on every fully transparent pixel which is `(dx, dy)` away
+/// from one that isn't.
+pub fn add_drop_shadow(image: &mut DynamicImage, [r, g, b]: [u8; 3], (dx, dy): (i64, i64)) {
+    let before = image.clone();
+    let transparent = |x: u32, y: u32| before.get_pixel(x, y).0[3] == 0;
+
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if transparent(x, y) {
+                continue;
+            }
+
+            let (Ok(sx), Ok(sy)) = (u32::try_from(x as i64 + dx), u32::try_from(y as i64 + dy))
+            else {
+                continue;
+            };
+            if sx < image.width() && sy < image.height() && transparent(sx, sy) {
+                image.put_pixel(sx, sy, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+}
+
+/// Draw the color on every fully transparent pixel next to one that isn't,
+/// above, below, or to either side.
+pub fn add_outline(image: &mut DynamicImage, [r, g, b]: [u8; 3]) {
+    let before = image.clone();
+    let opaque = |x: u32, y: u32| before.get_pixel(x, y).0[3] != 0;
+
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if opaque(x, y) {
+                continue;
+            }
+
+            let touches = (x > 0 && opaque(x - 1, y))
+                || (x + 1 < image.width() && opaque(x + 1, y))
+                || (y > 0 && opaque(x, y - 1))
+                || (y + 1 < image.height() && opaque(x, y + 1));
+            if touches {
+                image.put_pixel(x, y, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+}
+
+/// Draw a line of the color down every column which is a multiple of
+/// `width`, and across every row which is a multiple of `height`, except the
+/// first.
+pub fn draw_grid(image: &mut DynamicImage, width: u32, height: u32, [r, g, b]: [u8; 3]) {
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            if (x > 0 && x % width == 0) || (y > 0 && y % height == 0) {
+                image.put_pixel(x, y, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+}
+
+pub struct RecolorRule {
+    pub from: [u8; 3],
+    /// How far, by L1 distance, a pixel may be from `from` and still match.
+    pub tolerance: u64,
+    pub to: [u8; 3],
+}
+
+/// Palettize the image, except pixels that match one of the rules become the
+/// palette color nearest its `to` color. The first rule a pixel matches wins.
+/// Pixels are matched by their color before palettizing.
+pub fn recolor_image(
+    image: &mut DynamicImage,
+    colors: &[[u8; 3]],
+    rules: &[RecolorRule],
+    options: &Options,
+) -> Result<(), RowTimeout> {
+    let matcher = matcher(colors, options);
+    let targets = rules
+        .iter()
+        .map(|rule| matcher.nearest(rule.to).0)
+        .collect::<Vec<_>>();
+
+    let mut recolored = Vec::new();
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let color = image.get_pixel(x, y);
+            let rgb = [color.0[0], color.0[1], color.0[2]];
+            let rule = rules.iter().position(|rule| {
+                let diff = (0..3)
+                    .map(|c| rgb[c].abs_diff(rule.from[c]) as u64)
+                    .sum::<u64>();
+                diff <= rule.tolerance
+            });
+            if let Some(rule) = rule {
+                let [r, g, b] = targets[rule];
+                recolored.push((x, y, Rgba([r, g, b, color.0[3]])));
+            }
+        }
+    }
+
+    palettize_image(image, colors, options)?;
+    for (x, y, color) in recolored {
+        image.put_pixel(x, y, color);
+    }
+
+    Ok(())
+}
+
+/// Draw the watermark over the image with its top left corner at `(x, y)`,
+/// blending by both of their alpha channels, with the watermark's scaled by
+/// `alpha`. Whatever's off the edge of the image is left out.
+pub fn overlay_watermark(
+    image: &mut DynamicImage,
+    watermark: &DynamicImage,
+    (x, y): (i64, i64),
+    alpha: f32,
+) {
+    for wy in 0..watermark.height() {
+        for wx in 0..watermark.width() {
+            let (Ok(ix), Ok(iy)) = (u32::try_from(x + wx as i64), u32::try_from(y + wy as i64))
+            else {
+                continue;
+            };
+            if ix >= image.width() || iy >= image.height() {
+                continue;
+            }
+
+            let over = watermark.get_pixel(wx, wy);
+            let under = image.get_pixel(ix, iy);
+            let over_alpha = over.0[3] as f32 / 255.0 * alpha;
+            let under_alpha = under.0[3] as f32 / 255.0;
+            let out_alpha = over_alpha + under_alpha * (1.0 - over_alpha);
+            if out_alpha == 0.0 {
+                continue;
+            }
+
+            let blend = |c: usize| {
+                let color = (over.0[c] as f32 * over_alpha
+                    + under.0[c] as f32 * under_alpha * (1.0 - over_alpha))
+                    / out_alpha;
+                color.round().clamp(0.0, 255.0) as u8
+            };
+            let out_alpha = (out_alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+            image.put_pixel(ix, iy, Rgba([blend(0), blend(1), blend(2), out_alpha]));
+        }
+    }
+}
+
+/// Replace pixels which exactly match a color in the map. Unlike palettizing,
+/// every other pixel is left alone.
+pub fn remap_image(image: &mut DynamicImage, color_map: &HashMap<[u8; 3], [u8; 3]>) {
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let color = image.get_pixel(x, y);
+            if let Some([r, g, b]) = color_map.get(&[color.0[0], color.0[1], color.0[2]]) {
+                image.put_pixel(x, y, Rgba([*r, *g, *b, color.0[3]]));
+            }
+        }
+    }
+}
+
+/// The closest palette color and its distance from `color`.
+pub fn nearest_color(colors: &[[u8; 3]], color: [u8; 3]) -> ([u8; 3], u64) {
+    let mut min_diff = u64::MAX;
+    let mut min_color = [0, 0, 0];
+    for palette_color in colors.iter() {
+        let diff = color[0].abs_diff(palette_color[0]) as u64
+            + color[1].abs_diff(palette_color[1]) as u64
+            + color[2].abs_diff(palette_color[2]) as u64;
+        if diff < min_diff {
+            min_diff = diff;
+            min_color = *palette_color;
+        }
+    }
+    (min_color, min_diff)
+}
+
+/// Give each tile the index of the first tile with exactly the same pixels,
+/// counting unique tiles in order, like packing a tileset for a console.
+/// Tiles on the right and bottom edges may be smaller. Returns the index of
+/// each tile by row and then column, and how many unique tiles there are.
+pub fn dedup_tiles(
+    image: &DynamicImage,
+    tile_width: u32,
+    tile_height: u32,
+) -> (Vec<Vec<usize>>, usize) {
+    let mut unique = HashMap::<(u32, u32, Vec<u8>), usize>::new();
+    let mut tile_map = Vec::new();
+
+    for tile_y in (0..image.height()).step_by(tile_height as usize) {
+        let mut row = Vec::new();
+        let height = tile_height.min(image.height() - tile_y);
+
+        for tile_x in (0..image.width()).step_by(tile_width as usize) {
+            let width = tile_width.min(image.width() - tile_x);
+            let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+            for y in tile_y..tile_y + height {
+                for x in tile_x..tile_x + width {
+                    pixels.extend_from_slice(&image.get_pixel(x, y).0);
+                }
+            }
+
+            let next = unique.len();
+            row.push(*unique.entry((width, height, pixels)).or_insert(next));
+        }
+        tile_map.push(row);
+    }
+
+    let count = unique.len();
+    (tile_map, count)
+}
+
+/// The most common color in a rectangle of the image, and how many different
+/// colors it has. Ties go to the smallest color.
+pub fn region_colors(
+    image: &DynamicImage,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> ([u8; 3], usize) {
+    let mut counts = HashMap::<[u8; 3], u64>::new();
+    for y in y..y + height {
+        for x in x..x + width {
+            let Rgba([r, g, b, _]) = image.get_pixel(x, y);
+            *counts.entry([r, g, b]).or_default() += 1;
+        }
+    }
+
+    let dominant = counts
+        .iter()
+        .max_by_key(|&(&color, &count)| (count, std::cmp::Reverse(color)))
+        .map(|(&color, _)| color)
+        .unwrap_or_default();
+    (dominant, counts.len())
+}
+
+/// Which pixels are reached by flood filling out from each seed, through
+/// neighbors within `tolerance` of the seed's color by L1 distance. The mask
+/// is by row, then column. Seeds have to be inside the image.
+pub fn flood_fill_mask(image: &DynamicImage, seeds: &[(u32, u32)], tolerance: u32) -> Vec<bool> {
+    let (width, height) = image.dimensions();
+    let rgb = |x: u32, y: u32| {
+        let color = image.get_pixel(x, y);
+        [color.0[0], color.0[1], color.0[2]]
+    };
+    let mut mask = vec![false; width as usize * height as usize];
+
+    for &(seed_x, seed_y) in seeds {
+        let seed = rgb(seed_x, seed_y);
+        let mut stack = vec![(seed_x, seed_y)];
+        while let Some((x, y)) = stack.pop() {
+            let i = y as usize * width as usize + x as usize;
+            if mask[i] {
+                continue;
+            }
+            let color = rgb(x, y);
+            let diff = (0..3)
+                .map(|c| color[c].abs_diff(seed[c]) as u32)
+                .sum::<u32>();
+            if diff > tolerance {
+                continue;
+            }
+
+            mask[i] = true;
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if x + 1 < width {
+                stack.push((x + 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            if y + 1 < height {
+                stack.push((x, y + 1));
+            }
+        }
+    }
+    mask
+}
+
+/// Make every pixel of exactly `color` fully transparent, like hardware that
+/// treats one palette entry as see-through.
+pub fn make_transparent(image: &mut DynamicImage, color: [u8; 3]) {
+    // otherwise the alpha would be dropped on the way in
+    if !image.color().has_alpha() {
+        *image = DynamicImage::ImageRgba8(image.to_rgba8());
+    }
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let mut pixel = image.get_pixel(x, y);
+            if pixel.0[..3] == color {
+                pixel.0[3] = 0;
+                image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+}
+
+/// The palette's colors which show up in the image, in palette order.
+pub fn used_colors(image: &DynamicImage, colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    let present = image
+        .pixels()
+        .map(|(_, _, color)| [color.0[0], color.0[1], color.0[2]])
+        .collect::<HashSet<_>>();
+    colors
+        .iter()
+        .copied()
+        .filter(|color| present.contains(color))
+        .collect()
+}
+
+/// Crop out the center of the image, or all of it if it's smaller than the
+/// requested size.
+pub fn center_crop(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let width = width.min(image.width());
+    let height = height.min(image.height());
+    image.crop_imm(
+        (image.width() - width) / 2,
+        (image.height() - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Put the image in the middle of a square as big as its longest side, with
+/// the rest filled in.
+pub fn pad_to_square(image: &DynamicImage, fill: Rgba<u8>) -> DynamicImage {
+    let size = image.width().max(image.height());
+    pad(
+        image,
+        (size, size),
+        ((size - image.width()) / 2, (size - image.height()) / 2),
+        fill,
+    )
+}
+
+/// Put the image in the top left of a bigger one, with the rest filled in.
+pub fn pad_to_size(image: &DynamicImage, width: u32, height: u32, fill: Rgba<u8>) -> DynamicImage {
+    pad(image, (width, height), (0, 0), fill)
+}
+
+fn pad(
+    image: &DynamicImage,
+    (width, height): (u32, u32),
+    (x, y): (u32, u32),
+    fill: Rgba<u8>,
+) -> DynamicImage {
+    let mut padded = DynamicImage::new_rgba8(width, height);
+    for py in 0..height {
+        for px in 0..width {
+            padded.put_pixel(px, py, fill);
+        }
+    }
+
+    padded
+        .copy_from(image, x, y)
+        .expect("the image fits in the padding");
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What palettizing does with none of the form fields set.
+    fn options() -> Options {
+        Options {
+            color_temp: None,
+            hue_rotate: 0.0,
+            saturation: 1.0,
+            distance: Distance::L1,
+            illuminant: Illuminant::D65,
+            lab_clamp: false,
+            min_diff: 0,
+            simulate: None,
+            quantize_bits: 8,
+            dither: None,
+            serpentine: false,
+            alpha_levels: None,
+            per_channel_dither: false,
+            exact_match_passthrough: false,
+            smooth_noise: false,
+            background: None,
+            row_timeout: Duration::from_secs(60),
+            pins: HashMap::new(),
+            channels: Channels::Rgb,
+        }
+    }
+
+    static BLACK_AND_WHITE: &[[u8; 3]] = &[[0, 0, 0], [255, 255, 255]];
+
+    /// Black to white, left to right.
+    fn gray_gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 16, |x, _| {
+            image::Rgb([x as u8; 3])
+        }))
+    }
+
+    fn dithered(dither: Dither, serpentine: bool) -> DynamicImage {
+        let mut image = gray_gradient();
+        let options = Options {
+            dither: Some(dither),
+            serpentine,
+            ..options()
+        };
+        palettize_image(&mut image, BLACK_AND_WHITE, &options).unwrap();
+        image
+    }
+
+    #[test]
+    fn serpentine_dithers_differently() {
+        let forwards = dithered(Dither::FloydSteinberg, false);
+        let serpentine = dithered(Dither::FloydSteinberg, true);
+        assert!(forwards.as_bytes() != serpentine.as_bytes());
+    }
+
+    /// Peak signal to noise ratio of the gray levels, in decibels.
+    fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        let (a, b) = (a.to_luma8(), b.to_luma8());
+        let mse = a
+            .pixels()
+            .zip(b.pixels())
+            .map(|(a, b)| (a.0[0] as f64 - b.0[0] as f64).powi(2))
+            .sum::<f64>()
+            / a.len() as f64;
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    #[test]
+    fn stucki_is_closer_than_floyd_steinberg() {
+        let original = gray_gradient();
+        let floyd_steinberg = psnr(&original, &dithered(Dither::FloydSteinberg, false));
+        let stucki = psnr(&original, &dithered(Dither::Stucki, false));
+        assert!(
+            stucki > floyd_steinberg,
+            "{} vs {}",
+            stucki,
+            floyd_steinberg
+        );
+    }
+
+    /// A transparent image with one opaque white pixel.
+    fn one_pixel(width: u32, height: u32, x: u32, y: u32) -> DynamicImage {
+        let mut image = DynamicImage::new_rgba8(width, height);
+        image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+        image
+    }
+
+    /// Where the pixels of exactly this color are.
+    fn pixels_of(image: &DynamicImage, color: Rgba<u8>) -> Vec<(u32, u32)> {
+        image
+            .pixels()
+            .filter(|&(_, _, pixel)| pixel == color)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn one_pixel_gets_four_outline_pixels() {
+        let mut image = one_pixel(5, 5, 2, 2);
+        add_outline(&mut image, [255, 0, 0]);
+        assert_eq!(
+            pixels_of(&image, Rgba([255, 0, 0, 255])),
+            vec![(2, 1), (1, 2), (3, 2), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn shadows_fall_down_and_right() {
+        let mut image = one_pixel(10, 10, 5, 5);
+        add_drop_shadow(&mut image, [0, 0, 0], (1, 1));
+        assert_eq!(pixels_of(&image, Rgba([0, 0, 0, 255])), vec![(6, 6)]);
+    }
+
+    #[test]
+    fn shadows_stay_off_opaque_pixels() {
+        let mut image = one_pixel(10, 10, 5, 5);
+        image.put_pixel(6, 6, Rgba([255, 255, 255, 255]));
+        add_drop_shadow(&mut image, [0, 0, 0], (1, 1));
+        assert_eq!(pixels_of(&image, Rgba([0, 0, 0, 255])), vec![(7, 7)]);
+    }
+
+    #[test]
+    fn alpha_only_leaves_rgb_unchanged() {
+        let mut image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgba([10, 20, 30, 100])
+            } else {
+                Rgba([200, 100, 50, 240])
+            }
+        }));
+        let options = Options {
+            channels: Channels::AlphaOnly,
+            ..options()
+        };
+        palettize_image(&mut image, BLACK_AND_WHITE, &options).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([10, 20, 30, 0]));
+        assert_eq!(image.get_pixel(1, 0), Rgba([200, 100, 50, 255]));
+    }
+
+    #[test]
+    fn all_channels_are_matched_together() {
+        // white on its own and alpha 0 on its own are nearest, but white
+        // comes with alpha 255, which is still nearer than black with 0
+        let mut image =
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, Rgba([250, 250, 250, 0])));
+        let options = Options {
+            channels: Channels::All,
+            ..options()
+        };
+        palettize_image(&mut image, BLACK_AND_WHITE, &options).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn pixels_within_min_diff_are_left_alone() {
+        // 5 away from black by L1, and 300 away
+        let mut image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+            image::Rgb(if x == 0 { [2, 2, 1] } else { [100, 100, 100] })
+        }));
+        let options = Options {
+            min_diff: 10,
+            ..options()
+        };
+        palettize_image(&mut image, BLACK_AND_WHITE, &options).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([2, 2, 1, 255]));
+        assert_eq!(image.get_pixel(1, 0), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn palette_colors_have_no_diff() {
+        let mut image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 4, |x, y| {
+            image::Rgb([((x + y) % 2) as u8 * 255; 3])
+        }));
+        let stats = palettize_image(&mut image, BLACK_AND_WHITE, &options()).unwrap();
+        assert_eq!(stats.min(), 0.0);
+        assert_eq!(stats.max(), 0.0);
+        assert_eq!(stats.mean(), 0.0);
+    }
+
+    #[test]
+    fn padding_to_square_centers_the_image() {
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            10,
+            6,
+            Rgba([255, 255, 255, 255]),
+        ));
+        let padded = pad_to_square(&image, Rgba([0, 0, 0, 255]));
+        assert_eq!(padded.dimensions(), (10, 10));
+        for (x, y, pixel) in padded.pixels() {
+            let inside = (2..8).contains(&y);
+            let expected = if inside { 255 } else { 0 };
+            assert_eq!(
+                pixel,
+                Rgba([expected, expected, expected, 255]),
+                "{}, {}",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn smoothing_absorbs_an_isolated_pixel() {
+        let mut image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(5, 5, |x, y| {
+            image::Rgb([if (x, y) == (2, 2) { 255 } else { 0 }; 3])
+        }));
+        let options = Options {
+            smooth_noise: true,
+            ..options()
+        };
+        palettize_image(&mut image, BLACK_AND_WHITE, &options).unwrap();
+        assert!(pixels_of(&image, Rgba([255, 255, 255, 255])).is_empty());
+        assert_eq!(pixels_of(&image, Rgba([0, 0, 0, 255])).len(), 25);
+    }
+
+    #[test]
+    fn per_channel_dither_mixes_along_each_channel() {
+        let corners = [0, 255]
+            .into_iter()
+            .flat_map(|r| [0, 255].into_iter().map(move |g| (r, g)))
+            .flat_map(|(r, g)| [0, 255].into_iter().map(move |b| [r, g, b]))
+            .collect::<Vec<_>>();
+        let purple = || {
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([128, 0, 255])))
+        };
+
+        // magenta is the nearest, by 1
+        let mut global = purple();
+        palettize_image(&mut global, &corners, &options()).unwrap();
+        assert_eq!(pixels_of(&global, Rgba([255, 0, 255, 255])).len(), 64);
+
+        // red is about halfway, so half the pixels get it
+        let mut per_channel = purple();
+        let options = Options {
+            per_channel_dither: true,
+            ..options()
+        };
+        palettize_image(&mut per_channel, &corners, &options).unwrap();
+        assert_eq!(pixels_of(&per_channel, Rgba([255, 0, 255, 255])).len(), 32);
+        assert_eq!(pixels_of(&per_channel, Rgba([0, 0, 255, 255])).len(), 32);
+    }
+
+    #[test]
+    fn opaque_watermarks_cover_what_they_overlap() {
+        let mut image =
+            DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255])));
+        let watermark = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            4,
+            Rgba([255, 255, 255, 255]),
+        ));
+        overlay_watermark(&mut image, &watermark, (0, 0), 1.0);
+        let white = pixels_of(&image, Rgba([255, 255, 255, 255]));
+        assert_eq!(white.len(), 16);
+        assert!(white.iter().all(|&(x, y)| x < 4 && y < 4));
+        assert_eq!(pixels_of(&image, Rgba([0, 0, 0, 255])).len(), 48);
+    }
+
+    #[test]
+    fn transparent_pixels_take_the_background() {
+        let mut image = DynamicImage::new_rgba8(1, 1);
+        let options = Options {
+            background: Some([255, 255, 255]),
+            ..options()
+        };
+        let palette = [[0, 0, 0], [200, 200, 200], [255, 0, 0]];
+        palettize_image(&mut image, &palette, &options).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([200, 200, 200, 255]));
+    }
+
+    #[test]
+    fn quantizing_keeps_the_high_bits() {
+        let options = Options {
+            quantize_bits: 3,
+            ..options()
+        };
+        assert_eq!(adjust([255, 255, 255], &options), [224, 224, 224]);
+    }
+
+    #[test]
+    fn red_turned_a_third_of_the_way_is_green() {
+        let mut image =
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1, 1, image::Rgb([255, 0, 0])));
+        let options = Options {
+            hue_rotate: 120.0,
+            ..options()
+        };
+        let palette = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        palettize_image(&mut image, &palette, &options).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn no_saturation_is_gray() {
+        let options = Options {
+            saturation: 0.0,
+            ..options()
+        };
+        for color in [[255, 0, 0], [200, 100, 50], [10, 240, 130]] {
+            let [r, g, b] = adjust(color, &options);
+            assert!(r == g && g == b, "{:?} became {:?}", color, [r, g, b]);
+        }
+    }
+
+    #[test]
+    fn remapping_leaves_other_colors_alone() {
+        let mut image = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 1, |x, _| {
+            [
+                Rgba([255, 0, 0, 255]),
+                Rgba([254, 0, 0, 255]),
+                Rgba([10, 20, 30, 128]),
+            ][x as usize]
+        }));
+        let color_map = HashMap::from([([255, 0, 0], [0, 0, 255])]);
+        remap_image(&mut image, &color_map);
+        assert_eq!(image.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(image.get_pixel(1, 0), Rgba([254, 0, 0, 255]));
+        assert_eq!(image.get_pixel(2, 0), Rgba([10, 20, 30, 128]));
+    }
+
+    #[test]
+    fn grid_lines_go_between_tiles() {
+        let mut image = DynamicImage::new_rgba8(4, 4);
+        draw_grid(&mut image, 2, 2, [255, 0, 0]);
+        assert_eq!(
+            pixels_of(&image, Rgba([255, 0, 0, 255])),
+            vec![(2, 0), (2, 1), (0, 2), (1, 2), (2, 2), (3, 2), (2, 3)]
+        );
+    }
+}
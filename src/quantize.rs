@@ -0,0 +1,400 @@
+//! Fitting palettes to images.
+
+use crate::{
+    color::{self, Illuminant},
+    process::nearest_color,
+};
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use std::collections::HashMap;
+
+/// Images are shrunk to at most this size on a side before optimizing, which
+/// otherwise looks at every pixel many times over.
+const OPTIMIZE_SIZE: u32 = 128;
+
+/// K-means refines the colors median cut picks, on a shrunken image.
+const KMEANS_ITERATIONS: usize = 10;
+
+/// How many of the image's most common colors get tried as replacements.
+const OPTIMIZE_CANDIDATES: usize = 64;
+
+const OPTIMIZE_PASSES: usize = 4;
+
+/// Each distinct color in the image and how many pixels have it.
+pub fn histogram(image: &DynamicImage) -> HashMap<[u8; 3], u64> {
+    let mut histogram = HashMap::new();
+    for (_, _, color) in image.pixels() {
+        *histogram
+            .entry([color.0[0], color.0[1], color.0[2]])
+            .or_default() += 1;
+    }
+    histogram
+}
+
+/// How many pixels palettize to each palette color, in the palette's order,
+/// and how many of all of them are less than `max_error` away from their
+/// palette color by L1 distance.
+pub fn palette_usage(
+    histogram: &HashMap<[u8; 3], u64>,
+    palette: &[[u8; 3]],
+    max_error: u64,
+) -> (Vec<u64>, u64) {
+    let mut uses = vec![0; palette.len()];
+    let mut covered = 0;
+    for (&color, &count) in histogram.iter() {
+        let (nearest, error) = nearest_color(palette, color);
+        if let Some(index) = palette.iter().position(|&c| c == nearest) {
+            uses[index] += count;
+        }
+        if error < max_error {
+            covered += count;
+        }
+    }
+    (uses, covered)
+}
+
+/// Merge the two colors closest in Lab until only `target` are left. Merged
+/// colors are the average of the two, weighted by `weights` if there are any,
+/// which go in the palette's order and add up as colors are merged.
+pub fn compress_palette(
+    colors: &[[u8; 3]],
+    weights: Option<&[u64]>,
+    target: usize,
+) -> Vec<[u8; 3]> {
+    let mut entries = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| {
+            let weight = weights.map_or(1, |weights| weights[i]);
+            (color, color::srgb_to_lab(color, Illuminant::D65), weight)
+        })
+        .collect::<Vec<_>>();
+
+    while entries.len() > target.max(1) {
+        let mut closest = (0, 1);
+        let mut min_diff = f32::INFINITY;
+        for i in 0..entries.len() {
+            for j in i + 1..entries.len() {
+                let diff = color::cie76(entries[i].1, entries[j].1);
+                if diff < min_diff {
+                    min_diff = diff;
+                    closest = (i, j);
+                }
+            }
+        }
+
+        let (i, j) = closest;
+        let (b, _, weight_b) = entries.swap_remove(j);
+        let (a, _, weight_a) = entries[i];
+        // colors nothing palettizes to still count for something, or they'd
+        // never move
+        let (wa, wb) = if weight_a + weight_b == 0 {
+            (1.0, 1.0)
+        } else {
+            (weight_a as f32, weight_b as f32)
+        };
+        let merged =
+            [0, 1, 2].map(|c| ((a[c] as f32 * wa + b[c] as f32 * wb) / (wa + wb)).round() as u8);
+        entries[i] = (
+            merged,
+            color::srgb_to_lab(merged, Illuminant::D65),
+            weight_a + weight_b,
+        );
+    }
+
+    entries.into_iter().map(|(color, _, _)| color).collect()
+}
+
+/// Mean squared error between the image's colors and what they palettize to.
+pub fn mean_squared_error(histogram: &HashMap<[u8; 3], u64>, palette: &[[u8; 3]]) -> f64 {
+    let mut total_error = 0.0;
+    let mut total_pixels = 0;
+    for (&color, &count) in histogram.iter() {
+        let (nearest, _) = nearest_color(palette, color);
+        let error = (0..3)
+            .map(|c| (color[c] as f64 - nearest[c] as f64).powi(2))
+            .sum::<f64>();
+        total_error += error * count as f64;
+        total_pixels += count;
+    }
+
+    if total_pixels == 0 {
+        0.0
+    } else {
+        total_error / total_pixels as f64
+    }
+}
+
+/// The image to actually optimize against, see [`OPTIMIZE_SIZE`].
+pub fn optimization_sample(image: &DynamicImage) -> DynamicImage {
+    if image.width() > OPTIMIZE_SIZE || image.height() > OPTIMIZE_SIZE {
+        image.resize(OPTIMIZE_SIZE, OPTIMIZE_SIZE, FilterType::Nearest)
+    } else {
+        image.clone()
+    }
+}
+
+fn l1(a: [u8; 3], b: [u8; 3]) -> u64 {
+    (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum()
+}
+
+fn squared_error(a: [u8; 3], b: [u8; 3]) -> f64 {
+    (0..3).map(|c| (a[c] as f64 - b[c] as f64).powi(2)).sum()
+}
+
+/// The L1 distance and index of the nearest and second nearest palette
+/// colors, earlier entries winning ties the same as [`nearest_color`]. With
+/// one color, the second is `(u64::MAX, usize::MAX)`.
+fn nearest_two(palette: &[[u8; 3]], color: [u8; 3]) -> [(u64, usize); 2] {
+    let mut nearest = [(u64::MAX, usize::MAX); 2];
+    for (i, &palette_color) in palette.iter().enumerate() {
+        let entry = (l1(color, palette_color), i);
+        if entry < nearest[0] {
+            nearest = [entry, nearest[0]];
+        } else if entry < nearest[1] {
+            nearest[1] = entry;
+        }
+    }
+    nearest
+}
+
+/// Greedy hill climbing: keep swapping palette entries for one of the image's
+/// common colors as long as that lowers the mean squared error.
+///
+/// Measuring a swap from scratch would look at the whole palette for every
+/// color in the image. Instead, each color remembers its two nearest palette
+/// entries, so a swap only has to compare the replacement against whichever
+/// of them isn't being replaced. They're only worked out again when a swap
+/// is kept.
+pub fn optimize_palette(image: &DynamicImage, palette: Vec<[u8; 3]>) -> Vec<[u8; 3]> {
+    let histogram = histogram(&optimization_sample(image));
+
+    let mut candidates = histogram.iter().collect::<Vec<_>>();
+    candidates.sort_by(|(color_a, a), (color_b, b)| b.cmp(a).then(color_a.cmp(color_b)));
+    candidates.truncate(OPTIMIZE_CANDIDATES);
+
+    let entries = histogram
+        .iter()
+        .map(|(&color, &count)| (color, count))
+        .collect::<Vec<_>>();
+    let pixels = entries.iter().map(|&(_, count)| count).sum::<u64>().max(1);
+
+    let mut palette = palette;
+    let mut nearest = entries
+        .iter()
+        .map(|&(color, _)| nearest_two(&palette, color))
+        .collect::<Vec<_>>();
+    let mut error = entries
+        .iter()
+        .zip(nearest.iter())
+        .map(|(&(color, count), [(_, i), _])| squared_error(color, palette[*i]) * count as f64)
+        .sum::<f64>();
+
+    for pass in 0..OPTIMIZE_PASSES {
+        let mut improved = false;
+
+        for i in 0..palette.len() {
+            for &(&candidate, _) in candidates.iter() {
+                if palette.contains(&candidate) {
+                    continue;
+                }
+
+                let mut new_error = 0.0;
+                for (&(color, count), &[first, second]) in entries.iter().zip(nearest.iter()) {
+                    let kept = if first.1 == i { second } else { first };
+                    let (_, index) = kept.min((l1(color, candidate), i));
+                    let replacement = if index == i {
+                        candidate
+                    } else {
+                        palette[index]
+                    };
+                    new_error += squared_error(color, replacement) * count as f64;
+                    if new_error >= error {
+                        break;
+                    }
+                }
+
+                if new_error < error {
+                    palette[i] = candidate;
+                    error = new_error;
+                    improved = true;
+                    for (nearest, &(color, _)) in nearest.iter_mut().zip(entries.iter()) {
+                        *nearest = nearest_two(&palette, color);
+                    }
+                }
+            }
+        }
+
+        tracing::debug!("optimize pass {}: mse {}", pass, error / pixels as f64);
+        if !improved {
+            break;
+        }
+    }
+
+    palette
+}
+
+/// A box of colors, for median cut.
+struct ColorBox {
+    colors: Vec<([u8; 3], u64)>,
+}
+
+impl ColorBox {
+    /// The channel with the widest range of values, and how wide it is.
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let min = self.colors.iter().map(|(color, _)| color[c]).min();
+                let max = self.colors.iter().map(|(color, _)| color[c]).max();
+                (c, max.unwrap_or(0) - min.unwrap_or(0))
+            })
+            .max_by_key(|&(_, range)| range)
+            .expect("there are three channels")
+    }
+
+    /// Split at the weighted median of the widest channel.
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        self.colors.sort_by_key(|(color, _)| color[channel]);
+
+        let total = self.colors.iter().map(|(_, count)| count).sum::<u64>();
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, count)) in self.colors.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                at = i + 1;
+                break;
+            }
+        }
+        // both halves need at least one color
+        let at = at.clamp(1, self.colors.len() - 1);
+
+        let rest = self.colors.split_off(at);
+        (self, ColorBox { colors: rest })
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let total = self
+            .colors
+            .iter()
+            .map(|(_, count)| count)
+            .sum::<u64>()
+            .max(1);
+        let channel = |c: usize| {
+            let sum = self
+                .colors
+                .iter()
+                .map(|(color, count)| color[c] as u64 * count)
+                .sum::<u64>();
+            ((sum + total / 2) / total) as u8
+        };
+        [channel(0), channel(1), channel(2)]
+    }
+}
+
+/// Pick up to `n` colors by repeatedly splitting the box of colors with the
+/// widest range in half.
+pub fn median_cut(histogram: &HashMap<[u8; 3], u64>, n: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![ColorBox {
+        colors: histogram
+            .iter()
+            .map(|(&color, &count)| (color, count))
+            .collect(),
+    }];
+
+    while boxes.len() < n {
+        let Some((widest, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.colors.len() > 1)
+            .max_by_key(|(_, color_box)| color_box.widest_channel().1)
+        else {
+            // every box is down to one color
+            break;
+        };
+
+        let (a, b) = boxes.swap_remove(widest).split();
+        boxes.push(a);
+        boxes.push(b);
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+/// Start from median cut's colors, then move each to the mean of the colors
+/// nearest to it a few times over.
+pub fn kmeans(image: &DynamicImage, n: usize) -> Vec<[u8; 3]> {
+    let histogram = histogram(&optimization_sample(image));
+    let mut centers = median_cut(&histogram, n);
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![([0u64; 3], 0u64); centers.len()];
+        for (&color, &count) in histogram.iter() {
+            let nearest = (0..centers.len())
+                .min_by_key(|&i| {
+                    (0..3)
+                        .map(|c| (color[c] as i64 - centers[i][c] as i64).pow(2))
+                        .sum::<i64>()
+                })
+                .expect("median cut gives at least one color");
+            for c in 0..3 {
+                sums[nearest].0[c] += color[c] as u64 * count;
+            }
+            sums[nearest].1 += count;
+        }
+
+        let mut moved = false;
+        for (center, (sum, count)) in centers.iter_mut().zip(sums) {
+            // nothing was nearest to this one, so leave it be
+            if count == 0 {
+                continue;
+            }
+            let new_center = sum.map(|s| ((s + count / 2) / count) as u8);
+            moved |= new_center != *center;
+            *center = new_center;
+        }
+
+        if !moved {
+            break;
+        }
+    }
+
+    centers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn optimizing_swaps_in_the_image_colors() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(4, 1, |x, _| {
+            if x < 2 {
+                Rgb([10, 10, 10])
+            } else {
+                Rgb([200, 50, 50])
+            }
+        }));
+        let optimized = optimize_palette(&image, vec![[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(optimized, vec![[10, 10, 10], [200, 50, 50]]);
+    }
+
+    #[test]
+    fn optimizing_never_makes_the_error_worse() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])
+        }));
+        let palette = vec![
+            [0, 0, 0],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 255],
+        ];
+        let histogram = histogram(&image);
+        let before = mean_squared_error(&histogram, &palette);
+        let after = mean_squared_error(&histogram, &optimize_palette(&image, palette));
+        assert!(after < before, "{} isn't less than {}", after, before);
+    }
+}
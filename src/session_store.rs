@@ -0,0 +1,207 @@
+//! Sessions kept in memory, up to a total size, so that stored images can't
+//! use up all of it.
+
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tower_sessions::{
+    cookie::time::OffsetDateTime,
+    session::{Id, Record},
+    session_store::{self, ExpiredDeletion, SessionStore},
+};
+
+/// Like tower-sessions' `MemoryStore`, except once the sessions add up to
+/// `max_bytes`, the ones that have gone unused the longest are forgotten to
+/// make room. Expired sessions are only forgotten by
+/// [`ExpiredDeletion::delete_expired`], or when they're next loaded.
+#[derive(Clone, Debug)]
+pub struct BoundedMemoryStore {
+    sessions: Arc<Mutex<Sessions>>,
+    max_bytes: usize,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    /// Each record with the size of its data as JSON.
+    records: HashMap<Id, (Record, usize)>,
+    bytes: usize,
+}
+
+impl Sessions {
+    fn insert(&mut self, record: Record, size: usize) {
+        self.remove(&record.id);
+        self.bytes += size;
+        self.records.insert(record.id, (record, size));
+    }
+
+    fn remove(&mut self, id: &Id) {
+        if let Some((_, size)) = self.records.remove(id) {
+            self.bytes -= size;
+        }
+    }
+
+    /// The session that expires soonest. Sessions expire after they go
+    /// unused for a while, so it's also the one unused the longest.
+    fn oldest(&self) -> Option<Id> {
+        self.records
+            .iter()
+            .min_by_key(|(_, (record, _))| record.expiry_date)
+            .map(|(&id, _)| id)
+    }
+}
+
+impl BoundedMemoryStore {
+    pub fn new(max_bytes: usize) -> BoundedMemoryStore {
+        BoundedMemoryStore {
+            sessions: Arc::new(Mutex::new(Sessions::default())),
+            max_bytes,
+        }
+    }
+
+    /// How many bytes of session data are stored.
+    pub fn bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.sessions.lock().expect("session store lock poisoned")
+    }
+}
+
+fn record_size(record: &Record) -> session_store::Result<usize> {
+    serde_json::to_vec(&record.data)
+        .map(|data| data.len())
+        .map_err(|err| session_store::Error::Encode(err.to_string()))
+}
+
+#[async_trait]
+impl SessionStore for BoundedMemoryStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let size = record_size(record)?;
+        let mut sessions = self.lock();
+        while sessions.records.contains_key(&record.id) {
+            record.id = Id::default();
+        }
+        make_room(&mut sessions, size, self.max_bytes);
+        sessions.insert(record.clone(), size);
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let size = record_size(record)?;
+        let mut sessions = self.lock();
+        sessions.remove(&record.id);
+        make_room(&mut sessions, size, self.max_bytes);
+        sessions.insert(record.clone(), size);
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let mut sessions = self.lock();
+        let Some((record, _)) = sessions.records.get(id) else {
+            return Ok(None);
+        };
+        if record.expiry_date <= OffsetDateTime::now_utc() {
+            sessions.remove(id);
+            return Ok(None);
+        }
+        Ok(Some(record.clone()))
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        self.lock().remove(id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for BoundedMemoryStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut sessions = self.lock();
+        let expired = sessions
+            .records
+            .iter()
+            .filter(|(_, (record, _))| record.expiry_date <= now)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in expired {
+            sessions.remove(&id);
+        }
+        Ok(())
+    }
+}
+
+/// Forget the oldest sessions until `size` more bytes fit. A session bigger
+/// than `max_bytes` on its own is still kept, just with nothing else.
+fn make_room(sessions: &mut Sessions, size: usize, max_bytes: usize) {
+    while sessions.bytes + size > max_bytes {
+        let Some(oldest) = sessions.oldest() else {
+            break;
+        };
+        sessions.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_sessions::cookie::time::Duration;
+
+    /// A session holding `bytes` worth of string, expiring in `minutes`.
+    fn record(bytes: usize, minutes: i64) -> Record {
+        Record {
+            id: Id::default(),
+            data: HashMap::from([(
+                String::from("image"),
+                serde_json::Value::String("x".repeat(bytes)),
+            )]),
+            expiry_date: OffsetDateTime::now_utc() + Duration::minutes(minutes),
+        }
+    }
+
+    #[tokio::test]
+    async fn oldest_sessions_make_room() {
+        // each record is its string plus `{"image":""}`
+        let store = BoundedMemoryStore::new(300);
+        let old = record(88, 1);
+        let newer = record(88, 2);
+        let newest = record(88, 3);
+        for record in [&old, &newer, &newest] {
+            store.save(record).await.unwrap();
+        }
+        assert_eq!(store.bytes(), 300);
+
+        let extra = record(88, 4);
+        store.save(&extra).await.unwrap();
+        assert!(store.load(&old.id).await.unwrap().is_none());
+        for record in [&newer, &newest, &extra] {
+            assert!(store.load(&record.id).await.unwrap().is_some());
+        }
+        assert_eq!(store.bytes(), 300);
+    }
+
+    #[tokio::test]
+    async fn saving_again_replaces_the_size() {
+        let store = BoundedMemoryStore::new(1000);
+        let mut session = record(88, 1);
+        store.save(&session).await.unwrap();
+        session.data.clear();
+        store.save(&session).await.unwrap();
+        assert_eq!(store.bytes(), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_sessions_are_deleted() {
+        let store = BoundedMemoryStore::new(1000);
+        let expired = record(10, -1);
+        let current = record(10, 1);
+        store.save(&expired).await.unwrap();
+        store.save(&current).await.unwrap();
+        store.delete_expired().await.unwrap();
+        assert_eq!(store.bytes(), 22);
+        assert!(store.load(&current.id).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,57 @@
+//! Sending a palettized PNG while it's still being made.
+
+use crate::process::{palettize_row, Options};
+use bytes::Bytes;
+use image::{DynamicImage, GenericImageView};
+use std::io::Write;
+use tokio::sync::mpsc::Sender;
+
+/// Sends whatever's written to it as a chunk of a response body.
+pub struct ChannelWriter(pub Sender<std::io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Palettize the image a row at a time, writing out the PNG as it goes. The
+/// signature and header go out before any rows are done. If a row takes too
+/// long, the body ends with an error instead of the rest of the PNG, since
+/// the status has already been sent.
+pub fn stream_png(
+    image: &DynamicImage,
+    colors: &[[u8; 3]],
+    options: &Options,
+    writer: ChannelWriter,
+) -> anyhow::Result<()> {
+    let errors = writer.0.clone();
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut stream = encoder.write_header()?.into_stream_writer()?;
+    for y in 0..image.height() {
+        let row = match palettize_row(image, y, colors, options) {
+            Ok(row) => row,
+            Err(timeout) => {
+                let _ = errors.blocking_send(Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    timeout.to_string(),
+                )));
+                return Err(timeout.into());
+            }
+        };
+        stream.write_all(&row)?;
+    }
+    stream.finish()?;
+
+    Ok(())
+}
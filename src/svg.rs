@@ -0,0 +1,94 @@
+//! Pixel art as SVG rectangles.
+
+use image::{DynamicImage, GenericImageView};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// A horizontal run of same-colored pixels.
+struct Run {
+    x: u32,
+    y: u32,
+    len: u32,
+}
+
+fn hex([r, g, b, _]: [u8; 4]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn opacity([_, _, _, a]: [u8; 4]) -> String {
+    if a == 255 {
+        String::new()
+    } else {
+        format!(" fill-opacity=\"{:.3}\"", a as f32 / 255.0)
+    }
+}
+
+/// Each row is split into runs of the same color, which become one `<rect>`
+/// each. Fully transparent pixels are left out. Every pixel is
+/// `pixel_size` units on a side, and with `group_by_color` the rects of each
+/// color are wrapped in a `<g>` with its fill. The image's width and height
+/// times `pixel_size` have to fit in a `u32`.
+pub fn image_to_svg(image: &DynamicImage, pixel_size: u32, group_by_color: bool) -> String {
+    let mut runs = BTreeMap::<[u8; 4], Vec<Run>>::new();
+    let mut in_order = Vec::new();
+
+    for y in 0..image.height() {
+        let mut x = 0;
+        while x < image.width() {
+            let color = image.get_pixel(x, y).0;
+            let mut len = 1;
+            while x + len < image.width() && image.get_pixel(x + len, y).0 == color {
+                len += 1;
+            }
+
+            if color[3] != 0 {
+                if group_by_color {
+                    runs.entry(color).or_default().push(Run { x, y, len });
+                } else {
+                    in_order.push((color, Run { x, y, len }));
+                }
+            }
+
+            x += len;
+        }
+    }
+
+    let (width, height) = (image.width() * pixel_size, image.height() * pixel_size);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" shape-rendering=\"crispEdges\">\n"
+    );
+
+    let rect = |svg: &mut String, run: &Run, fill: &str| {
+        // writing to a String can't fail
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}/>",
+            run.x * pixel_size,
+            run.y * pixel_size,
+            run.len * pixel_size,
+            pixel_size,
+            fill,
+        );
+    };
+
+    if group_by_color {
+        for (color, runs) in runs {
+            let _ = writeln!(svg, "<g fill=\"{}\"{}>", hex(color), opacity(color));
+            for run in runs.iter() {
+                rect(&mut svg, run, "");
+            }
+            svg.push_str("</g>\n");
+        }
+    } else {
+        for (color, run) in in_order {
+            rect(
+                &mut svg,
+                &run,
+                &format!(" fill=\"{}\"{}", hex(color), opacity(color)),
+            );
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
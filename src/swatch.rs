@@ -0,0 +1,65 @@
+//! Palettes as HTML pages of color swatches, for looking over by eye.
+
+use crate::{color, palette::hex_color};
+use std::fmt::Write;
+
+const SWATCH_SIZE: u32 = 60;
+
+/// One `<div>` per color with a square of it and its hex, and the nearest CSS
+/// color name if `names` is set. Everything is styled inline, so the page
+/// stands on its own.
+pub fn swatch_page(colors: &[[u8; 3]], names: bool) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>palette of {} colors</title>\n</head>\n\
+         <body style=\"display: flex; flex-wrap: wrap; gap: 12px; margin: 16px; \
+         font-family: monospace;\">\n",
+        colors.len()
+    );
+
+    for &color in colors {
+        let hex = hex_color(color);
+        let _ = write!(
+            html,
+            "<div style=\"width: {size}px; text-align: center;\">\
+             <span style=\"display: block; width: {size}px; height: {size}px; \
+             background: {hex}; border: 1px solid #888;\"></span>{hex}",
+            size = SWATCH_SIZE,
+            hex = hex,
+        );
+        if names {
+            let _ = write!(
+                html,
+                "<br><small>{}</small>",
+                color::nearest_css_name(color)
+            );
+        }
+        html.push_str("</div>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_div_per_color() {
+        let colors = [[0xFF, 0x00, 0x00], [0x12, 0x34, 0x56], [0xFF, 0xFF, 0xFF]];
+        let html = swatch_page(&colors, false);
+        assert_eq!(html.matches("<div").count(), 3);
+        assert_eq!(html.matches("</div>").count(), 3);
+        for (div, hex) in html
+            .split("<div")
+            .skip(1)
+            .zip(["#FF0000", "#123456", "#FFFFFF"])
+        {
+            assert!(div.contains(&format!("background: {};", hex)), "{}", div);
+            assert!(div.contains(&format!("</span>{}</div>", hex)), "{}", div);
+        }
+    }
+}